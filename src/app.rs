@@ -1,9 +1,10 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::ptr::copy_nonoverlapping as memcpy;
-use std::u64;
 
 use anyhow::{anyhow, Result};
-use cgmath::{point3, vec3, Deg};
-use std::time::Instant;
+use log::*;
+use cgmath::{vec3, vec4, Deg, EuclideanSpace, SquareMatrix};
 use vk::{KhrSurfaceExtension, KhrSwapchainExtension};
 use vulkanalia::loader::{LibloadingLoader, LIBRARY};
 use vulkanalia::prelude::v1_3::*;
@@ -13,27 +14,52 @@ use vulkanalia::window as vk_window;
 use vulkanalia::Version;
 use winit::window::Window;
 
-use crate::vulkan::buffers::depth_buffer::create_depth_objects;
-use crate::vulkan::buffers::index_buffer::create_index_buffer;
+use crate::camera::Camera;
+use crate::clock::{Clock, WallClock};
+use crate::config::{RendererConfig, ShadowSettings};
+use crate::recording::Recorder;
+use crate::vulkan::allocator::Allocator;
+use crate::vulkan::buffers::instance_buffer::{
+    create_instance_buffers, update_instance_buffer, MAX_INSTANCE_TEXTURES,
+};
 use crate::vulkan::buffers::uniform_buffer::{
     create_descriptor_pool, create_descriptor_set_layout, create_descriptor_sets,
-    create_uniform_buffers, Mat4, UniformBufferObject,
+    create_uniform_buffers, DirectionalLight, LightUniformBufferObject, Mat4, PointLight,
+    UniformBufferObject, MAX_POINT_LIGHTS,
 };
-use crate::vulkan::buffers::vertex_buffer::create_vertex_buffer;
-use crate::vulkan::commands::{create_command_buffers, create_command_pool};
+use crate::vulkan::commands::{create_command_buffers, create_command_pools, record_command_buffer};
+use crate::vulkan::debug::DebugUtils;
 use crate::vulkan::device::create_logical_device;
-use crate::vulkan::framebuffer::create_framebuffers;
-use crate::vulkan::image::{
-    create_color_objects, create_texture_image, create_texture_image_view, create_texture_sampler,
-};
+use crate::vulkan::frame::FrameContext;
+use crate::vulkan::image::{create_texture_sampler, Texture, TextureData};
 use crate::vulkan::instance::create_instance;
-use crate::vulkan::model::load_model;
+use crate::vulkan::material::{
+    create_default_textures, create_material_buffers, create_material_descriptor_pool,
+    create_material_descriptor_set_layout, create_material_descriptor_sets,
+};
+use crate::vulkan::model::Material;
+use crate::vulkan::offscreen::{create_offscreen_target, save_offscreen_image};
 use crate::vulkan::physical_device::pick_physical_device;
-use crate::vulkan::pipeline::create_pipeline;
-use crate::vulkan::render_pass::create_render_pass;
+use crate::vulkan::pipeline::{
+    create_pipeline, create_pipeline_layout, create_shadow_pipeline, create_shadow_pipeline_layout,
+};
+use crate::vulkan::passes::create_render_graph;
+use crate::vulkan::pipeline_cache::{create_pipeline_cache, save_pipeline_cache};
+use crate::vulkan::queries::{
+    create_draw_query_pools, create_timestamp_query_pools, plan_draws, read_draw_statistics,
+    read_occlusion, read_pass_timings, DrawStatistics, PassTiming,
+};
+use crate::vulkan::render_graph::RenderGraph;
+use crate::vulkan::resources::{Buffer, DeletionQueue, Image, ImageView, Pipeline, Sampler};
+use crate::vulkan::scene::{load_mesh, ObjectId, Scene};
+use crate::vulkan::screenshot::{create_screenshot, read_screenshot, write_png};
+use crate::vulkan::shader::{Shader, ShaderLibrary};
+use crate::vulkan::shadow::{
+    create_shadow_map, create_shadow_sampler, light_space_matrix,
+};
 use crate::vulkan::swapchain::{create_swapchain, create_swapchain_image_views};
 use crate::vulkan::synchronization::create_sync_objects;
-use crate::vulkan::vertex::Vertex;
+use crate::vulkan::vertex::Vec4;
 
 pub const MAX_FRAMES_IN_FLIGHT: usize = 3;
 pub const VALIDATION_ENABLED: bool = cfg!(debug_assertions);
@@ -42,59 +68,146 @@ pub const VALIDATION_LAYER: vk::ExtensionName =
 pub const PORTABILITY_MACOS_VERSION: Version = Version::new(1, 3, 216);
 
 /// The Vulkan App
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct App {
     pub entry: Entry,
     pub instance: Instance,
     pub data: AppData,
     pub device: Device,
+    pub config: RendererConfig,
     pub frame: usize,
     pub resized: bool,
-    pub start: Instant,
+    /// The time of the frames, which the camera moves by.
+    pub clock: Box<dyn Clock>,
+    pub camera: Camera,
+    pub directional_light: DirectionalLight,
+    /// Only the first `MAX_POINT_LIGHTS` lights are used.
+    pub point_lights: Vec<PointLight>,
+    /// Where the next frame saves its screenshot, see `request_screenshot`.
+    pub screenshot: Option<PathBuf>,
+    /// Set when every frame rendered headless is recorded.
+    pub recorder: Option<Recorder>,
+    /// The GPU time of the passes of the last frame whose timestamps were read.
+    pub pass_timings: Vec<PassTiming>,
+    /// The pipeline statistics of the draws of the last frame whose queries were read.
+    pub draw_statistics: Vec<DrawStatistics>,
+    /// The objects occlusion culling found hidden, with the number of frames since.
+    pub hidden_objects: HashMap<ObjectId, u32>,
 }
 
 impl App {
     /// Creates our Vulkan app.
-    pub unsafe fn create(window: &Window) -> Result<Self> {
+    pub unsafe fn create(window: &Window, config: RendererConfig) -> Result<Self> {
         let loader = LibloadingLoader::new(LIBRARY)?;
         let entry = Entry::new(loader).map_err(|b| anyhow!("{}", b))?;
         let mut data = AppData::default();
-        let instance = create_instance(window, &entry, &mut data)?;
+        let instance = create_instance(Some(window), &entry, &mut data)?;
         data.surface = vk_window::create_surface(&instance, &window, &window)?;
-        pick_physical_device(&instance, &mut data)?;
+        pick_physical_device(&instance, &mut data, &config)?;
         let device = create_logical_device(&entry, &instance, &mut data)?;
-        create_swapchain(window, &instance, &device, &mut data)?;
+        create_swapchain(window, &instance, &device, &mut data, config.present_mode)?;
         create_swapchain_image_views(&device, &mut data)?;
-        create_render_pass(&instance, &device, &mut data)?;
+        Self::create_resources(entry, instance, device, data, config, Box::new(WallClock::new()))
+    }
+
+    /// Creates our Vulkan app without a window. Frames are rendered into an
+    /// offscreen image of the configured size instead of a swapchain image,
+    /// which can then be saved with `save_frame`.
+    ///
+    /// The frames take their time from the given clock, usually a `FixedClock` so that
+    /// they don't depend on how fast they are rendered.
+    pub unsafe fn create_headless(config: RendererConfig, clock: Box<dyn Clock>) -> Result<Self> {
+        let loader = LibloadingLoader::new(LIBRARY)?;
+        let entry = Entry::new(loader).map_err(|b| anyhow!("{}", b))?;
+        let mut data = AppData {
+            headless: true,
+            ..Default::default()
+        };
+        let instance = create_instance(None, &entry, &mut data)?;
+        pick_physical_device(&instance, &mut data, &config)?;
+        let device = create_logical_device(&entry, &instance, &mut data)?;
+        create_offscreen_target(&instance, &device, &mut data, config.width, config.height)?;
+        Self::create_resources(entry, instance, device, data, config, clock)
+    }
+
+    /// Creates everything that doesn't depend on whether we render to a window or not.
+    unsafe fn create_resources(
+        entry: Entry,
+        instance: Instance,
+        device: Device,
+        mut data: AppData,
+        config: RendererConfig,
+        clock: Box<dyn Clock>,
+    ) -> Result<Self> {
+        data.frames.resize_with(MAX_FRAMES_IN_FLIGHT, FrameContext::default);
+        // Only a window keeps running long enough for the shaders to be edited. The layouts
+        // are reflected from the shaders, so they are loaded first.
+        data.shaders = ShaderLibrary::load(
+            config.shaders.as_deref(),
+            !data.headless,
+            data.non_uniform_indexing,
+        )?;
         create_descriptor_set_layout(&device, &mut data)?;
+        create_material_descriptor_set_layout(&device, &mut data)?;
+        create_pipeline_cache(&instance, &device, &mut data, &config.pipeline_cache)?;
+        create_pipeline_layout(&device, &mut data)?;
+        create_shadow_pipeline_layout(&device, &mut data)?;
+        create_shadow_map(&instance, &device, &mut data, config.shadows.map_size)?;
+        // The pipelines are created for the passes of the render graph.
+        create_render_graph(&instance, &device, &mut data)?;
         create_pipeline(&device, &mut data)?;
-        create_command_pool(&instance, &device, &mut data)?;
-        create_color_objects(&instance, &device, &mut data)?;
-        create_depth_objects(&instance, &device, &mut data)?;
-        create_framebuffers(&device, &mut data)?;
-        create_texture_image(&instance, &device, &mut data)?;
-        create_texture_image_view(&device, &mut data)?;
+        create_shadow_pipeline(&device, &mut data, &config.shadows)?;
+        create_command_pools(&instance, &device, &mut data)?;
+        create_timestamp_query_pools(&instance, &device, &mut data)?;
+        create_draw_query_pools(&device, &mut data)?;
+        create_default_textures(&instance, &device, &mut data)?;
+        load_models(&instance, &device, &mut data, &config.models, &config.texture)?;
         create_texture_sampler(&device, &mut data)?;
-        load_model(&mut data)?;
-        create_vertex_buffer(&instance, &device, &mut data)?;
-        create_index_buffer(&instance, &device, &mut data)?;
+        create_shadow_sampler(&device, &mut data)?;
         create_uniform_buffers(&instance, &device, &mut data)?;
+        let objects = data.scene.objects.len();
+        create_instance_buffers(&instance, &device, &mut data, objects)?;
         create_descriptor_pool(&device, &mut data)?;
         create_descriptor_sets(&device, &mut data)?;
+        create_material_buffers(&instance, &device, &mut data)?;
+        create_material_descriptor_pool(&device, &mut data)?;
+        create_material_descriptor_sets(&device, &mut data)?;
         create_command_buffers(&device, &mut data)?;
         create_sync_objects(&device, &mut data)?;
 
+        let mut camera = Camera::new(Deg(config.fov), config.near, config.far);
+        camera.frame(data.scene.bounds());
+        camera.turntable = Deg(config.turntable).into();
+
         Ok(Self {
             entry,
             instance,
             data,
             device,
+            config,
             frame: 0,
             resized: false,
-            start: Instant::now(),
+            clock,
+            camera,
+            // A warm light shining down at the model from the front.
+            directional_light: DirectionalLight {
+                direction: vec4(-0.3, -0.5, -1.0, 0.0),
+                color: vec4(1.0, 0.95, 0.85, 3.0),
+            },
+            point_lights: vec![PointLight {
+                position: vec4(1.0, 1.0, 1.5, 5.0),
+                color: vec4(1.0, 0.7, 0.4, 4.0),
+            }],
+            screenshot: None,
+            recorder: None,
+            pass_timings: vec![],
+            draw_statistics: vec![],
+            hidden_objects: HashMap::new(),
         })
     }
 
+    /// Recreates the swapchain and everything that depends on it, e.g. after the window
+    /// was resized.
     pub unsafe fn recreate_swapchain(&mut self, window: &Window) -> Result<()> {
         dbg!("Recreating swapchain");
 
@@ -102,133 +215,434 @@ impl App {
         self.device.device_wait_idle()?;
         self.destroy_swapchain();
 
-        create_swapchain(window, &self.instance, &self.device, &mut self.data)?;
+        create_swapchain(
+            window,
+            &self.instance,
+            &self.device,
+            &mut self.data,
+            self.config.present_mode,
+        )?;
         create_swapchain_image_views(&self.device, &mut self.data)?;
-        create_render_pass(&self.instance, &self.device, &mut self.data)?;
+        create_render_graph(&self.instance, &self.device, &mut self.data)?;
+        create_pipeline(&self.device, &mut self.data)?;
+
+        // The resources of the frames in flight don't depend on the swapchain, so they
+        // are kept as they are.
+        Ok(())
+    }
+
+    /// Changes the shadow settings, rebuilding the shadow map and the shadow pipeline.
+    ///
+    /// The render graph and the descriptor sets refer to the old shadow map, so they are
+    /// rebuilt as well, and the main pipeline along with the graph's render passes.
+    pub unsafe fn set_shadow_settings(&mut self, settings: ShadowSettings) -> Result<()> {
+        if settings == self.config.shadows {
+            return Ok(());
+        }
+
+        self.device.device_wait_idle()?;
+        self.data.render_graph.destroy(&self.device);
+        self.config.shadows = settings;
+
+        create_shadow_map(
+            &self.instance,
+            &self.device,
+            &mut self.data,
+            settings.map_size,
+        )?;
+        create_render_graph(&self.instance, &self.device, &mut self.data)?;
         create_pipeline(&self.device, &mut self.data)?;
-        create_color_objects(&self.instance, &self.device, &mut self.data)?;
-        create_depth_objects(&self.instance, &self.device, &mut self.data)?;
-        create_framebuffers(&self.device, &mut self.data)?;
-        create_uniform_buffers(&self.instance, &self.device, &mut self.data)?;
-        create_descriptor_pool(&self.device, &mut self.data)?;
+        create_shadow_pipeline(&self.device, &mut self.data, &settings)?;
+
+        self.device.reset_descriptor_pool(
+            self.data.descriptor_pool,
+            vk::DescriptorPoolResetFlags::empty(),
+        )?;
         create_descriptor_sets(&self.device, &mut self.data)?;
-        create_command_buffers(&self.device, &mut self.data)?;
-        self.data
-            .image_usage_fences
-            .resize(self.data.swapchain_images.len(), vk::Fence::null());
 
-        for item in &self.data.image_available_semaphores {
-            dbg!(item);
+        Ok(())
+    }
+
+    /// Rebuilds the pipelines whose shaders changed on disk. A shader that doesn't compile
+    /// or a pipeline that can't be created is reported, and the previous pipeline is kept.
+    unsafe fn reload_shaders(&mut self) {
+        for shader in self.data.shaders.poll() {
+            let result = match shader {
+                Shader::Main => create_pipeline(&self.device, &mut self.data),
+                Shader::Shadow => create_shadow_pipeline(
+                    &self.device,
+                    &mut self.data,
+                    &self.config.shadows,
+                ),
+            };
+
+            if let Err(e) = result {
+                error!("Failed to rebuild the pipeline of {}: {}", shader.file_name(), e);
+                self.data.shaders.revert(shader);
+            }
         }
-        for item in &self.data.render_finished_semaphores {
-            dbg!(item);
+    }
+
+    /// Loads a model as a new mesh, which can then be drawn by adding objects for it.
+    /// Returns the index of the mesh in `AppData::scene`.
+    pub unsafe fn load_mesh(&mut self, path: &Path) -> Result<usize> {
+        // The material descriptor sets are replaced, they mustn't be in use.
+        self.device.device_wait_idle()?;
+
+        let mesh = load_mesh(
+            &self.instance,
+            &self.device,
+            &mut self.data,
+            path,
+            &self.config.texture,
+        )?;
+
+        // The mesh may have brought new materials, which the pool has no room for.
+        create_material_buffers(&self.instance, &self.device, &mut self.data)?;
+        self.device
+            .destroy_descriptor_pool(self.data.material_descriptor_pool, None);
+        create_material_descriptor_pool(&self.device, &mut self.data)?;
+        create_material_descriptor_sets(&self.device, &mut self.data)?;
+
+        // And new textures for the instances to choose from.
+        self.device.reset_descriptor_pool(
+            self.data.descriptor_pool,
+            vk::DescriptorPoolResetFlags::empty(),
+        )?;
+        create_descriptor_sets(&self.device, &mut self.data)?;
+
+        Ok(mesh)
+    }
+
+    /// Adds an object that draws a loaded mesh with the given model matrix.
+    pub fn add_object(&mut self, mesh: usize, transform: Mat4) -> Result<ObjectId> {
+        if mesh >= self.data.scene.meshes.len() {
+            return Err(anyhow!("There is no mesh {}.", mesh));
+        }
+
+        Ok(self.data.scene.add_object(mesh, transform))
+    }
+
+    /// Removes an object from the scene, returns whether it was there.
+    pub fn remove_object(&mut self, id: ObjectId) -> bool {
+        self.data.scene.remove_object(id).is_some()
+    }
+
+    /// Moves an object, returns whether it is in the scene.
+    pub fn set_object_transform(&mut self, id: ObjectId, transform: Mat4) -> bool {
+        match self.data.scene.object_mut(id) {
+            Some(object) => {
+                object.transform = transform;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Changes the color an object's base color is multiplied with, returns whether
+    /// the object is in the scene.
+    pub fn set_object_tint(&mut self, id: ObjectId, tint: Vec4) -> bool {
+        match self.data.scene.object_mut(id) {
+            Some(object) => {
+                object.tint = tint;
+                true
+            }
+            None => false,
         }
-        for item in &self.data.image_usage_fences {
-            dbg!(item);
+    }
+
+    /// Draws an object with one of the loaded textures instead of the base color
+    /// textures of its materials, or with its materials again when `None`. Fails on
+    /// devices without non-uniform indexing, whose shader can't pick an instance texture.
+    pub fn set_object_texture(&mut self, id: ObjectId, texture: Option<usize>) -> Result<()> {
+        if texture.is_some() && !self.data.non_uniform_indexing {
+            return Err(anyhow!(
+                "Instances can't pick a texture, the device doesn't support non-uniform indexing."
+            ));
         }
-        for item in &self.data.command_completion_fences {
-            dbg!(item);
+
+        let available = self.data.textures.len().min(MAX_INSTANCE_TEXTURES);
+        if let Some(texture) = texture.filter(|t| *t >= available) {
+            return Err(anyhow!(
+                "Texture {} can't be used by instances, only the first {} textures can.",
+                texture,
+                available
+            ));
         }
+
+        let object = self
+            .data
+            .scene
+            .object_mut(id)
+            .ok_or_else(|| anyhow!("There is no object {:?}.", id))?;
+        object.texture = texture;
+
+        Ok(())
+    }
+
+    /// Grows the instance buffers when they have no room for every object of the scene.
+    /// The old buffers may still be read by frames in flight, dropping them leaves them
+    /// to the deletion queue.
+    unsafe fn reserve_instances(&mut self) -> Result<()> {
+        let objects = self.data.scene.objects.len();
+        if objects > self.data.instance_capacity {
+            create_instance_buffers(&self.instance, &self.device, &mut self.data, objects)?;
+        }
+
         Ok(())
     }
 
     /// Destroys our Vulkan app.
+    ///
+    /// The buffers, images, image views, samplers and pipelines destroy themselves when
+    /// they are dropped, so only the remaining handles are destroyed by hand.
     pub unsafe fn destroy(&mut self) {
         self.destroy_swapchain();
 
-        self.device.destroy_sampler(self.data.texture_sampler, None);
         self.device
-            .destroy_image_view(self.data.texture_image_view, None);
-        self.device.destroy_image(self.data.texture_image, None);
+            .destroy_pipeline_layout(self.data.shadow_pipeline_layout, None);
         self.device
-            .free_memory(self.data.texture_image_memory, None);
-        self.device
-            .destroy_descriptor_set_layout(self.data.descriptor_set_layout, None);
-        self.device.destroy_buffer(self.data.vertex_buffer, None);
-        self.device.free_memory(self.data.index_buffer_memory, None);
-        self.device.destroy_buffer(self.data.index_buffer, None);
+            .destroy_pipeline_layout(self.data.pipeline_layout, None);
+
+        // The app still shuts down cleanly if the cache can't be written, the next
+        // start just compiles the pipelines from scratch.
+        if let Err(e) =
+            save_pipeline_cache(&self.device, &self.data, &self.config.pipeline_cache)
+        {
+            warn!("Failed to save the pipeline cache: {}", e);
+        }
         self.device
-            .free_memory(self.data.vertex_buffer_memory, None);
-        self.data
-            .command_completion_fences
-            .iter()
-            .for_each(|f| self.device.destroy_fence(*f, None));
-        self.data
-            .render_finished_semaphores
-            .iter()
-            .for_each(|s| self.device.destroy_semaphore(*s, None));
-        self.data
-            .image_available_semaphores
-            .iter()
-            .for_each(|s| self.device.destroy_semaphore(*s, None));
+            .destroy_pipeline_cache(self.data.pipeline_cache, None);
 
+        self.device
+            .destroy_descriptor_pool(self.data.material_descriptor_pool, None);
+        self.device
+            .destroy_descriptor_pool(self.data.descriptor_pool, None);
+        self.device
+            .destroy_descriptor_set_layout(self.data.material_descriptor_set_layout, None);
+        self.device
+            .destroy_descriptor_set_layout(self.data.descriptor_set_layout, None);
+        for frame in &self.data.frames {
+            self.device
+                .destroy_fence(frame.command_completion_fence, None);
+            self.device
+                .destroy_semaphore(frame.render_finished_semaphore, None);
+            self.device
+                .destroy_semaphore(frame.image_available_semaphore, None);
+            self.device.destroy_command_pool(frame.command_pool, None);
+            self.device.destroy_query_pool(frame.timestamp_pool, None);
+            self.device.destroy_query_pool(frame.statistics_pool, None);
+            self.device.destroy_query_pool(frame.occlusion_pool, None);
+        }
         self.device
             .destroy_command_pool(self.data.command_pool, None);
+
+        // Dropping the data hands every resource it owns to the deletion queue. The
+        // device is idle, so the queue can destroy them right away.
+        let mut data = std::mem::take(&mut self.data);
+        let mut allocator = std::mem::take(&mut data.allocator);
+        let deletion_queue = data.deletion_queue.clone();
+        let (messenger, surface, headless) = (data.messenger, data.surface, data.headless);
+        drop(data);
+        deletion_queue.flush(&self.device, &mut allocator);
+
+        info!("GPU memory: {}.", allocator.stats());
+        allocator.destroy(&self.device);
         self.device.destroy_device(None);
         if VALIDATION_ENABLED {
             self.instance
-                .destroy_debug_utils_messenger_ext(self.data.messenger, None);
+                .destroy_debug_utils_messenger_ext(messenger, None);
+        }
+        if !headless {
+            self.instance.destroy_surface_khr(surface, None);
         }
-        self.instance.destroy_surface_khr(self.data.surface, None);
         self.instance.destroy_instance(None);
     }
 
+    /// Destroys the handles that depend on the swapchain, along with the render graph. The
+    /// pipeline that depends on it is dropped when it is created again.
     unsafe fn destroy_swapchain(&mut self) {
-        self.device
-            .destroy_image_view(self.data.color_image_view, None);
-        self.device.free_memory(self.data.color_image_memory, None);
-        self.device.destroy_image(self.data.color_image, None);
-        self.device
-            .destroy_image_view(self.data.depth_image_view, None);
-        self.device.free_memory(self.data.depth_image_memory, None);
-        self.device.destroy_image(self.data.depth_image, None);
-        self.device
-            .destroy_descriptor_pool(self.data.descriptor_pool, None);
-        self.data
-            .uniform_buffers
-            .iter()
-            .for_each(|b| self.device.destroy_buffer(*b, None));
+        self.data.render_graph.destroy(&self.device);
+
+        // The views of the swapchain images must be gone before the swapchain. The device
+        // is idle, so the deletion queue can destroy them right away.
+        self.data.swapchain_image_views.clear();
         self.data
-            .uniform_buffers_memory
-            .iter()
-            .for_each(|m| self.device.free_memory(*m, None));
+            .deletion_queue
+            .flush(&self.device, &mut self.data.allocator);
 
-        // Freeing the command buffers is not mandatory as they are freed automatically
-        // when the command pool is destroyed.
-        self.device
-            .free_command_buffers(self.data.command_pool, &self.data.command_buffers);
+        // The offscreen image is owned by us, unlike the swapchain images, and is
+        // dropped along with the rest of the data.
+        if !self.data.headless {
+            self.device.destroy_swapchain_khr(self.data.swapchain, None);
+        }
+    }
+
+    /// Renders a frame into the offscreen image of a headless app.
+    ///
+    /// This is the same as `render`, but there is no swapchain image to acquire
+    /// or present, so no semaphores are needed. The command completion fences
+    /// still keep us from reusing resources that the GPU is working with.
+    pub unsafe fn render_offscreen(&mut self) -> Result<()> {
+        let delta = self.clock.tick();
+        self.camera.update(delta.as_secs_f32());
 
+        self.reserve_instances()?;
+
+        let fence = self.data.frames[self.frame].command_completion_fence;
+        self.device.wait_for_fences(&[fence], true, u64::MAX)?;
         self.data
-            .framebuffers
-            .iter()
-            .for_each(|f| self.device.destroy_framebuffer(*f, None));
-        self.device.destroy_pipeline(self.data.pipeline, None);
+            .deletion_queue
+            .next_frame(&self.device, &mut self.data.allocator);
+        self.read_queries()?;
+
+        // There is only one offscreen image, so every frame renders into it.
+        let image_index = 0;
+        self.prepare_screenshot()?;
+
+        self.update_uniform_buffer(self.frame)?;
+        update_instance_buffer(
+            &self.device,
+            &self.data,
+            self.frame,
+            &self.data.scene.instance_data(),
+        )?;
+
+        self.data.frames[self.frame].draws = plan_draws(&self.data, &mut self.hidden_objects);
+        let command_buffer =
+            record_command_buffer(&self.device, &self.data, self.frame, image_index)?;
+        let frame = &mut self.data.frames[self.frame];
+        frame.timed_passes = self.data.render_graph.timed_passes(frame);
+        let command_buffers = &[command_buffer];
+        let submit_info = vk::SubmitInfo::builder().command_buffers(command_buffers);
+
+        self.device.reset_fences(&[fence])?;
         self.device
-            .destroy_pipeline_layout(self.data.pipeline_layout, None);
-        self.device.destroy_render_pass(self.data.render_pass, None);
-        self.data
-            .swapchain_image_views
-            .iter()
-            .for_each(|v| self.device.destroy_image_view(*v, None));
+            .queue_submit(self.data.graphics_queue, &[submit_info], fence)?;
+        self.finish_screenshot()?;
+
+        self.frame = (self.frame + 1) % MAX_FRAMES_IN_FLIGHT;
+
+        Ok(())
+    }
+
+    /// The GPU time of a pass of the render graph in milliseconds, as of the last frame
+    /// whose timestamps were read. `None` if the pass isn't timed.
+    pub fn pass_milliseconds(&self, pass: &str) -> Option<f64> {
+        (self.pass_timings.iter())
+            .find(|t| t.pass == pass)
+            .map(|t| t.milliseconds)
+    }
+
+    /// Reads the queries the current frame wrote the last time it was rendered. Its fence
+    /// has been waited for, so they are available without stalling.
+    unsafe fn read_queries(&mut self) -> Result<()> {
+        let timings = read_pass_timings(&self.device, &mut self.data, self.frame)?;
+        if !timings.is_empty() {
+            let passes = (timings.iter())
+                .map(|t| format!("{} {:.3} ms", t.pass, t.milliseconds))
+                .collect::<Vec<_>>();
+            debug!("GPU time: {}.", passes.join(", "));
+            self.pass_timings = timings;
+        }
+
+        let statistics = read_draw_statistics(&self.device, &mut self.data, self.frame)?;
+        if !statistics.is_empty() {
+            debug!(
+                "Draws: {}, vertex shader invocations: {}, clipping primitives: {}, fragment shader invocations: {}.",
+                statistics.len(),
+                statistics.iter().map(|s| s.vertex_shader_invocations).sum::<u64>(),
+                statistics.iter().map(|s| s.clipping_primitives).sum::<u64>(),
+                statistics.iter().map(|s| s.fragment_shader_invocations).sum::<u64>(),
+            );
+            self.draw_statistics = statistics;
+        }
+
+        for (id, visible) in read_occlusion(&self.device, &mut self.data, self.frame)? {
+            if visible {
+                self.hidden_objects.remove(&id);
+            } else {
+                self.hidden_objects.entry(id).or_insert(0);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Takes a screenshot of the next frame that is rendered and writes it to a PNG file.
+    pub fn request_screenshot(&mut self, path: PathBuf) {
+        self.screenshot = Some(path);
+    }
+
+    /// Prepares the current frame to take the requested screenshot, if there is one.
+    /// When recording, every frame takes one.
+    unsafe fn prepare_screenshot(&mut self) -> Result<()> {
+        if let Some(recorder) = &mut self.recorder {
+            self.screenshot = Some(recorder.next_path());
+        }
+
+        if let Some(path) = self.screenshot.take() {
+            let screenshot = create_screenshot(&self.instance, &self.device, &mut self.data, path)?;
+            self.data.frames[self.frame].screenshot = Some(screenshot);
+        }
+
+        Ok(())
+    }
+
+    /// Saves the screenshot the current frame took once its commands have finished, and
+    /// adds it to the recording.
+    unsafe fn finish_screenshot(&mut self) -> Result<()> {
+        let Some(screenshot) = self.data.frames[self.frame].screenshot.take() else {
+            return Ok(());
+        };
+
+        let fence = self.data.frames[self.frame].command_completion_fence;
+        self.device.wait_for_fences(&[fence], true, u64::MAX)?;
+
+        let rgba = read_screenshot(&self.data, &screenshot)?;
+        write_png(&screenshot.path, &rgba, screenshot.extent)?;
 
-        self.device.destroy_swapchain_khr(self.data.swapchain, None);
+        match &mut self.recorder {
+            Some(recorder) => recorder.add_frame(&rgba)?,
+            None => info!("Saved screenshot to {}.", screenshot.path.display()),
+        }
+
+        Ok(())
+    }
+
+    /// Writes the last frame rendered by `render_offscreen` to a PNG file.
+    pub unsafe fn save_frame(&mut self, path: &Path) -> Result<()> {
+        save_offscreen_image(&self.instance, &self.device, &mut self.data, path)
     }
 
     /// Renders a frame for our Vulkan app.
     pub unsafe fn render(&mut self, window: &Window) -> Result<()> {
         dbg!(self.frame);
         std::thread::sleep(std::time::Duration::from_millis(50));
+
+        let delta = self.clock.tick();
+        self.camera.update(delta.as_secs_f32());
+
+        self.reload_shaders();
+
+        self.reserve_instances()?;
+
         // Ensures that the GPU has finished executing the commands for the current frame
         // (rendering & presenting) before starting a new frame. This avoids overwriting
         // resources (like command buffers and semaphores) that are still in use.
-        self.device.wait_for_fences(
-            &[self.data.command_completion_fences[self.frame]],
-            true,
-            u64::MAX,
-        )?;
+        let fence = self.data.frames[self.frame].command_completion_fence;
+        self.device.wait_for_fences(&[fence], true, u64::MAX)?;
+
+        // The frame that used this slot before has finished, and with it the frames before
+        // that, so resources dropped since are destroyed once no frame in flight can use them.
+        self.data
+            .deletion_queue
+            .next_frame(&self.device, &mut self.data.allocator);
+        self.read_queries()?;
 
         // This semaphore ensures synchronization between the swapchain and the rendering process.
-        let this_frame_image_available_semaphore = self.data.image_available_semaphores[self.frame];
+        let this_frame_image_available_semaphore =
+            self.data.frames[self.frame].image_available_semaphore;
 
         // More like a request to aquire an image - we get the index instantly, but this
         // doesn't mean the image is ready to be processed. It will be once the semaphore
@@ -253,31 +667,35 @@ impl App {
         };
         dbg!(image_index);
 
-        // If a fence exists and hasn't been signaled for this image, means the GPU
-        // is still processing it.
-        if !self.data.image_usage_fences[image_index as usize].is_null() {
-            // So we need to wait for the GPU to finish its operations on this image before proceeding.
-            self.device.wait_for_fences(
-                &[self.data.image_usage_fences[image_index as usize]],
-                true,
-                u64::MAX,
-            )?;
+        // The buffers written here belong to the frame, not to the swapchain image, so the
+        // fence above is all we need to wait for. The image itself is only rendered into
+        // once the semaphore says it's available.
+        // A screenshot that can't be taken is reported, but doesn't stop the rendering.
+        if let Err(e) = self.prepare_screenshot() {
+            warn!("Failed to take a screenshot: {}", e);
         }
-
-        // Associates the fence for the current frame with the swapchain image
-        // to track its usage.
-        self.data.image_usage_fences[image_index as usize] =
-            self.data.command_completion_fences[self.frame];
-
-        self.update_uniform_buffer(image_index)?;
+        self.update_uniform_buffer(self.frame)?;
+        update_instance_buffer(
+            &self.device,
+            &self.data,
+            self.frame,
+            &self.data.scene.instance_data(),
+        )?;
 
         let wait_semaphores = &[this_frame_image_available_semaphore];
 
         // The pipeline waits at the COLOR_ATTACHMENT_OUTPUT stage, which is where rendering
         // to the swapchain image occurs.
         let wait_stages = &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-        let command_buffers = &[self.data.command_buffers[image_index]];
-        let signal_semaphores = &[self.data.render_finished_semaphores[self.frame]];
+
+        // The commands are recorded anew every frame, so they always draw the current scene.
+        self.data.frames[self.frame].draws = plan_draws(&self.data, &mut self.hidden_objects);
+        let command_buffer =
+            record_command_buffer(&self.device, &self.data, self.frame, image_index)?;
+        let frame = &mut self.data.frames[self.frame];
+        frame.timed_passes = self.data.render_graph.timed_passes(frame);
+        let command_buffers = &[command_buffer];
+        let signal_semaphores = &[self.data.frames[self.frame].render_finished_semaphore];
         let submit_info = vk::SubmitInfo::builder()
             // The GPU will wait with processing this command buffer until this semaphore is
             // signaled and it is signaled when the GPU is finished aquiring the image
@@ -292,8 +710,7 @@ impl App {
         // This step ensures the fence associated with the current frame is ready for the next submission.
         // Resetting is mandatory for the fence to unsignal it so it can be signaled again
         // by the GPU when commands are finished executing in the queue.
-        self.device
-            .reset_fences(&[self.data.command_completion_fences[self.frame]])?;
+        self.device.reset_fences(&[fence])?;
 
         // Submission involved associating the command buffer with synchronization primitives
         // (semaphores and fences) to coordinate execution.
@@ -302,7 +719,7 @@ impl App {
             &[submit_info],
             // The fence is signaled by the GPU when all commands in the submitted command buffer
             // have been fully executed by the graphics queue.
-            self.data.command_completion_fences[self.frame],
+            fence,
         )?;
 
         let swapchains = &[self.data.swapchain];
@@ -328,59 +745,24 @@ impl App {
             return Err(anyhow!(e));
         }
 
+        // The copy was recorded before presenting, so the screenshot only has to wait for
+        // the frame's commands, not for the image to be presented.
+        if let Err(e) = self.finish_screenshot() {
+            warn!("Failed to save a screenshot: {}", e);
+        }
+
         self.frame = (self.frame + 1) % MAX_FRAMES_IN_FLIGHT;
 
         Ok(())
     }
 
-    unsafe fn update_uniform_buffer(&self, image_index: usize) -> Result<()> {
-        let time = self.start.elapsed().as_secs_f32();
-
-        let model = Mat4::from_axis_angle(
-            vec3(0.0, 0.0, 1.0),
-            Deg(45.0), // * time
+    /// Writes the matrices and lights into the uniform buffers of a frame in flight.
+    unsafe fn update_uniform_buffer(&self, frame: usize) -> Result<()> {
+        let view = self.camera.view();
+        let proj = self.camera.projection(
+            self.data.swapchain_extent.width as f32 / self.data.swapchain_extent.height as f32,
         );
 
-        let view = Mat4::look_at_rh(
-            point3(0.0, 2.5, 2.5),
-            point3(0.0, 0.0, 0.0),
-            vec3(0.0, 0.0, 1.0),
-        );
-
-        // Mat4::new constructs the matrix in a column-major order, so the matrix look like
-        // [1,  0, 0  , 0  ]
-        // [0, -1, 0  , 0  ]
-        // [0,  0, 0.5, 0.5]
-        // [0,  0, 0  , 1  ]
-        let correction = Mat4::new(
-            1.0,
-            0.0,
-            0.0,
-            0.0,
-            0.0,
-            -1.0,
-            0.0,
-            0.0,
-            0.0,
-            0.0,
-            1.0 / 2.0,
-            0.0,
-            0.0,
-            0.0,
-            1.0 / 2.0,
-            1.0,
-        );
-
-        // cgmath was originally designed for OpenGL, where the Y coordinate of the clip coordinates
-        // is inverted. This is the easiest way to compensate it.
-        let proj = correction
-            * cgmath::perspective(
-                Deg(45.0),
-                self.data.swapchain_extent.width as f32 / self.data.swapchain_extent.height as f32,
-                0.1,
-                10.0,
-            );
-
         // Passing in individual matrices to the GPU and multiplying them in the vertex shader
         // offloads work to the GPU, but is not recommended for low-poly meshes.
         // For static meshes (that don't change location) the MVP should be pre-calculated
@@ -388,34 +770,111 @@ impl App {
         // dynamic scenes, high-poly meshes, CPU-bound applications, per-vertex transformations.
         // There is also the hybrid approach: Calculate the VP one the CPU and MVP = VP * model
         // in the vertex shader. This reduces data transfer while retaining some GPU flexibility.
-        //let ubo = UniformBufferObject {
-        //    model, view, proj
-        //};
+        // The model matrices are part of the instance data (see instance_buffer.rs).
 
-        let ubo = UniformBufferObject { model, view, proj };
+        // The shadow map covers every object of the scene.
+        let light_space = light_space_matrix(
+            self.directional_light.direction.truncate(),
+            self.data.scene.bounds(),
+        );
 
-        let memory = self.device.map_memory(
-            self.data.uniform_buffers_memory[image_index],
-            0,
-            size_of::<UniformBufferObject>() as u64,
-            vk::MemoryMapFlags::empty(),
-        )?;
+        let ubo = UniformBufferObject {
+            view,
+            proj,
+            light_space,
+        };
+
+        let memory = self
+            .data
+            .allocator
+            .mapped(self.data.frames[frame].uniform_buffer.allocation())?;
 
         memcpy(&ubo, memory.cast(), 1);
 
-        self.device
-            .unmap_memory(self.data.uniform_buffers_memory[image_index]);
+        let mut point_lights = [PointLight {
+            position: vec4(0.0, 0.0, 0.0, 0.0),
+            color: vec4(0.0, 0.0, 0.0, 0.0),
+        }; MAX_POINT_LIGHTS];
+        let point_light_count = self.point_lights.len().min(MAX_POINT_LIGHTS);
+        point_lights[..point_light_count].copy_from_slice(&self.point_lights[..point_light_count]);
+
+        let lights = LightUniformBufferObject {
+            camera_position: self.camera.eye().to_vec().extend(1.0),
+            ambient: vec4(1.0, 1.0, 1.0, 0.1),
+            directional: self.directional_light,
+            shadow: vec4(
+                self.config.shadows.pcf_radius as f32,
+                1.0 / self.data.shadow_map_size as f32,
+                0.0,
+                0.0,
+            ),
+            point_lights,
+            point_light_count: point_light_count as u32,
+            _padding: [0; 3],
+        };
+
+        let memory = self
+            .data
+            .allocator
+            .mapped(self.data.frames[frame].light_buffer.allocation())?;
+
+        memcpy(&lights, memory.cast(), 1);
 
         Ok(())
     }
 }
 
+/// Loads the models into the scene, adding an object for each. The first model is
+/// placed at the origin and the others are lined up to the right of it along +X.
+unsafe fn load_models(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    models: &[PathBuf],
+    texture: &Path,
+) -> Result<()> {
+    let mut right = None;
+
+    for model in models {
+        let mesh = load_mesh(instance, device, data, model, texture)?;
+        let bounds = data.scene.meshes[mesh].bounds;
+
+        let transform = match right {
+            None => Mat4::identity(),
+            Some(right) => {
+                Mat4::from_translation(vec3(right + bounds.radius - bounds.center.x, 0.0, 0.0))
+            }
+        };
+
+        let placed = bounds.transformed(transform);
+        right = Some(placed.center.x + placed.radius);
+        data.scene.add_object(mesh, transform);
+    }
+
+    Ok(())
+}
+
 /// The Vulkan handles and associated properties used by our Vulkan app.
-#[derive(Clone, Debug, Default)]
+///
+/// The buffers, images, image views, samplers and pipelines are owned (see resources.rs)
+/// and destroy themselves through the deletion queue when they are dropped or replaced.
+/// The other handles are destroyed by `App::destroy`.
+#[derive(Debug, Default)]
 pub struct AppData {
+    /// Set when rendering without a window. There is no surface or swapchain then,
+    /// and the swapchain image fields hold a single offscreen image instead.
+    pub headless: bool,
     pub messenger: vk::DebugUtilsMessengerEXT,
+    /// Names objects and labels passes when validation is enabled.
+    pub debug_utils: DebugUtils,
     pub physical_device: vk::PhysicalDevice,
     pub msaa_samples: vk::SampleCountFlags,
+    /// Set when drawing with Vulkan 1.3 dynamic rendering (see render_graph.rs). The
+    /// render passes and framebuffers aren't created then and stay null.
+    pub dynamic_rendering: bool,
+    /// Set when the instance textures can be indexed with a non-uniform index, which
+    /// instances need to pick their own texture (see `App::set_object_texture`).
+    pub non_uniform_indexing: bool,
     pub graphics_queue: vk::Queue,
     pub present_queue: vk::Queue,
     pub surface: vk::SurfaceKHR,
@@ -423,68 +882,75 @@ pub struct AppData {
     pub swapchain_extent: vk::Extent2D,
     pub swapchain: vk::SwapchainKHR,
     pub swapchain_images: Vec<vk::Image>,
-    pub swapchain_image_views: Vec<vk::ImageView>,
-    pub render_pass: vk::RenderPass,
+    pub swapchain_image_views: Vec<ImageView>,
+    /// The passes that draw a frame and the attachments they create (see passes.rs).
+    pub render_graph: RenderGraph,
 
     /// The layoud of the descriptor set for the UBO that holds the MVP matrix.
     pub descriptor_set_layout: vk::DescriptorSetLayout,
     pub pipeline_layout: vk::PipelineLayout,
-    pub pipeline: vk::Pipeline,
-    pub framebuffers: Vec<vk::Framebuffer>,
+    pub pipeline: Pipeline,
+    /// The compiled shaders, recompiled when their files change.
+    pub shaders: ShaderLibrary,
+    /// Shared by every pipeline creation, saved to disk on exit (see pipeline_cache.rs).
+    pub pipeline_cache: vk::PipelineCache,
+    /// The pool of the one-time commands that upload resources.
     pub command_pool: vk::CommandPool,
-    pub command_buffers: Vec<vk::CommandBuffer>,
-
-    /// These semaphores corespond to swapchain images and are signaled
-    /// when the GPU has finished aquiring an image from the swapchain.
-    /// Used to synchronize rendering operations with image availability.
-    pub image_available_semaphores: Vec<vk::Semaphore>,
-
-    /// These semaphores are signaled when the GPU has finished rendering
-    /// to a swapchain image. They synchronize rendering operations with the
-    /// presentation engine, ensuring the image is ready to be presented.
-    pub render_finished_semaphores: Vec<vk::Semaphore>,
-
-    /// Signaled by the GPU when all commands for the current frame have
-    /// finished executing. Ensures that the CPU does not overwrite
-    /// or reuse resources still in use by the GPU.
-    pub command_completion_fences: Vec<vk::Fence>,
-
-    /// Fences associated with swapchain images currently in use by the GPU.
-    /// Ensures that a swapchain image is not overwritten or reused
-    /// while it is still being processed.
-    pub image_usage_fences: Vec<vk::Fence>,
-
-    pub vertices: Vec<Vertex>,
-    pub vertex_buffer: vk::Buffer,
-    pub vertex_buffer_memory: vk::DeviceMemory,
-
-    pub indices: Vec<u32>,
-    pub index_buffer: vk::Buffer,
-    pub index_buffer_memory: vk::DeviceMemory,
-
-    /// One uniform buffer per swapchain image as we will have a different MVP matrix
-    /// in every frame and we don't want to modify a buffer that is in use by the
-    /// previous frame.
-    pub uniform_buffers: Vec<vk::Buffer>,
-    pub uniform_buffers_memory: Vec<vk::DeviceMemory>,
 
+    /// The command buffers, uniform buffers, descriptor sets and sync objects of each
+    /// frame in flight, indexed by `App::frame`.
+    pub frames: Vec<FrameContext>,
+
+    /// The meshes with their vertex and index buffers, and the objects drawing them.
+    pub scene: Scene,
+    /// The materials of every mesh. The primitives of a mesh refer to them by index.
+    pub materials: Vec<Material>,
+    /// The material of primitives that have none, once a mesh needed it.
+    pub default_material: Option<usize>,
+    /// Decoded images referenced by the materials.
+    pub material_textures: Vec<TextureData>,
+
+    /// The number of instances the instance buffers of the frames have room for.
+    pub instance_capacity: usize,
+    pub shadow_pipeline_layout: vk::PipelineLayout,
+    pub shadow_pipeline: Pipeline,
+    pub shadow_map: Image,
+    pub shadow_map_view: ImageView,
+    pub shadow_map_size: u32,
+    pub shadow_sampler: Sampler,
+
+    /// Holds the descriptor sets of the frames.
     pub descriptor_pool: vk::DescriptorPool,
-    pub descriptor_sets: Vec<vk::DescriptorSet>,
-
-    /// Resources for textures
-    pub mip_levels: u32,
-    pub texture_image: vk::Image,
-    pub texture_image_memory: vk::DeviceMemory,
-    pub texture_image_view: vk::ImageView,
-    pub texture_sampler: vk::Sampler,
-
-    /// Resources for the depth buffer
-    pub depth_image: vk::Image,
-    pub depth_image_memory: vk::DeviceMemory,
-    pub depth_image_view: vk::ImageView,
-
-    // Resources for multisampling
-    pub color_image: vk::Image,
-    pub color_image_memory: vk::DeviceMemory,
-    pub color_image_view: vk::ImageView,
+
+    /// Resources for textures, uploaded from `material_textures` with the same indices.
+    pub textures: Vec<Texture>,
+    /// Sampled in place of the textures a material doesn't have.
+    pub white_texture: Texture,
+    pub flat_normal_texture: Texture,
+    pub texture_sampler: Sampler,
+
+    /// Resources for materials, one uniform buffer and descriptor set per material.
+    pub material_descriptor_set_layout: vk::DescriptorSetLayout,
+    pub material_buffers: Vec<Buffer>,
+    pub material_descriptor_pool: vk::DescriptorPool,
+    pub material_descriptor_sets: Vec<vk::DescriptorSet>,
+
+    /// The offscreen image that replaces the swapchain in headless mode.
+    pub offscreen_image: Image,
+    /// Set when the swapchain images (or the offscreen image) can be copied from, which
+    /// screenshots need.
+    pub capture_supported: bool,
+    /// The nanoseconds per tick of a timestamp query, and the number of bits the graphics
+    /// queue writes. Both are 0 when the queue has no timestamps.
+    pub timestamp_period: f32,
+    pub timestamp_valid_bits: u32,
+    /// Set when the draws of the main pass are counted with pipeline statistics queries.
+    pub pipeline_statistics: bool,
+    /// Set when objects hidden in an earlier frame skip drawing (see `plan_draws`).
+    pub occlusion_culling: bool,
+
+    /// Sub-allocates the memory of every buffer and image.
+    pub allocator: Allocator,
+    /// Destroys the resources above once they are dropped and no longer in use.
+    pub deletion_queue: DeletionQueue,
 }
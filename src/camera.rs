@@ -0,0 +1,270 @@
+use std::collections::HashSet;
+use std::f32::consts::FRAC_PI_2;
+
+use cgmath::{point3, vec3, Angle, Deg, EuclideanSpace, InnerSpace, Point3, Rad};
+use winit::dpi::PhysicalPosition;
+use winit::event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+use crate::vulkan::buffers::uniform_buffer::{Mat4, CORRECTION};
+use crate::vulkan::model::Bounds;
+use crate::vulkan::vertex::Vec3;
+
+/// Radians the camera turns per pixel the mouse moves.
+const ROTATE_SENSITIVITY: f32 = 0.005;
+/// Fraction of the orbit distance the camera pans per pixel the mouse moves.
+const PAN_SENSITIVITY: f32 = 0.0015;
+/// Factor the orbit distance changes by per line scrolled.
+const ZOOM_FACTOR: f32 = 0.9;
+/// Looking straight up or down would make the view direction parallel to the up vector.
+const MAX_PITCH: Rad<f32> = Rad(FRAC_PI_2 - 0.01);
+
+/// How the camera moves.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CameraMode {
+    /// Circles around a target point, for inspecting a model. Dragging with the left
+    /// mouse button orbits, with the right or middle button (or shift and the left
+    /// button) pans, and scrolling zooms in and out.
+    Orbit,
+    /// Moves freely like in a first person game. Dragging with the left mouse button
+    /// looks around, W, A, S, D move, Q and E move down and up, holding shift moves faster.
+    Fly,
+}
+
+/// The input state the camera keeps between window events.
+#[derive(Clone, Debug, Default)]
+struct CameraInput {
+    rotating: bool,
+    panning: bool,
+    shift: bool,
+    cursor: Option<PhysicalPosition<f64>>,
+    keys: HashSet<KeyCode>,
+}
+
+/// A camera driven by mouse and keyboard input, which provides the view and projection
+/// matrices. Tab switches between the orbit and fly modes. The scene uses Z as up.
+#[derive(Clone, Debug)]
+pub struct Camera {
+    pub mode: CameraMode,
+    /// The point the orbit camera looks at.
+    pub target: Point3<f32>,
+    /// The distance of the orbit camera from its target.
+    pub distance: f32,
+    /// The position of the fly camera.
+    pub position: Point3<f32>,
+    /// Rotation of the view direction around the Z axis, 0 looks along -X.
+    pub yaw: Rad<f32>,
+    /// Angle of the view direction below the horizon.
+    pub pitch: Rad<f32>,
+    /// Vertical field of view.
+    pub fov: Deg<f32>,
+    pub near: f32,
+    pub far: f32,
+    /// Units per second the fly camera moves.
+    pub speed: f32,
+    /// Angle per second the orbit camera circles its target by on its own, e.g. to
+    /// record a turntable video. 0 keeps it still.
+    pub turntable: Rad<f32>,
+    input: CameraInput,
+}
+
+impl Camera {
+    /// An orbit camera looking at the origin from above +Y.
+    pub fn new(fov: Deg<f32>, near: f32, far: f32) -> Self {
+        let mut camera = Self {
+            mode: CameraMode::Orbit,
+            target: point3(0.0, 0.0, 0.0),
+            distance: 2.5 * 2.0f32.sqrt(),
+            position: point3(0.0, 0.0, 0.0),
+            yaw: Deg(45.0).into(),
+            pitch: Deg(45.0).into(),
+            fov,
+            near,
+            far,
+            speed: 1.0,
+            turntable: Rad(0.0),
+            input: CameraInput::default(),
+        };
+        camera.position = camera.eye();
+        camera
+    }
+
+    /// Points the orbit camera at the center of the bounds, from far enough away to
+    /// see all of it, keeping the current direction.
+    pub fn frame(&mut self, bounds: Bounds) {
+        let radius = bounds.radius.max(0.01);
+        self.target = Point3::from_vec(bounds.center);
+        self.distance = radius / (self.fov / 2.0).sin();
+        self.position = self.eye();
+        self.speed = radius;
+    }
+
+    /// The direction from the orbit target towards the camera.
+    fn backward(&self) -> Vec3 {
+        let (yaw_sin, yaw_cos) = self.yaw.sin_cos();
+        let (pitch_sin, pitch_cos) = self.pitch.sin_cos();
+        vec3(pitch_cos * yaw_cos, pitch_cos * yaw_sin, pitch_sin)
+    }
+
+    /// The camera's position in world space.
+    pub fn eye(&self) -> Point3<f32> {
+        match self.mode {
+            CameraMode::Orbit => self.target + self.backward() * self.distance,
+            CameraMode::Fly => self.position,
+        }
+    }
+
+    pub fn view(&self) -> Mat4 {
+        let eye = self.eye();
+        Mat4::look_at_rh(eye, eye - self.backward(), vec3(0.0, 0.0, 1.0))
+    }
+
+    /// The perspective projection for a viewport with the given aspect ratio (width / height).
+    pub fn projection(&self, aspect: f32) -> Mat4 {
+        CORRECTION * cgmath::perspective(self.fov, aspect, self.near, self.far)
+    }
+
+    /// Switches between the orbit and fly modes without moving the camera.
+    pub fn toggle_mode(&mut self) {
+        match self.mode {
+            CameraMode::Orbit => {
+                self.position = self.eye();
+                self.mode = CameraMode::Fly;
+            }
+            CameraMode::Fly => {
+                // Orbit around the point in front of the camera.
+                self.target = self.position - self.backward() * self.distance;
+                self.mode = CameraMode::Orbit;
+            }
+        }
+    }
+
+    /// Updates the camera from a window event.
+    pub fn handle_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.input.shift = modifiers.state().shift_key();
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(code),
+                        state,
+                        repeat,
+                        ..
+                    },
+                ..
+            } => match state {
+                ElementState::Pressed if *code == KeyCode::Tab && !repeat => self.toggle_mode(),
+                ElementState::Pressed => {
+                    self.input.keys.insert(*code);
+                }
+                ElementState::Released => {
+                    self.input.keys.remove(code);
+                }
+            },
+            WindowEvent::MouseInput { state, button, .. } => {
+                let pressed = *state == ElementState::Pressed;
+                match button {
+                    MouseButton::Left if pressed && self.input.shift => self.input.panning = true,
+                    MouseButton::Left => {
+                        self.input.rotating = pressed;
+                        self.input.panning &= pressed;
+                    }
+                    MouseButton::Right | MouseButton::Middle => self.input.panning = pressed,
+                    _ => {}
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                if let Some(last) = self.input.cursor {
+                    let dx = (position.x - last.x) as f32;
+                    let dy = (position.y - last.y) as f32;
+                    if self.input.panning {
+                        self.pan(dx, dy);
+                    } else if self.input.rotating {
+                        self.rotate(dx, dy);
+                    }
+                }
+                self.input.cursor = Some(*position);
+            }
+            WindowEvent::CursorLeft { .. } => self.input.cursor = None,
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 50.0,
+                };
+                self.zoom(lines);
+            }
+            // Keys released while the window isn't focused would otherwise stay pressed.
+            WindowEvent::Focused(false) => self.input = CameraInput::default(),
+            _ => {}
+        }
+    }
+
+    /// Moves the fly camera with the keys that are held down, or turns the orbit camera
+    /// around its target.
+    pub fn update(&mut self, delta: f32) {
+        if self.mode == CameraMode::Orbit {
+            self.yaw += self.turntable * delta;
+            return;
+        }
+
+        let forward = -self.backward();
+        let right = forward.cross(vec3(0.0, 0.0, 1.0)).normalize();
+        let up = vec3(0.0, 0.0, 1.0);
+
+        let mut direction = vec3(0.0, 0.0, 0.0);
+        for (key, step) in [
+            (KeyCode::KeyW, forward),
+            (KeyCode::KeyS, -forward),
+            (KeyCode::KeyD, right),
+            (KeyCode::KeyA, -right),
+            (KeyCode::KeyE, up),
+            (KeyCode::KeyQ, -up),
+        ] {
+            if self.input.keys.contains(&key) {
+                direction += step;
+            }
+        }
+
+        if direction.magnitude2() > 0.0 {
+            let speed = if self.input.shift {
+                4.0 * self.speed
+            } else {
+                self.speed
+            };
+            self.position += direction.normalize() * speed * delta;
+        }
+    }
+
+    /// Dragging to the right turns the fly camera to the right, and moves the orbit
+    /// camera to the left so the model turns to the right. Dragging down tilts the
+    /// fly camera down and moves the orbit camera up.
+    fn rotate(&mut self, dx: f32, dy: f32) {
+        self.yaw -= Rad(dx * ROTATE_SENSITIVITY);
+        self.pitch += Rad(dy * ROTATE_SENSITIVITY);
+        self.pitch = Rad(self.pitch.0.clamp(-MAX_PITCH.0, MAX_PITCH.0));
+    }
+
+    /// Moves the camera parallel to the view plane, so the scene follows the mouse.
+    fn pan(&mut self, dx: f32, dy: f32) {
+        let forward = -self.backward();
+        let right = forward.cross(vec3(0.0, 0.0, 1.0)).normalize();
+        let up = right.cross(forward);
+        let offset = (-right * dx + up * dy) * self.distance * PAN_SENSITIVITY;
+
+        match self.mode {
+            CameraMode::Orbit => self.target += offset,
+            CameraMode::Fly => self.position += offset,
+        }
+    }
+
+    fn zoom(&mut self, lines: f32) {
+        match self.mode {
+            CameraMode::Orbit => {
+                self.distance = (self.distance * ZOOM_FACTOR.powf(lines)).max(self.near);
+            }
+            CameraMode::Fly => self.position += -self.backward() * lines * self.speed * 0.1,
+        }
+    }
+}
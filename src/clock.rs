@@ -0,0 +1,93 @@
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+
+/// Where the app gets the time of a frame from, which moves the camera and everything
+/// else that is animated.
+///
+/// Drawing to a window follows the wall clock. Rendering headless uses a fixed timestep
+/// instead, so that the same number of frames always shows the same scene, no matter how
+/// long they took to render (e.g. when recording a video).
+pub trait Clock: Debug {
+    /// Advances to the next frame and returns the time since the previous one.
+    fn tick(&mut self) -> Duration;
+}
+
+/// The real time. The first frame follows the creation of the clock.
+#[derive(Clone, Debug)]
+pub struct WallClock {
+    last: Instant,
+}
+
+impl WallClock {
+    pub fn new() -> Self {
+        Self {
+            last: Instant::now(),
+        }
+    }
+}
+
+impl Default for WallClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for WallClock {
+    fn tick(&mut self) -> Duration {
+        let now = Instant::now();
+        let delta = now.duration_since(self.last);
+        self.last = now;
+        delta
+    }
+}
+
+/// A simulated time that advances by the same step every frame. The first frame is at
+/// the start.
+#[derive(Clone, Debug)]
+pub struct FixedClock {
+    step: Duration,
+    /// Whether the first frame, which is at the start, has been ticked.
+    started: bool,
+}
+
+impl FixedClock {
+    /// A clock for a video with the given frame rate.
+    pub fn new(fps: u32) -> Self {
+        Self {
+            step: Duration::from_secs(1) / fps.max(1),
+            started: false,
+        }
+    }
+}
+
+impl Clock for FixedClock {
+    fn tick(&mut self) -> Duration {
+        if self.started {
+            self.step
+        } else {
+            self.started = true;
+            Duration::ZERO
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_clock_starts_at_zero_and_advances_by_the_frame_time() {
+        let mut clock = FixedClock::new(25);
+        assert_eq!(clock.tick(), Duration::ZERO);
+        for _ in 0..3 {
+            assert_eq!(clock.tick(), Duration::from_millis(40));
+        }
+    }
+
+    #[test]
+    fn fixed_clock_without_a_frame_rate_steps_by_a_second() {
+        let mut clock = FixedClock::new(0);
+        clock.tick();
+        assert_eq!(clock.tick(), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,291 @@
+use std::path::PathBuf;
+
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, ValueEnum};
+use vulkanalia::prelude::v1_3::*;
+
+use crate::vulkan::shader::SHADER_SOURCE_DIR;
+
+/// Settings that choose what the renderer draws and how.
+#[derive(Clone, Debug)]
+pub struct RendererConfig {
+    /// The models to load, OBJ or glTF 2.0 (.gltf or .glb) files. Each is added to the
+    /// scene as an object.
+    pub models: Vec<PathBuf>,
+    /// The PNG texture used for the parts of the models that have no material.
+    pub texture: PathBuf,
+    /// Size of the window, or of the offscreen image when rendering headless.
+    pub width: u32,
+    pub height: u32,
+    /// The highest number of samples per pixel used for multisampling. The device
+    /// may support less, in which case its maximum is used instead.
+    pub msaa_samples: vk::SampleCountFlags,
+    /// The preferred presentation mode. FIFO is used when it's not supported, as
+    /// that is the only mode every device is required to support.
+    pub present_mode: vk::PresentModeKHR,
+    pub shadows: ShadowSettings,
+    /// Vertical field of view of the camera in degrees.
+    pub fov: f32,
+    /// Distances of the camera's near and far clipping planes.
+    pub near: f32,
+    pub far: f32,
+    /// Degrees per second the orbit camera circles the scene by on its own.
+    pub turntable: f32,
+    /// Where the pipeline cache is loaded from at startup and saved to on exit.
+    pub pipeline_cache: PathBuf,
+    /// The directory of the WGSL shaders, `None` for the shaders built into the binary.
+    /// When rendering to a window, the pipelines are rebuilt whenever a shader in it
+    /// changes.
+    pub shaders: Option<PathBuf>,
+    /// Draws with render passes and framebuffers even when the device supports dynamic
+    /// rendering.
+    pub legacy_render_pass: bool,
+    /// Counts the shader invocations and primitives of every draw, if the device can.
+    pub pipeline_statistics: bool,
+    /// Skips drawing the objects that were hidden in an earlier frame.
+    pub occlusion_culling: bool,
+}
+
+/// Settings of the shadow map of the directional light. The shadow map resources are
+/// rebuilt when they change (see `App::set_shadow_settings`).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ShadowSettings {
+    /// Width and height of the shadow map in texels. Larger maps give sharper shadows.
+    pub map_size: u32,
+    /// The shadow of a fragment is averaged over the (2 * radius + 1)^2 texels around it,
+    /// which softens the jagged shadow edges. 0 samples a single texel.
+    pub pcf_radius: u32,
+    /// The depth bias added to every texel of the shadow map, and the one scaled by the
+    /// slope of the surface. They push the shadow map back a little, so that surfaces
+    /// facing the light don't shadow themselves (shadow acne).
+    pub depth_bias_constant: f32,
+    pub depth_bias_slope: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            map_size: 2048,
+            pcf_radius: 1,
+            depth_bias_constant: 1.25,
+            depth_bias_slope: 1.75,
+        }
+    }
+}
+
+/// Presentation modes that can be chosen on the command line.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PresentMode {
+    /// Images are presented right away, which may cause tearing.
+    Immediate,
+    /// Replaces queued images with newer ones, no tearing and low latency.
+    Mailbox,
+    /// Waits for the vertical blank (vsync).
+    Fifo,
+    /// Like FIFO, but presents right away if the application is late.
+    FifoRelaxed,
+}
+
+impl From<PresentMode> for vk::PresentModeKHR {
+    fn from(mode: PresentMode) -> Self {
+        match mode {
+            PresentMode::Immediate => vk::PresentModeKHR::IMMEDIATE,
+            PresentMode::Mailbox => vk::PresentModeKHR::MAILBOX,
+            PresentMode::Fifo => vk::PresentModeKHR::FIFO,
+            PresentMode::FifoRelaxed => vk::PresentModeKHR::FIFO_RELAXED,
+        }
+    }
+}
+
+/// Command-line interface of the viewer.
+#[derive(Debug, Parser)]
+#[command(about = "A Vulkan model viewer")]
+pub struct Cli {
+    /// A model to load, an OBJ or glTF 2.0 (.gltf or .glb) file. Can be given more than
+    /// once to load several models, which are placed next to each other.
+    #[arg(long = "model", default_value = "resources/viking_room.obj")]
+    pub models: Vec<PathBuf>,
+
+    /// The PNG texture used for the parts of the models that have no material.
+    #[arg(long, default_value = "resources/viking_room.png")]
+    pub texture: PathBuf,
+
+    /// Width of the window or offscreen image.
+    #[arg(
+        long,
+        default_value_t = 1024,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub width: u32,
+
+    /// Height of the window or offscreen image.
+    #[arg(
+        long,
+        default_value_t = 768,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub height: u32,
+
+    /// Maximum number of MSAA samples per pixel (1, 2, 4, 8, 16, 32 or 64).
+    #[arg(long, default_value_t = 64, value_parser = parse_msaa_samples)]
+    pub msaa: u32,
+
+    /// Preferred presentation mode, FIFO is used if it's not supported.
+    #[arg(long, value_enum, default_value_t = PresentMode::Mailbox)]
+    pub present_mode: PresentMode,
+
+    /// Width and height of the shadow map in texels.
+    #[arg(
+        long,
+        default_value_t = ShadowSettings::default().map_size,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub shadow_map_size: u32,
+
+    /// Radius in texels of the PCF filter that softens the shadow edges, 0 disables filtering.
+    #[arg(long, default_value_t = ShadowSettings::default().pcf_radius)]
+    pub shadow_pcf_radius: u32,
+
+    /// Vertical field of view of the camera in degrees.
+    #[arg(long, default_value_t = 45.0, value_parser = parse_fov)]
+    pub fov: f32,
+
+    /// Distance of the camera's near clipping plane.
+    #[arg(long, default_value_t = 0.1, value_parser = parse_positive)]
+    pub near: f32,
+
+    /// Distance of the camera's far clipping plane, must be beyond the near plane.
+    #[arg(long, default_value_t = 100.0, value_parser = parse_positive)]
+    pub far: f32,
+
+    /// Degrees per second the camera circles the scene by on its own, for turntable videos.
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub turntable: f32,
+
+    /// Directory the WGSL shaders are loaded from and watched in. By default they are
+    /// loaded from the source tree the binary was built from if it still exists, or else
+    /// the shaders built into the binary are used.
+    #[arg(long)]
+    pub shaders: Option<PathBuf>,
+
+    /// File the compiled pipelines are cached in between runs.
+    #[arg(long, default_value = "pipeline_cache.bin")]
+    pub pipeline_cache: PathBuf,
+
+    /// Use render passes and framebuffers instead of Vulkan 1.3 dynamic rendering.
+    #[arg(long)]
+    pub legacy_render_pass: bool,
+
+    /// Count the vertex and fragment shader invocations and clipped primitives of every draw.
+    #[arg(long)]
+    pub pipeline_statistics: bool,
+
+    /// Skip drawing objects that occlusion queries found hidden in an earlier frame.
+    #[arg(long)]
+    pub occlusion_culling: bool,
+
+    /// Render without a window and save the last frame to a PNG file.
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Number of frames to render in headless mode.
+    #[arg(
+        long,
+        default_value_t = 1,
+        requires = "headless",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub frames: u32,
+
+    /// Where to save the last frame in headless mode.
+    #[arg(long, default_value = "frame.png", requires = "headless")]
+    pub output: PathBuf,
+
+    /// Frames per second of the simulated time in headless mode. Every frame advances
+    /// the time by the same step, no matter how long it took to render.
+    #[arg(
+        long,
+        default_value_t = 30,
+        requires = "headless",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub fps: u32,
+
+    /// Save every frame rendered in headless mode to a numbered PNG file in this directory.
+    #[arg(long, requires = "headless")]
+    pub record: Option<PathBuf>,
+
+    /// Also write the recorded frames to an uncompressed Y4M video, "-" for stdout.
+    #[arg(long, requires = "record")]
+    pub y4m: Option<PathBuf>,
+}
+
+impl Cli {
+    pub fn renderer_config(&self) -> RendererConfig {
+        RendererConfig {
+            models: self.models.clone(),
+            texture: self.texture.clone(),
+            width: self.width,
+            height: self.height,
+            msaa_samples: vk::SampleCountFlags::from_bits_truncate(self.msaa),
+            present_mode: self.present_mode.into(),
+            shadows: ShadowSettings {
+                map_size: self.shadow_map_size,
+                pcf_radius: self.shadow_pcf_radius,
+                ..Default::default()
+            },
+            fov: self.fov,
+            near: self.near,
+            far: self.far,
+            turntable: self.turntable,
+            pipeline_cache: self.pipeline_cache.clone(),
+            shaders: (self.shaders.clone())
+                .or_else(|| Some(PathBuf::from(SHADER_SOURCE_DIR)).filter(|d| d.is_dir())),
+            legacy_render_pass: self.legacy_render_pass,
+            pipeline_statistics: self.pipeline_statistics,
+            occlusion_culling: self.occlusion_culling,
+        }
+    }
+
+    /// Checks the arguments that depend on each other, which clap can't validate.
+    pub fn validate(&self) -> Result<(), clap::Error> {
+        if self.far <= self.near {
+            return Err(Self::command().error(
+                ErrorKind::ValueValidation,
+                format!(
+                    "the far plane ({}) must be beyond the near plane ({})",
+                    self.far, self.near
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_fov(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(fov) if fov > 0.0 && fov < 180.0 => Ok(fov),
+        _ => Err(format!(
+            "'{}' is not an angle between 0 and 180 degrees",
+            value
+        )),
+    }
+}
+
+fn parse_positive(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(distance) if distance > 0.0 && distance.is_finite() => Ok(distance),
+        _ => Err(format!("'{}' is not a positive number", value)),
+    }
+}
+
+fn parse_msaa_samples(value: &str) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(samples) if samples.is_power_of_two() && samples <= 64 => Ok(samples),
+        _ => Err(format!(
+            "'{}' is not one of 1, 2, 4, 8, 16, 32 or 64",
+            value
+        )),
+    }
+}
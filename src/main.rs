@@ -6,34 +6,95 @@
 )]
 
 mod app;
+mod camera;
+mod clock;
+mod config;
+mod recording;
 mod vulkan;
 
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use anyhow::Result;
 use app::App;
+use clap::Parser;
+use clock::FixedClock;
+use config::{Cli, RendererConfig};
+use log::*;
+use recording::Recorder;
 use vulkanalia::prelude::v1_3::*;
 use winit::dpi::LogicalSize;
-use winit::event::{Event, WindowEvent};
+use winit::event::{ElementState, Event, KeyEvent, WindowEvent};
 use winit::event_loop::EventLoop;
+use winit::keyboard::{KeyCode, PhysicalKey};
 use winit::window::WindowBuilder;
 
+/// Renders the requested number of frames without a window and saves the last one.
+/// Used e.g. in CI with a software Vulkan driver, or to record every frame.
+fn run_headless(config: RendererConfig, cli: &Cli) -> Result<()> {
+    unsafe {
+        let (width, height) = (config.width, config.height);
+        let mut app = App::create_headless(config, Box::new(FixedClock::new(cli.fps)))?;
+        if let Some(dir) = &cli.record {
+            let recorder = Recorder::new(dir, cli.y4m.as_deref(), width, height, cli.fps)?;
+            app.recorder = Some(recorder);
+        }
+
+        for _ in 0..cli.frames {
+            app.render_offscreen()?;
+        }
+        app.save_frame(&cli.output)?;
+        info!("Saved frame to {}.", cli.output.display());
+
+        if let Some(recorder) = app.recorder.take() {
+            let frames = recorder.finish()?;
+            info!("Recorded {} frames.", frames);
+        }
+
+        app.device.device_wait_idle()?;
+        app.destroy();
+    }
+
+    Ok(())
+}
+
+/// A file name for a screenshot that doesn't overwrite earlier ones.
+fn screenshot_path() -> PathBuf {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+    PathBuf::from(format!("screenshot-{}.png", millis))
+}
+
 fn main() -> Result<()> {
     pretty_env_logger::init();
 
+    let cli = Cli::parse();
+    if let Err(error) = cli.validate() {
+        error.exit();
+    }
+    let config = cli.renderer_config();
+
+    if cli.headless {
+        return run_headless(config, &cli);
+    }
+
     // Window
     let event_loop = EventLoop::new()?;
     let window = WindowBuilder::new()
         .with_title("Vulkan here we goo!!!")
-        .with_inner_size(LogicalSize::new(1024, 768))
+        .with_inner_size(LogicalSize::new(config.width, config.height))
         .build(&event_loop)?;
 
     // Vulkan App
-    let mut app = unsafe { App::create(&window)? };
+    let mut app = unsafe { App::create(&window, config)? };
     let mut minimized = false;
     event_loop.run(move |event, elwt| {
         match event {
             // Request a redraw when all events were processed.
             Event::AboutToWait => window.request_redraw(),
             Event::WindowEvent { event, .. } => {
+                app.camera.handle_event(&event);
                 match event {
                     WindowEvent::RedrawRequested if !elwt.exiting() && !minimized => {
                         unsafe { app.render(&window) }.unwrap()
@@ -59,6 +120,17 @@ fn main() -> Result<()> {
                             app.resized = true;
                         }
                     }
+                    // Saves the next frame to the working directory.
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                physical_key: PhysicalKey::Code(KeyCode::F12),
+                                state: ElementState::Pressed,
+                                repeat: false,
+                                ..
+                            },
+                        ..
+                    } => app.request_screenshot(screenshot_path()),
                     WindowEvent::DroppedFile(buf) => {
                         println!("{}", buf.display());
                     }
@@ -0,0 +1,186 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+/// Records every frame rendered headless, e.g. for a turntable video.
+///
+/// Each frame is saved to a numbered PNG file (frame-00000.png, frame-00001.png, ...)
+/// in the recording's directory. The frames can also be written to an uncompressed
+/// YUV4MPEG2 (Y4M) stream, which encoders like ffmpeg read directly:
+///
+/// ```sh
+/// cargo run -- --headless --frames 300 --record frames --y4m - | ffmpeg -i - turntable.mp4
+/// ```
+///
+/// The frames are taken as screenshots (see `App::render_offscreen`), and the time
+/// advances by the same step every frame, so a recording doesn't depend on how fast the
+/// frames are rendered.
+#[derive(Debug)]
+pub struct Recorder {
+    dir: PathBuf,
+    /// The number of the next frame.
+    frame: u32,
+    y4m: Option<Y4mWriter>,
+}
+
+impl Recorder {
+    /// Creates the directory of the frames, and the Y4M file if one is given. A path of
+    /// `-` writes the Y4M stream to stdout, so it can be piped into an encoder.
+    pub fn new(dir: &Path, y4m: Option<&Path>, width: u32, height: u32, fps: u32) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        let y4m = match y4m {
+            Some(path) => {
+                let output: Box<dyn Write> = if path == Path::new("-") {
+                    Box::new(io::stdout().lock())
+                } else {
+                    Box::new(File::create(path)?)
+                };
+                Some(Y4mWriter::new(output, width, height, fps)?)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            frame: 0,
+            y4m,
+        })
+    }
+
+    /// The PNG file of the next frame.
+    pub fn next_path(&mut self) -> PathBuf {
+        let path = self.dir.join(format!("frame-{:05}.png", self.frame));
+        self.frame += 1;
+        path
+    }
+
+    /// Adds the 8-bit RGBA pixels of a frame to the Y4M stream.
+    pub fn add_frame(&mut self, rgba: &[u8]) -> Result<()> {
+        if let Some(y4m) = &mut self.y4m {
+            y4m.write_frame(rgba)?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the Y4M stream. Returns the number of frames recorded.
+    pub fn finish(mut self) -> Result<u32> {
+        if let Some(y4m) = &mut self.y4m {
+            y4m.output.flush()?;
+        }
+
+        Ok(self.frame)
+    }
+}
+
+/// Writes frames to a YUV4MPEG2 stream: a header line, followed by every frame as a
+/// `FRAME` line and the Y, U and V planes.
+///
+/// The frames are stored without chroma subsampling (4:4:4), in the BT.601 limited range
+/// that players assume for Y4M when nothing else is said.
+struct Y4mWriter {
+    output: BufWriter<Box<dyn Write>>,
+}
+
+impl Y4mWriter {
+    fn new(output: Box<dyn Write>, width: u32, height: u32, fps: u32) -> io::Result<Self> {
+        let mut output = BufWriter::new(output);
+        writeln!(
+            output,
+            "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444 XCOLORRANGE=LIMITED",
+            width, height, fps
+        )?;
+
+        Ok(Self { output })
+    }
+
+    /// Converts the gamma encoded RGB of the pixels to YCbCr. The alpha is ignored.
+    fn write_frame(&mut self, rgba: &[u8]) -> io::Result<()> {
+        let pixels = rgba.len() / 4;
+        let mut planes = vec![0; pixels * 3];
+        let (y, uv) = planes.split_at_mut(pixels);
+        let (u, v) = uv.split_at_mut(pixels);
+
+        for (i, p) in rgba.chunks_exact(4).enumerate() {
+            let [r, g, b] = [p[0], p[1], p[2]].map(|c| c as f32 / 255.0);
+            y[i] = (16.0 + 65.481 * r + 128.553 * g + 24.966 * b).round() as u8;
+            u[i] = (128.0 - 37.797 * r - 74.203 * g + 112.0 * b).round() as u8;
+            v[i] = (128.0 + 112.0 * r - 93.786 * g - 18.214 * b).round() as u8;
+        }
+
+        self.output.write_all(b"FRAME\n")?;
+        self.output.write_all(&planes)
+    }
+}
+
+impl fmt::Debug for Y4mWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Y4mWriter").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    /// Collects what is written, so it can be looked at while the writer still owns it.
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn y4m_streams_start_with_the_header() {
+        let output = Output::default();
+        let mut y4m = Y4mWriter::new(Box::new(output.clone()), 640, 480, 30).unwrap();
+        y4m.output.flush().unwrap();
+
+        assert_eq!(
+            output.0.borrow().as_slice(),
+            b"YUV4MPEG2 W640 H480 F30:1 Ip A1:1 C444 XCOLORRANGE=LIMITED\n"
+        );
+    }
+
+    #[test]
+    fn y4m_frames_are_limited_range_bt601() {
+        let output = Output::default();
+        let mut y4m = Y4mWriter::new(Box::new(output.clone()), 5, 1, 30).unwrap();
+        y4m.output.flush().unwrap();
+        output.0.borrow_mut().clear();
+
+        // Black, white, red, green and blue, with an alpha that is ignored.
+        #[rustfmt::skip]
+        let rgba = [
+            0, 0, 0, 255,
+            255, 255, 255, 0,
+            255, 0, 0, 255,
+            0, 255, 0, 255,
+            0, 0, 255, 255,
+        ];
+        y4m.write_frame(&rgba).unwrap();
+        y4m.output.flush().unwrap();
+
+        let output = output.0.borrow();
+        let (frame, planes) = output.split_at(6);
+        assert_eq!(frame, b"FRAME\n");
+        assert_eq!(planes[0..5], [16, 235, 81, 145, 41]);
+        assert_eq!(planes[5..10], [128, 128, 90, 54, 240]);
+        assert_eq!(planes[10..15], [128, 128, 240, 34, 110]);
+        assert_eq!(planes.len(), 15);
+    }
+}
@@ -0,0 +1,717 @@
+use std::ffi::c_void;
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use log::*;
+use vulkanalia::prelude::v1_3::*;
+
+/// The size of the memory blocks that resources are sub-allocated from. Heaps smaller
+/// than 1 GiB (e.g. the host visible device local heap of many GPUs) get smaller blocks,
+/// so a single block doesn't take up a large part of the heap.
+const BLOCK_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+const SMALL_HEAP_SIZE: vk::DeviceSize = 1024 * 1024 * 1024;
+
+/// How a resource lays out its memory. Linear resources (buffers and images with linear
+/// tiling) and non-linear ones (images with optimal tiling) that are closer to each other
+/// than the `bufferImageGranularity` of the device may alias, so they are kept apart.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResourceKind {
+    Linear,
+    Optimal,
+}
+
+/// Memory bound to a buffer or an image: a range of a memory block, or a dedicated
+/// allocation of its own. Allocations are freed with `Allocator::free`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Allocation {
+    pub memory: vk::DeviceMemory,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+    /// Index into `Allocator::blocks`, `None` for dedicated allocations.
+    block: Option<usize>,
+}
+
+/// A range of a memory block, which is free when it has no kind.
+#[derive(Copy, Clone, Debug)]
+struct Chunk {
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    kind: Option<ResourceKind>,
+}
+
+impl Chunk {
+    fn end(&self) -> vk::DeviceSize {
+        self.offset + self.size
+    }
+}
+
+/// A single `allocate_memory` that resources are sub-allocated from. The chunks cover
+/// the whole block in order, and no two free chunks are next to each other.
+#[derive(Clone, Debug)]
+struct MemoryBlock {
+    memory: vk::DeviceMemory,
+    memory_type: u32,
+    size: vk::DeviceSize,
+    chunks: Vec<Chunk>,
+    /// Host visible memory stays mapped for as long as the block lives.
+    mapped: *mut u8,
+}
+
+#[derive(Clone, Debug)]
+struct DedicatedAllocation {
+    memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
+    mapped: *mut u8,
+}
+
+/// Numbers about the memory the allocator manages.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AllocatorStats {
+    /// The memory blocks and how much memory they take up.
+    pub block_count: usize,
+    pub block_bytes: vk::DeviceSize,
+    /// The resources sub-allocated from the blocks and how much of the blocks they use.
+    pub allocation_count: usize,
+    pub allocated_bytes: vk::DeviceSize,
+    /// The resources with memory of their own.
+    pub dedicated_count: usize,
+    pub dedicated_bytes: vk::DeviceSize,
+}
+
+impl AllocatorStats {
+    /// The number of `allocate_memory` calls in use, which is limited by the device's
+    /// `maxMemoryAllocationCount` (only 4096 on many devices).
+    pub fn device_allocation_count(&self) -> usize {
+        self.block_count + self.dedicated_count
+    }
+}
+
+impl fmt::Display for AllocatorStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MIB: f64 = 1024.0 * 1024.0;
+        write!(
+            f,
+            "{} allocations using {:.1} of {:.1} MiB in {} blocks, {} dedicated allocations using {:.1} MiB",
+            self.allocation_count,
+            self.allocated_bytes as f64 / MIB,
+            self.block_bytes as f64 / MIB,
+            self.block_count,
+            self.dedicated_count,
+            self.dedicated_bytes as f64 / MIB,
+        )
+    }
+}
+
+/// Sub-allocates the memory of buffers and images from large blocks.
+///
+/// Every `allocate_memory` call is expensive and devices only allow a few thousand
+/// allocations at a time, so instead of allocating memory for every resource, the
+/// allocator allocates a block per memory type and places resources next to each
+/// other in it (first fit). Resources that are too large to share a block, or that
+/// the driver would rather give memory of their own, get a dedicated allocation.
+#[derive(Clone, Debug, Default)]
+pub struct Allocator {
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    buffer_image_granularity: vk::DeviceSize,
+    blocks: Vec<MemoryBlock>,
+    dedicated: Vec<DedicatedAllocation>,
+}
+
+impl Allocator {
+    pub unsafe fn new(instance: &Instance, physical_device: vk::PhysicalDevice) -> Self {
+        let properties = instance.get_physical_device_properties(physical_device);
+
+        Self {
+            memory_properties: instance.get_physical_device_memory_properties(physical_device),
+            buffer_image_granularity: properties.limits.buffer_image_granularity.max(1),
+            blocks: Vec::new(),
+            dedicated: Vec::new(),
+        }
+    }
+
+    /// Allocates memory with the given properties for a buffer and binds it.
+    pub unsafe fn allocate_buffer_memory(
+        &mut self,
+        device: &Device,
+        buffer: vk::Buffer,
+        properties: vk::MemoryPropertyFlags,
+    ) -> Result<Allocation> {
+        let info = vk::BufferMemoryRequirementsInfo2::builder().buffer(buffer);
+        let mut dedicated_requirements = vk::MemoryDedicatedRequirements::builder();
+        let mut requirements =
+            vk::MemoryRequirements2::builder().push_next(&mut dedicated_requirements);
+        device.get_buffer_memory_requirements2(&info, &mut requirements);
+        let requirements = requirements.memory_requirements;
+
+        let dedicated = dedicated_requirements.prefers_dedicated_allocation == vk::TRUE;
+        let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::builder().buffer(buffer);
+        let allocation = self.allocate(
+            device,
+            requirements,
+            properties,
+            ResourceKind::Linear,
+            dedicated,
+            &mut dedicated_info,
+        )?;
+
+        device.bind_buffer_memory(buffer, allocation.memory, allocation.offset)?;
+
+        Ok(allocation)
+    }
+
+    /// Allocates memory with the given properties for an image and binds it.
+    pub unsafe fn allocate_image_memory(
+        &mut self,
+        device: &Device,
+        image: vk::Image,
+        tiling: vk::ImageTiling,
+        properties: vk::MemoryPropertyFlags,
+    ) -> Result<Allocation> {
+        let info = vk::ImageMemoryRequirementsInfo2::builder().image(image);
+        let mut dedicated_requirements = vk::MemoryDedicatedRequirements::builder();
+        let mut requirements =
+            vk::MemoryRequirements2::builder().push_next(&mut dedicated_requirements);
+        device.get_image_memory_requirements2(&info, &mut requirements);
+        let requirements = requirements.memory_requirements;
+
+        let kind = if tiling == vk::ImageTiling::LINEAR {
+            ResourceKind::Linear
+        } else {
+            ResourceKind::Optimal
+        };
+
+        let dedicated = dedicated_requirements.prefers_dedicated_allocation == vk::TRUE;
+        let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::builder().image(image);
+        let allocation = self.allocate(
+            device,
+            requirements,
+            properties,
+            kind,
+            dedicated,
+            &mut dedicated_info,
+        )?;
+
+        device.bind_image_memory(image, allocation.memory, allocation.offset)?;
+
+        Ok(allocation)
+    }
+
+    /// Allocates memory with the given properties without binding it, for resources that
+    /// share it (e.g. the aliased images of the render graph). The requirements must
+    /// cover every one of them.
+    pub unsafe fn allocate_memory(
+        &mut self,
+        device: &Device,
+        requirements: vk::MemoryRequirements,
+        properties: vk::MemoryPropertyFlags,
+        kind: ResourceKind,
+    ) -> Result<Allocation> {
+        // Without a resource, a dedicated allocation is just one of its own.
+        let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::builder();
+        self.allocate(
+            device,
+            requirements,
+            properties,
+            kind,
+            false,
+            &mut dedicated_info,
+        )
+    }
+
+    /// Finds room for the requirements in a block of a suitable memory type, allocating
+    /// a new block when none has room. Large resources get a dedicated allocation instead.
+    unsafe fn allocate(
+        &mut self,
+        device: &Device,
+        requirements: vk::MemoryRequirements,
+        properties: vk::MemoryPropertyFlags,
+        kind: ResourceKind,
+        dedicated: bool,
+        dedicated_info: &mut vk::MemoryDedicatedAllocateInfoBuilder,
+    ) -> Result<Allocation> {
+        let memory_type = self.memory_type_index(properties, requirements)?;
+        let block_size = self.block_size(memory_type);
+
+        if dedicated || requirements.size > block_size / 2 {
+            return self.allocate_dedicated(device, requirements, memory_type, dedicated_info);
+        }
+
+        for (index, block) in self.blocks.iter_mut().enumerate() {
+            if block.memory_type != memory_type {
+                continue;
+            }
+
+            if let Some(offset) = block.allocate(requirements, kind, self.buffer_image_granularity)
+            {
+                return Ok(Allocation {
+                    memory: block.memory,
+                    offset,
+                    size: requirements.size,
+                    block: Some(index),
+                });
+            }
+        }
+
+        let mut block = self.allocate_block(device, memory_type, block_size)?;
+        let offset = block
+            .allocate(requirements, kind, self.buffer_image_granularity)
+            .ok_or_else(|| anyhow!("Failed to sub-allocate from a new memory block."))?;
+
+        let allocation = Allocation {
+            memory: block.memory,
+            offset,
+            size: requirements.size,
+            block: Some(self.blocks.len()),
+        };
+        self.blocks.push(block);
+
+        Ok(allocation)
+    }
+
+    unsafe fn allocate_dedicated(
+        &mut self,
+        device: &Device,
+        requirements: vk::MemoryRequirements,
+        memory_type: u32,
+        dedicated_info: &mut vk::MemoryDedicatedAllocateInfoBuilder,
+    ) -> Result<Allocation> {
+        // Tells the driver which resource the memory is for, which lets it optimize it.
+        let info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type)
+            .push_next(dedicated_info);
+
+        let memory = device.allocate_memory(&info, None)?;
+        let mapped = self.map(device, memory, memory_type)?;
+        self.dedicated.push(DedicatedAllocation {
+            memory,
+            size: requirements.size,
+            mapped,
+        });
+
+        Ok(Allocation {
+            memory,
+            offset: 0,
+            size: requirements.size,
+            block: None,
+        })
+    }
+
+    unsafe fn allocate_block(
+        &self,
+        device: &Device,
+        memory_type: u32,
+        size: vk::DeviceSize,
+    ) -> Result<MemoryBlock> {
+        let info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(size)
+            .memory_type_index(memory_type);
+
+        let memory = device.allocate_memory(&info, None)?;
+        debug!(
+            "Allocated a {} byte memory block of memory type {}.",
+            size, memory_type
+        );
+
+        Ok(MemoryBlock {
+            memory,
+            memory_type,
+            size,
+            chunks: vec![Chunk {
+                offset: 0,
+                size,
+                kind: None,
+            }],
+            mapped: self.map(device, memory, memory_type)?,
+        })
+    }
+
+    /// Maps host visible memory for as long as it lives, as memory can only be mapped
+    /// once at a time and resources sharing a block may be written to independently.
+    unsafe fn map(
+        &self,
+        device: &Device,
+        memory: vk::DeviceMemory,
+        memory_type: u32,
+    ) -> Result<*mut u8> {
+        let flags = self.memory_properties.memory_types[memory_type as usize].property_flags;
+        if !flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            return Ok(std::ptr::null_mut());
+        }
+
+        let mapped = device.map_memory(
+            memory,
+            0,
+            vk::WHOLE_SIZE as u64,
+            vk::MemoryMapFlags::empty(),
+        )?;
+        Ok(mapped.cast())
+    }
+
+    /// Returns a pointer to the memory of a host visible allocation, which the CPU can
+    /// write to (or read from) directly.
+    pub fn mapped(&self, allocation: &Allocation) -> Result<*mut c_void> {
+        let mapped = match allocation.block {
+            Some(block) => self.blocks[block].mapped,
+            None => self
+                .dedicated
+                .iter()
+                .find(|d| d.memory == allocation.memory)
+                .map_or(std::ptr::null_mut(), |d| d.mapped),
+        };
+
+        if mapped.is_null() {
+            return Err(anyhow!("Memory that isn't host visible can't be mapped."));
+        }
+
+        Ok(unsafe { mapped.add(allocation.offset as usize) }.cast())
+    }
+
+    /// Frees the memory of an allocation, after the resource using it was destroyed.
+    /// Blocks stay allocated when they become empty, so they can be reused right away.
+    pub unsafe fn free(&mut self, device: &Device, allocation: &Allocation) {
+        match allocation.block {
+            Some(block) => self.blocks[block].free(allocation.offset),
+            None => {
+                if let Some(index) = self
+                    .dedicated
+                    .iter()
+                    .position(|d| d.memory == allocation.memory)
+                {
+                    self.dedicated.swap_remove(index);
+                    device.free_memory(allocation.memory, None);
+                }
+            }
+        }
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        let allocations = self
+            .blocks
+            .iter()
+            .flat_map(|b| &b.chunks)
+            .filter(|c| c.kind.is_some());
+
+        AllocatorStats {
+            block_count: self.blocks.len(),
+            block_bytes: self.blocks.iter().map(|b| b.size).sum(),
+            allocation_count: allocations.clone().count(),
+            allocated_bytes: allocations.map(|c| c.size).sum(),
+            dedicated_count: self.dedicated.len(),
+            dedicated_bytes: self.dedicated.iter().map(|d| d.size).sum(),
+        }
+    }
+
+    /// Frees all the memory, after every resource was destroyed.
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        let stats = self.stats();
+        if stats.allocation_count > 0 || stats.dedicated_count > 0 {
+            warn!(
+                "Destroying the allocator while memory is in use: {}.",
+                stats
+            );
+        }
+
+        for block in self.blocks.drain(..) {
+            device.free_memory(block.memory, None);
+        }
+        for dedicated in self.dedicated.drain(..) {
+            device.free_memory(dedicated.memory, None);
+        }
+    }
+
+    /// Returns a memory type index for memory that satisfies the given requirements
+    /// and has the given properties.
+    fn memory_type_index(
+        &self,
+        properties: vk::MemoryPropertyFlags,
+        requirements: vk::MemoryRequirements,
+    ) -> Result<u32> {
+        (0..self.memory_properties.memory_type_count)
+            .find(|i| {
+                let suitable = (requirements.memory_type_bits & (1 << i)) != 0;
+                let memory_type = self.memory_properties.memory_types[*i as usize];
+
+                suitable && memory_type.property_flags.contains(properties)
+            })
+            .ok_or_else(|| anyhow!("Failed to find suitable memory type."))
+    }
+
+    fn block_size(&self, memory_type: u32) -> vk::DeviceSize {
+        let heap = self.memory_properties.memory_types[memory_type as usize].heap_index;
+        let heap_size = self.memory_properties.memory_heaps[heap as usize].size;
+
+        if heap_size < SMALL_HEAP_SIZE {
+            heap_size / 8
+        } else {
+            BLOCK_SIZE
+        }
+    }
+}
+
+impl MemoryBlock {
+    /// Finds the first free chunk with room for the requirements and returns the offset
+    /// of the new allocation, or `None` when the block is too full.
+    fn allocate(
+        &mut self,
+        requirements: vk::MemoryRequirements,
+        kind: ResourceKind,
+        granularity: vk::DeviceSize,
+    ) -> Option<vk::DeviceSize> {
+        // Whether two offsets are on the same "page" of the buffer-image granularity.
+        let same_page = |a: vk::DeviceSize, b: vk::DeviceSize| a / granularity == b / granularity;
+
+        for i in 0..self.chunks.len() {
+            let chunk = self.chunks[i];
+            if chunk.kind.is_some() {
+                continue;
+            }
+
+            let mut offset = align_up(chunk.offset, requirements.alignment);
+
+            // A resource of the other kind before it must not share a page with it...
+            if let Some(previous) = i.checked_sub(1).map(|p| self.chunks[p]) {
+                if previous.kind != Some(kind) && same_page(previous.end() - 1, offset) {
+                    offset = align_up(offset, granularity);
+                }
+            }
+
+            let end = offset + requirements.size;
+            if end > chunk.end() {
+                continue;
+            }
+
+            // ...and neither must one after it.
+            if let Some(next) = self.chunks.get(i + 1) {
+                if next.kind != Some(kind) && same_page(end - 1, next.offset) {
+                    continue;
+                }
+            }
+
+            // The chunk is split into the padding before the allocation, the allocation
+            // and the rest after it. The padding and the rest stay free.
+            let mut split = Vec::with_capacity(3);
+            if offset > chunk.offset {
+                split.push(Chunk {
+                    offset: chunk.offset,
+                    size: offset - chunk.offset,
+                    kind: None,
+                });
+            }
+            split.push(Chunk {
+                offset,
+                size: requirements.size,
+                kind: Some(kind),
+            });
+            if end < chunk.end() {
+                split.push(Chunk {
+                    offset: end,
+                    size: chunk.end() - end,
+                    kind: None,
+                });
+            }
+            self.chunks.splice(i..=i, split);
+
+            return Some(offset);
+        }
+
+        None
+    }
+
+    /// Marks the allocation at the offset as free and merges it with free neighbors.
+    fn free(&mut self, offset: vk::DeviceSize) {
+        let Some(mut i) = self
+            .chunks
+            .iter()
+            .position(|c| c.offset == offset && c.kind.is_some())
+        else {
+            return;
+        };
+
+        self.chunks[i].kind = None;
+
+        if self.chunks.get(i + 1).is_some_and(|c| c.kind.is_none()) {
+            self.chunks[i].size += self.chunks[i + 1].size;
+            self.chunks.remove(i + 1);
+        }
+
+        if i > 0 && self.chunks[i - 1].kind.is_none() {
+            self.chunks[i - 1].size += self.chunks[i].size;
+            self.chunks.remove(i);
+            i -= 1;
+        }
+
+        debug_assert!(self.chunks[i].kind.is_none());
+    }
+}
+
+fn align_up(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    value.div_ceil(alignment.max(1)) * alignment.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(size: vk::DeviceSize) -> MemoryBlock {
+        MemoryBlock {
+            memory: vk::DeviceMemory::null(),
+            memory_type: 0,
+            size,
+            chunks: vec![Chunk {
+                offset: 0,
+                size,
+                kind: None,
+            }],
+            mapped: std::ptr::null_mut(),
+        }
+    }
+
+    fn requirements(size: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::MemoryRequirements {
+        vk::MemoryRequirements {
+            size,
+            alignment,
+            memory_type_bits: u32::MAX,
+        }
+    }
+
+    /// Checks that the chunks cover the whole block in order, and that no two free chunks
+    /// are next to each other.
+    fn check_chunks(block: &MemoryBlock) {
+        let mut offset = 0;
+        for (i, chunk) in block.chunks.iter().enumerate() {
+            assert_eq!(chunk.offset, offset, "gap before chunk {}", i);
+            assert!(chunk.size > 0, "empty chunk {}", i);
+            if i > 0 {
+                let previous = block.chunks[i - 1];
+                assert!(
+                    previous.kind.is_some() || chunk.kind.is_some(),
+                    "free chunks {} and {}",
+                    i - 1,
+                    i
+                );
+            }
+            offset = chunk.end();
+        }
+        assert_eq!(offset, block.size);
+    }
+
+    #[test]
+    fn align_up_rounds_to_the_next_multiple() {
+        assert_eq!(align_up(0, 16), 0);
+        assert_eq!(align_up(1, 16), 16);
+        assert_eq!(align_up(16, 16), 16);
+        assert_eq!(align_up(17, 16), 32);
+        // An alignment of 0 means the resource has none.
+        assert_eq!(align_up(17, 0), 17);
+    }
+
+    #[test]
+    fn allocations_are_aligned_and_fill_gaps_first() {
+        let mut block = block(1024);
+
+        assert_eq!(
+            block.allocate(requirements(10, 1), ResourceKind::Linear, 1),
+            Some(0)
+        );
+        assert_eq!(
+            block.allocate(requirements(10, 256), ResourceKind::Linear, 1),
+            Some(256)
+        );
+        // The padding before the aligned allocation is the first chunk with room.
+        assert_eq!(
+            block.allocate(requirements(100, 4), ResourceKind::Linear, 1),
+            Some(12)
+        );
+        check_chunks(&block);
+
+        assert_eq!(
+            block.allocate(requirements(1024, 1), ResourceKind::Linear, 1),
+            None
+        );
+    }
+
+    #[test]
+    fn different_kinds_do_not_share_a_granularity_page() {
+        let mut block = block(4096);
+
+        assert_eq!(
+            block.allocate(requirements(100, 1), ResourceKind::Linear, 256),
+            Some(0)
+        );
+        // An image after a buffer starts on the next page...
+        assert_eq!(
+            block.allocate(requirements(100, 1), ResourceKind::Optimal, 256),
+            Some(256)
+        );
+        // ...while another buffer can share the buffer's page.
+        assert_eq!(
+            block.allocate(requirements(50, 1), ResourceKind::Linear, 256),
+            Some(100)
+        );
+        // A buffer that doesn't fit before the image starts on the page after it.
+        assert_eq!(
+            block.allocate(requirements(120, 1), ResourceKind::Linear, 256),
+            Some(512)
+        );
+        check_chunks(&block);
+    }
+
+    #[test]
+    fn different_kinds_do_not_share_a_page_with_the_next_chunk() {
+        let mut block = block(4096);
+
+        assert_eq!(
+            block.allocate(requirements(100, 1), ResourceKind::Linear, 256),
+            Some(0)
+        );
+        assert_eq!(
+            block.allocate(requirements(300, 1), ResourceKind::Linear, 256),
+            Some(100)
+        );
+        block.free(0);
+
+        // The freed range is on the page of the buffer after it, so the image can't use it.
+        assert_eq!(
+            block.allocate(requirements(50, 1), ResourceKind::Optimal, 256),
+            Some(512)
+        );
+        // A buffer can.
+        assert_eq!(
+            block.allocate(requirements(50, 1), ResourceKind::Linear, 256),
+            Some(0)
+        );
+        check_chunks(&block);
+    }
+
+    #[test]
+    fn freeing_merges_free_neighbors() {
+        let mut block = block(300);
+        for offset in [0, 100, 200] {
+            let allocated = block.allocate(requirements(100, 1), ResourceKind::Linear, 1);
+            assert_eq!(allocated, Some(offset));
+        }
+
+        block.free(100);
+        check_chunks(&block);
+        assert_eq!(block.chunks.len(), 3);
+
+        block.free(0);
+        check_chunks(&block);
+        assert_eq!(block.chunks.len(), 2);
+        assert_eq!(block.chunks[0].size, 200);
+
+        block.free(200);
+        check_chunks(&block);
+        assert_eq!(block.chunks.len(), 1);
+
+        // Freeing an offset that isn't allocated changes nothing.
+        block.free(200);
+        check_chunks(&block);
+
+        assert_eq!(
+            block.allocate(requirements(300, 1), ResourceKind::Linear, 1),
+            Some(0)
+        );
+    }
+}
@@ -2,15 +2,19 @@ use vulkanalia::prelude::v1_3::*;
 use anyhow::*;
 
 use crate::{app::AppData, vulkan::commands::{begin_single_time_commands, end_single_time_commands}};
+use crate::vulkan::resources::Buffer;
 
+/// Creates a buffer with memory bound to it. The name shows up in validation messages
+/// and frame debuggers (see `DebugUtils`).
 pub unsafe fn create_buffer(
     instance: &Instance,
     device: &Device,
     data: &mut AppData,
+    name: &str,
     size: vk::DeviceSize,
     usage: vk::BufferUsageFlags,
     properties: vk::MemoryPropertyFlags,
-) -> Result<(vk::Buffer, vk::DeviceMemory)> {
+) -> Result<Buffer> {
     let buffer_info = vk::BufferCreateInfo::builder()
         .size(size)
         .usage(usage)
@@ -22,43 +26,18 @@ pub unsafe fn create_buffer(
     // This creates a buffer handle, but no memory is allocated for it yet.
     let buffer = device.create_buffer(&buffer_info, None)?;
 
-    // These are the requirements for the buffer that we need to find suitable memory for.
-    // The memory must be big enough and satisfy the right requirements (needs to have
-    // the right memory type bits set).
-    let requirements = device.get_buffer_memory_requirements(buffer);
-
-    let memory_type_index = get_memory_type_index(instance, data, properties, requirements)?;
-    
-    let memory_info = vk::MemoryAllocateInfo::builder()
-        .allocation_size(requirements.size)
-        .memory_type_index(memory_type_index);
-
-    let buffer_memory = device.allocate_memory(&memory_info, None)?;
-
-    // If the offset happens to be non-zero, it must be divisible by requirements.alignment.
-    device.bind_buffer_memory(buffer, buffer_memory, 0)?;
-
-    Ok((buffer, buffer_memory))
-}
-
-/// Returns a memory type index for memory that satisfies the given requirements
-/// and has the given properties.
-pub unsafe fn get_memory_type_index(
-    instance: &Instance,
-    data: &mut AppData,
-    properties: vk::MemoryPropertyFlags,
-    requirements: vk::MemoryRequirements,
-) -> Result<u32> {
-    let memory: vk::PhysicalDeviceMemoryProperties = instance.get_physical_device_memory_properties(data.physical_device);
-
-    (0..memory.memory_type_count)
-        .find(|i| {
-            let suitable = (requirements.memory_type_bits & (1 << i)) != 0;
-            let memory_type: vk::MemoryType = memory.memory_types[*i as usize];
-
-            suitable && memory_type.property_flags.contains(properties)
-        })
-        .ok_or_else(|| anyhow!("Failed to find suitable memory type."))
+    // The buffer needs memory that is big enough, has the right alignment and is of a
+    // memory type the buffer supports. The allocator finds room for it in one of its
+    // memory blocks and binds it to the buffer.
+    let allocation = data
+        .allocator
+        .allocate_buffer_memory(device, buffer, properties)
+        .inspect_err(|_| device.destroy_buffer(buffer, None))?;
+    data.debug_utils.set_name(device, buffer, name);
+
+    // From here on the buffer destroys itself when it's dropped, even if an error
+    // is returned before the caller is done with it.
+    Ok(Buffer::new(buffer, allocation, &data.deletion_queue))
 }
 
 /// Copies data from one buffer to another.
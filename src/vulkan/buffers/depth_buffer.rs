@@ -1,44 +1,6 @@
 use vulkanalia::prelude::v1_3::*;
 use anyhow::*;
-use crate::{app::AppData, vulkan::image::{create_image, create_image_view}};
-
-pub unsafe fn create_depth_objects(
-    instance: &Instance,
-    device: &Device,
-    data: &mut AppData,
-) -> Result<()> {
-
-    let format = get_depth_format(instance, data)?;
-
-    // The depth buffer is an image like the ones in the swapchain and texture.
-    let (depth_image, depth_image_memory) = create_image(
-        instance, 
-        device, 
-        data, 
-        data.swapchain_extent.width, 
-        data.swapchain_extent.height,
-        1,
-        data.msaa_samples,
-        format,
-        vk::ImageTiling::OPTIMAL, 
-        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT, 
-        vk::MemoryPropertyFlags::DEVICE_LOCAL
-    )?;
-    
-    data.depth_image = depth_image;
-    data.depth_image_memory = depth_image_memory;
-
-    data.depth_image_view = create_image_view(
-        device, 
-        depth_image, 
-        format,
-        vk::ImageAspectFlags::DEPTH,
-        1,
-    )?;
-
-    Ok(())
-}
-
+use crate::app::AppData;
 
 pub unsafe fn get_depth_format(
     instance: &Instance,
@@ -57,7 +19,7 @@ pub unsafe fn get_depth_format(
         vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
 }
 
-unsafe fn get_supported_format(
+pub unsafe fn get_supported_format(
     instance: &Instance,
     data: &AppData,
     candidates: &[vk::Format],
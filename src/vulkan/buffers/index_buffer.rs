@@ -4,6 +4,7 @@ use vulkanalia::prelude::v1_3::*;
 use anyhow::*;
 
 use crate::app::AppData;
+use crate::vulkan::scene::Mesh;
 
 use super::buffer::{copy_buffer, create_buffer};
 
@@ -12,37 +13,30 @@ pub unsafe fn create_index_buffer(
     instance: &Instance,
     device: &Device,
     data: &mut AppData,
+    mesh: &mut Mesh,
 ) -> Result<()> {
 
-    let size = (size_of::<u32>() * data.indices.len()) as u64;
+    let size = (size_of::<u32>() * mesh.indices.len()) as u64;
 
-    let (staging_buffer, staging_buffer_memory) = create_buffer(
-        instance, device, data, size, 
+    let staging_buffer = create_buffer(
+        instance, device, data, &format!("{} index staging buffer", mesh.name), size,
         vk::BufferUsageFlags::TRANSFER_SRC,
-        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_COHERENT
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE
     )?;
 
-    let memory = device.map_memory(
-        staging_buffer_memory, 0, size, vk::MemoryMapFlags::empty()
-    )?;
-
-    memcpy(data.indices.as_ptr(), memory.cast(), data.indices.len());
+    let memory = data.allocator.mapped(staging_buffer.allocation())?;
 
-    device.unmap_memory(staging_buffer_memory);
+    memcpy(mesh.indices.as_ptr(), memory.cast(), mesh.indices.len());
 
-    let (index_buffer, index_buffer_memory) = create_buffer(
-        instance, device, data, size, 
+    let index_buffer = create_buffer(
+        instance, device, data, &format!("{} index buffer", mesh.name), size,
         vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
         vk::MemoryPropertyFlags::DEVICE_LOCAL
     )?;
 
-    data.index_buffer = index_buffer;
-    data.index_buffer_memory = index_buffer_memory;
-
-    copy_buffer(device, data, staging_buffer, data.index_buffer, size)?;
+    copy_buffer(device, data, staging_buffer.handle(), index_buffer.handle(), size)?;
 
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(staging_buffer_memory, None);
+    mesh.index_buffer = index_buffer;
 
     Ok(())
 }
\ No newline at end of file
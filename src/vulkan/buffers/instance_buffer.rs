@@ -0,0 +1,130 @@
+use std::ptr::copy_nonoverlapping as memcpy;
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_3::*;
+
+use crate::app::AppData;
+use crate::vulkan::vertex::Vec4;
+
+use super::buffer::create_buffer;
+use super::uniform_buffer::Mat4;
+
+/// The most textures instances can choose from, the first textures of the loaded
+/// materials. A device only has to support 16 samplers per shader stage, 6 of which
+/// are taken by the shadow map and the material textures.
+pub const MAX_INSTANCE_TEXTURES: usize = 8;
+
+/// The data of a single instance, which the vertex shader reads from the instance
+/// buffer (binding 1). Unlike the vertices, it advances once per instance instead of
+/// once per vertex, so every instance of a draw gets its own entry.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct InstanceData {
+    /// The model matrix, which transforms the mesh from model space into world space.
+    pub transform: Mat4,
+    /// Multiplied with the base color of the instance.
+    pub tint: Vec4,
+    /// Replaces the base color texture of the materials with the texture at this index
+    /// of `AppData::textures`, unless it's negative.
+    pub texture_index: i32,
+}
+
+impl InstanceData {
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(1)
+            .stride(size_of::<InstanceData>() as u32)
+            .input_rate(vk::VertexInputRate::INSTANCE)
+            .build()
+    }
+
+    /// A vertex attribute holds at most 4 components, so the matrix takes up one
+    /// location per column (4 to 7), followed by the tint and the texture index.
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 6] {
+        let column = |i: u32| {
+            vk::VertexInputAttributeDescription::builder()
+                .binding(1)
+                .location(4 + i)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(i * size_of::<Vec4>() as u32)
+                .build()
+        };
+
+        let tint = vk::VertexInputAttributeDescription::builder()
+            .binding(1)
+            .location(8)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset(size_of::<Mat4>() as u32)
+            .build();
+
+        let texture_index = vk::VertexInputAttributeDescription::builder()
+            .binding(1)
+            .location(9)
+            .format(vk::Format::R32_SINT)
+            .offset((size_of::<Mat4>() + size_of::<Vec4>()) as u32)
+            .build();
+
+        [
+            column(0),
+            column(1),
+            column(2),
+            column(3),
+            tint,
+            texture_index,
+        ]
+    }
+}
+
+/// Creates an instance buffer for every frame in flight, with room for at least the
+/// given number of instances. The instances are written every frame, so like the
+/// uniform buffers they live in host visible memory and aren't shared between frames.
+///
+/// Any previous instance buffers are dropped, which queues them for destruction.
+pub unsafe fn create_instance_buffers(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    capacity: usize,
+) -> Result<()> {
+    // Grows in powers of two, so adding objects one by one rarely recreates the buffers.
+    let capacity = capacity.max(1).next_power_of_two();
+    let size = (size_of::<InstanceData>() * capacity) as u64;
+
+    for i in 0..data.frames.len() {
+        let buffer = create_buffer(
+            instance,
+            device,
+            data,
+            &format!("frame {} instance buffer", i),
+            size,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        data.frames[i].instance_buffer = buffer;
+    }
+
+    data.instance_capacity = capacity;
+
+    Ok(())
+}
+
+/// Writes the instances into the instance buffer of a frame in flight.
+pub unsafe fn update_instance_buffer(
+    device: &Device,
+    data: &AppData,
+    frame: usize,
+    instances: &[InstanceData],
+) -> Result<()> {
+    if instances.is_empty() {
+        return Ok(());
+    }
+
+    let memory = data
+        .allocator
+        .mapped(data.frames[frame].instance_buffer.allocation())?;
+
+    memcpy(instances.as_ptr(), memory.cast(), instances.len());
+
+    Ok(())
+}
@@ -2,4 +2,5 @@ pub mod buffer;
 pub mod vertex_buffer;
 pub mod index_buffer;
 pub mod uniform_buffer;
-pub mod depth_buffer;
\ No newline at end of file
+pub mod depth_buffer;
+pub mod instance_buffer;
@@ -1,18 +1,91 @@
 use vulkanalia::prelude::v1_3::*;
 use anyhow::Result;
 
-use crate::app::AppData;
+use crate::app::{AppData, MAX_FRAMES_IN_FLIGHT};
+
+use crate::vulkan::vertex::Vec4;
 
 use super::buffer::create_buffer;
+use super::instance_buffer::MAX_INSTANCE_TEXTURES;
 
 pub type Mat4 = cgmath::Matrix4<f32>;
 
+/// cgmath was originally designed for OpenGL, where the Y coordinate of the clip coordinates
+/// is inverted and the depth range is [-1, 1] instead of [0, 1]. Multiplying a cgmath
+/// projection matrix with this matrix is the easiest way to compensate it.
+///
+/// Mat4::new constructs the matrix in a column-major order, so the matrix look like
+/// [1,  0, 0  , 0  ]
+/// [0, -1, 0  , 0  ]
+/// [0,  0, 0.5, 0.5]
+/// [0,  0, 0  , 1  ]
+#[rustfmt::skip]
+pub const CORRECTION: Mat4 = Mat4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, -1.0, 0.0, 0.0,
+    0.0, 0.0, 1.0 / 2.0, 0.0,
+    0.0, 0.0, 1.0 / 2.0, 1.0,
+);
+
+/// The most point lights the fragment shader handles. Uniform blocks can't hold
+/// arrays of a dynamic size, so the array has a fixed size and only the first
+/// `point_light_count` entries are used.
+pub const MAX_POINT_LIGHTS: usize = 4;
+
+/// The matrices shared by every object drawn in a frame. The model matrix differs
+/// between instances, so it is part of `InstanceData` in the instance buffer instead
+/// (binding 1, locations 4 to 7).
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct UniformBufferObject {
-    pub model: Mat4,
     pub view: Mat4,
     pub proj: Mat4,
+    /// The view and projection of the directional light, which transforms world space
+    /// positions into the clip space of the shadow map.
+    pub light_space: Mat4,
+}
+
+/// A light infinitely far away, like the sun, that lights everything from the same direction.
+///
+/// Only vec4s are used in the light structs. In the std140 uniform layout that WGSL
+/// uses, a vec3 is aligned like a vec4, so using vec4s makes the Rust and WGSL layouts
+/// match without padding.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct DirectionalLight {
+    /// The direction the light travels in, in world space. w is unused.
+    pub direction: Vec4,
+    /// The color in xyz and the intensity in w.
+    pub color: Vec4,
+}
+
+/// A light that shines in every direction from a point and fades with the distance.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PointLight {
+    /// The position in world space in xyz and the range in w. The light fades out
+    /// completely at that distance.
+    pub position: Vec4,
+    /// The color in xyz and the intensity in w.
+    pub color: Vec4,
+}
+
+/// The lights of the scene, read by the fragment shader from set 0, binding 1.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct LightUniformBufferObject {
+    /// Needed for the specular highlights, which depend on where the surface is seen from.
+    pub camera_position: Vec4,
+    /// The ambient color in xyz and its intensity in w. Ambient light is a cheap stand-in
+    /// for light bouncing around the scene, so surfaces facing away from every light aren't black.
+    pub ambient: Vec4,
+    pub directional: DirectionalLight,
+    /// The PCF radius in x and the size of a shadow map texel in uv coordinates in y.
+    pub shadow: Vec4,
+    pub point_lights: [PointLight; MAX_POINT_LIGHTS],
+    pub point_light_count: u32,
+    // Pads the struct to the 16 byte alignment of the uniform block.
+    pub _padding: [u32; 3],
 }
 
 
@@ -22,48 +95,48 @@ pub unsafe fn create_uniform_buffers(
     data: &mut AppData
 ) -> Result<()> {
 
-    data.uniform_buffers.clear();
-    data.uniform_buffers_memory.clear();
-
-    for _ in 0..data.swapchain_images.len() {
-        let (uniform_buffer, uniform_buffer_memory) = create_buffer(
-            instance, device, data, size_of::<UniformBufferObject>() as u64, 
+    for i in 0..data.frames.len() {
+        let uniform_buffer = create_buffer(
+            instance, device, data, &format!("frame {} uniform buffer", i),
+            size_of::<UniformBufferObject>() as u64,
             vk::BufferUsageFlags::UNIFORM_BUFFER,
         vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT)?;
 
-        data.uniform_buffers.push(uniform_buffer);
-        data.uniform_buffers_memory.push(uniform_buffer_memory);
+        data.frames[i].uniform_buffer = uniform_buffer;
+
+        // The lights can change every frame as well, so they get a buffer per frame too.
+        let light_buffer = create_buffer(
+            instance,
+            device,
+            data,
+            &format!("frame {} light buffer", i),
+            size_of::<LightUniformBufferObject>() as u64,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        data.frames[i].light_buffer = light_buffer;
     }
 
     Ok(())
 }
 
 
-/// This function should probably take in a descriptor type and the stage flags
-/// for more flexibility. That's to be done when we will need descriptor set layouts
-/// other than the one for the uniform buffer.
-/// 
 /// A descriptor set layout defines the structure of descriptors visible to shaders.
+/// This is the layout of set 0, which is bound once per frame. Binding 0 holds the
+/// transformation matrices, binding 1 the lights, binding 2 the shadow map of the
+/// directional light and binding 3 the array of textures the instances can choose from.
+/// The textures are bound per material in set 1 (see material.rs).
+///
+/// The bindings are reflected from the shaders, so their types, counts and stages always
+/// match the way the shaders declare them.
 pub unsafe fn create_descriptor_set_layout(
     device: &Device,
     data: &mut AppData,
 ) -> Result<()> {
-
-    let ubo_binding = vk::DescriptorSetLayoutBinding::builder()
-        .binding(0)
-        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-        .descriptor_count(1)
-        .stage_flags(vk::ShaderStageFlags::VERTEX);
-
-    let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
-        .binding(1)
-        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-        .descriptor_count(1)
-        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
-
-    let bindings = &[ubo_binding, sampler_binding];
+    let bindings = data.shaders.set_layout_bindings(0);
     let info = vk::DescriptorSetLayoutCreateInfo::builder()
-        .bindings(bindings);
+        .bindings(&bindings);
 
     data.descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
 
@@ -79,17 +152,18 @@ pub unsafe fn create_descriptor_pool(
     let ubo_size = vk::DescriptorPoolSize::builder()
         .type_(vk::DescriptorType::UNIFORM_BUFFER)
         
-        // We want to allocate one UBO for every swapchain image.
-        .descriptor_count(data.swapchain_images.len() as u32);
+        // We want to allocate two UBOs (matrices and lights) for every frame in flight.
+        .descriptor_count(2 * MAX_FRAMES_IN_FLIGHT as u32);
 
     let sampler_size = vk::DescriptorPoolSize::builder()
         .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-        .descriptor_count(data.swapchain_images.len() as u32);
+        // And the shadow map and the instance textures.
+        .descriptor_count((1 + MAX_INSTANCE_TEXTURES) as u32 * MAX_FRAMES_IN_FLIGHT as u32);
 
     let pool_sizes = &[ubo_size, sampler_size];
     let info = vk::DescriptorPoolCreateInfo::builder()
         .pool_sizes(pool_sizes)
-        .max_sets(data.swapchain_images.len() as u32);
+        .max_sets(MAX_FRAMES_IN_FLIGHT as u32);
 
     data.descriptor_pool = device.create_descriptor_pool(&info, None)?;
 
@@ -119,25 +193,30 @@ pub unsafe fn create_descriptor_sets(
     device: &Device,
     data: &mut AppData,
 ) -> Result<()> {
-    // We use the same layout for all swapchain images.
-    let layouts = vec![data.descriptor_set_layout; data.swapchain_images.len()];
+    // We use the same layout for all frames in flight.
+    let layouts = vec![data.descriptor_set_layout; data.frames.len()];
     
     let info = vk::DescriptorSetAllocateInfo::builder()
         .descriptor_pool(data.descriptor_pool)
         .set_layouts(&layouts);
     
-    data.descriptor_sets = device.allocate_descriptor_sets(&info)?;
+    let descriptor_sets = device.allocate_descriptor_sets(&info)?;
+
+    for (i, (frame, descriptor_set)) in data.frames.iter_mut().zip(descriptor_sets).enumerate() {
+        frame.descriptor_set = descriptor_set;
+        data.debug_utils.set_name(device, descriptor_set, &format!("frame {} descriptor set", i));
+    }
 
-    for i in 0..data.swapchain_images.len() {
+    for frame in &data.frames {
         let buffer_info = vk::DescriptorBufferInfo::builder()
-            .buffer(data.uniform_buffers[i])
+            .buffer(frame.uniform_buffer.handle())
             .offset(0)
             .range(size_of::<UniformBufferObject>() as u64);
         
         let buffer_infos = &[buffer_info];
 
         let ubo_write = vk::WriteDescriptorSet::builder()
-            .dst_set(data.descriptor_sets[i])
+            .dst_set(frame.descriptor_set)
             .dst_binding(0)
 
             // Descriptors can be arrays, but we're not using one.
@@ -149,23 +228,56 @@ pub unsafe fn create_descriptor_sets(
             // texel_buffer_view -descriptors that refer to buffer views.
             .buffer_info(buffer_infos);
 
-        let image_info = vk::DescriptorImageInfo::builder()
-            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(data.texture_image_view)
-            .sampler(data.texture_sampler);
-
-        let image_infos = &[image_info];
+        let light_info = vk::DescriptorBufferInfo::builder()
+            .buffer(frame.light_buffer.handle())
+            .offset(0)
+            .range(size_of::<LightUniformBufferObject>() as u64);
 
-        let sampler_write = vk::WriteDescriptorSet::builder()
-            .dst_set(data.descriptor_sets[i])
+        let light_infos = &[light_info];
+        let light_write = vk::WriteDescriptorSet::builder()
+            .dst_set(frame.descriptor_set)
             .dst_binding(1)
             .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(light_infos);
+
+        // The render pass of the shadow map leaves it in a read-only layout for sampling.
+        let shadow_map_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+            .image_view(data.shadow_map_view.handle())
+            .sampler(data.shadow_sampler.handle());
+
+        let shadow_map_infos = &[shadow_map_info];
+        let shadow_map_write = vk::WriteDescriptorSet::builder()
+            .dst_set(frame.descriptor_set)
+            .dst_binding(2)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(shadow_map_infos);
+
+        // The textures past the loaded ones are never used, but every descriptor of
+        // the array has to be valid, so they are filled with the white texture.
+        let instance_texture_infos = (0..MAX_INSTANCE_TEXTURES)
+            .map(|t| {
+                let texture = data.textures.get(t).unwrap_or(&data.white_texture);
+                vk::DescriptorImageInfo::builder()
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .image_view(texture.view.handle())
+                    .sampler(data.texture_sampler.handle())
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let instance_textures_write = vk::WriteDescriptorSet::builder()
+            .dst_set(frame.descriptor_set)
+            .dst_binding(3)
+            .dst_array_element(0)
             .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .image_info(image_infos);
+            .image_info(&instance_texture_infos);
 
         // The second argument can be used to copy descriptor sets to each other.
         device.update_descriptor_sets(
-            &[ubo_write, sampler_write],
+            &[ubo_write, light_write, shadow_map_write, instance_textures_write],
             &[] as &[vk::CopyDescriptorSet]
         );
     }
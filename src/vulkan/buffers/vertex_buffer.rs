@@ -2,25 +2,28 @@ use anyhow::*;
 use std::ptr::copy_nonoverlapping as memcpy;
 use vulkanalia::prelude::v1_3::*;
 
-use crate::{app::AppData, vulkan::vertex::Vertex};
+use crate::{app::AppData, vulkan::scene::Mesh, vulkan::vertex::Vertex};
 
 use super::buffer::{copy_buffer, create_buffer};
 
+/// Uploads the vertices of a mesh into its own vertex buffer.
 pub unsafe fn create_vertex_buffer(
     instance: &Instance,
     device: &Device,
     data: &mut AppData,
+    mesh: &mut Mesh,
 ) -> Result<()> {
     // The buffer needs to hold all our vertex data.
-    let size = (size_of::<Vertex>() * data.vertices.len()) as u64;
+    let size = (size_of::<Vertex>() * mesh.vertices.len()) as u64;
 
     // Creates a staging buffer accessible to both the CPU and GPU so that we can
     // transfer the vertex data to a more optimal buffer, which the GPU will read
     // the data from when it's needed. It won't be accessible from the CPU anymore.
-    let (staging_buffer, staging_buffer_memory) = create_buffer(
+    let staging_buffer = create_buffer(
         instance,
         device,
         data,
+        &format!("{} vertex staging buffer", mesh.name),
         size,
         // This buffer can be used as a source in a memory transfer operation, meaning
         // the GPU will only perform transfer operations that copy from the buffer, not to it.
@@ -32,30 +35,28 @@ pub unsafe fn create_vertex_buffer(
         vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
     )?;
 
-    let memory = device.map_memory(staging_buffer_memory, 0, size, vk::MemoryMapFlags::empty())?;
+    // Host visible memory stays mapped (see allocator.rs), so we get a pointer to it right away.
+    let memory = data.allocator.mapped(staging_buffer.allocation())?;
 
     // Copies the vertex data from CPU memory to the GPU-accessible memory region.
-    memcpy(data.vertices.as_ptr(), memory.cast(), data.vertices.len());
+    // The memory is host coherent, so the GPU sees the writes without flushing them.
+    memcpy(mesh.vertices.as_ptr(), memory.cast(), mesh.vertices.len());
 
-    // Unmap the memory after writing to ensure all changes are visible to the GPU.
-    device.unmap_memory(staging_buffer_memory);
-
-    let (vertex_buffer, vertex_buffer_memory) = create_buffer(
+    let vertex_buffer = create_buffer(
         instance,
         device,
         data,
+        &format!("{} vertex buffer", mesh.name),
         size,
         vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER,
         vk::MemoryPropertyFlags::DEVICE_LOCAL,
     )?;
 
-    data.vertex_buffer = vertex_buffer;
-    data.vertex_buffer_memory = vertex_buffer_memory;
+    copy_buffer(device, data, staging_buffer.handle(), vertex_buffer.handle(), size)?;
 
-    copy_buffer(device, data, staging_buffer, vertex_buffer, size)?;
+    mesh.vertex_buffer = vertex_buffer;
 
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(staging_buffer_memory, None);
+    // The staging buffer is dropped here, which queues it for destruction.
 
     Ok(())
 }
@@ -3,6 +3,7 @@ use anyhow::Result;
 
 use crate::app::AppData;
 
+use super::queries::cmd_reset_draw_queries;
 use super::queue::QueueFamilyIndices;
 
 /// A command pool is an object used to manage the memory allocation of command buffers.
@@ -19,7 +20,13 @@ use super::queue::QueueFamilyIndices;
 /// 
 /// When a command pool is destroyed, all command buffers allocated from it 
 /// are reset or destroyed automatically.
-pub unsafe fn create_command_pool(
+///
+/// We create one pool for the one-time commands that upload resources, and one pool per
+/// frame in flight for the command buffer that draws the frame. A frame's pool is reset
+/// as a whole before its command buffer is recorded again, which is cheaper than resetting
+/// the command buffers one by one. Pools aren't thread safe, so giving each frame its own
+/// also keeps a frame from touching a pool that another frame's commands came from.
+pub unsafe fn create_command_pools(
     instance: &Instance,
     device: &Device,
     data: &mut AppData,
@@ -33,7 +40,9 @@ pub unsafe fn create_command_pool(
         // 1. Transient: Optimized for short-lived command buffers.
         // 2. Resettable command buffers: Command buffers allocated from this pool
         //      can be individually reset, rather than resetting the entire pool.
-        .flags(vk::CommandPoolCreateFlags::empty())
+        // Both the one-time commands and the frames' commands are recorded, submitted
+        // once and thrown away, and the frames' pools are reset as a whole.
+        .flags(vk::CommandPoolCreateFlags::TRANSIENT)
 
         // The command pool created is tied to a specific queue family, and thus
         // all the buffers allocated from it are tied to the same queue family as well.
@@ -41,6 +50,10 @@ pub unsafe fn create_command_pool(
 
     data.command_pool = device.create_command_pool(&info, None)?;
 
+    for frame in &mut data.frames {
+        frame.command_pool = device.create_command_pool(&info, None)?;
+    }
+
     Ok(())
 }
 
@@ -52,7 +65,7 @@ pub unsafe fn create_command_pool(
 /// 
 /// Buffers are needed for:
 /// 
-/// Minimized overhead: Commands are once recorded and reused, avoiding expensive per-frame CPU processing.
+/// Minimized overhead: Commands can be recorded once and reused, avoiding per-frame CPU processing.
 /// Parallelism: Multiple threads can record commands into different command buffers 
 ///     simultaneously maximizing CPU utilization.
 /// Synchronization: By organizing commands into discrete units (command buffers),
@@ -64,81 +77,56 @@ pub unsafe fn create_command_pool(
 ///     by a primary command buffer. Useful for splitting work across threads 
 ///     (recording different parts of a scene)
 /// 
+/// We allocate the command buffer of each frame in flight from the frame's pool. They are
+/// recorded every frame (see `record_command_buffer`), so whatever changed since the last
+/// frame, like the objects of the scene, is drawn right away.
 pub unsafe fn create_command_buffers(
     device: &Device,
     data: &mut AppData,
 ) -> Result<()> {
-    
-    let alloc_info = vk::CommandBufferAllocateInfo::builder()
-        .command_pool(data.command_pool)
-        .level(vk::CommandBufferLevel::PRIMARY)
-        .command_buffer_count(data.framebuffers.len() as u32);
-
-    data.command_buffers = device.allocate_command_buffers(&alloc_info)?;
-
-    for (i, command_buffer) in data.command_buffers.iter().enumerate() {
-        let inheritance = vk::CommandBufferInheritanceInfo::builder();
-
-        let info = vk::CommandBufferBeginInfo::builder()
-            .flags(vk::CommandBufferUsageFlags::empty())
-            .inheritance_info(&inheritance);
-
-        let render_area = vk::Rect2D::builder()
-            .offset(vk::Offset2D::default())
-            .extent(data.swapchain_extent);
-
-        let color_clear_value = vk::ClearValue {
-            color: vk::ClearColorValue {
-                float32: [0.02, 0.02, 0.02, 1.0]
-            }
-        };
-
-        let depth_clear_value = vk::ClearValue {
-            depth_stencil: vk::ClearDepthStencilValue {
-                depth: 1.0,
-                stencil: 0,
-            },
-        };
-
-        // The order of clear values should be identical to the order of attachments.
-        let clear_values = &[color_clear_value, depth_clear_value];
-        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
-            .render_pass(data.render_pass)
-            .framebuffer(data.framebuffers[i])
-            .render_area(render_area)
-            .clear_values(clear_values);
-        
-        
-        device.begin_command_buffer(*command_buffer, &info)?;
-            device.cmd_begin_render_pass(*command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
-        
-            // The command buffer tracks state changes (e.g., pipeline bindings) and
-            // ensures dependencies are managed correctly.
-            // The pipeline is meant to operate on attachments and the render pass describes them
-            // so the pipeline needs to be bound only after the render pass begins.
-            device.cmd_bind_pipeline(*command_buffer, vk::PipelineBindPoint::GRAPHICS, data.pipeline);
-            device.cmd_bind_vertex_buffers(*command_buffer, 0, &[data.vertex_buffer], &[0]);
-            device.cmd_bind_index_buffer(
-                *command_buffer,
-                data.index_buffer,
-                0,
-                vk::IndexType::UINT32);
-            device.cmd_bind_descriptor_sets(*command_buffer, 
-                vk::PipelineBindPoint::GRAPHICS,
-                data.pipeline_layout,
-                0,
-                &[data.descriptor_sets[i]],
-                &[]
-            );
-            device.cmd_draw_indexed(*command_buffer, data.indices.len() as u32,
-                1, 0, 0, 0);
-            device.cmd_end_render_pass(*command_buffer);
-        device.end_command_buffer(*command_buffer)?;
+
+    for frame in &mut data.frames {
+        let alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(frame.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+
+        frame.command_buffer = device.allocate_command_buffers(&alloc_info)?[0];
     }
 
     Ok(())
 }
 
+/// Records the commands that draw a frame in flight into the swapchain image with the
+/// given index, returning the frame's command buffer.
+///
+/// The frame's previous commands must have finished executing (its fence was waited for),
+/// as resetting the pool throws them away.
+pub unsafe fn record_command_buffer(
+    device: &Device,
+    data: &AppData,
+    frame: usize,
+    image_index: usize,
+) -> Result<vk::CommandBuffer> {
+
+    let frame = &data.frames[frame];
+    device.reset_command_pool(frame.command_pool, vk::CommandPoolResetFlags::empty())?;
+
+    let command_buffer = frame.command_buffer;
+
+    // The commands are submitted once and recorded again for the next frame.
+    let info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+    device.begin_command_buffer(command_buffer, &info)?;
+        cmd_reset_draw_queries(device, frame, command_buffer);
+        // The passes, their barriers and attachments all come from the render graph.
+        data.render_graph.execute(device, data, frame, command_buffer, image_index);
+    device.end_command_buffer(command_buffer)?;
+
+    Ok(command_buffer)
+}
+
 pub unsafe fn begin_single_time_commands(
     device: &Device,
     data: &AppData,
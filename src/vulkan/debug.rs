@@ -1,5 +1,6 @@
 use vulkanalia::prelude::v1_3::*;
-use std::ffi::{CStr, c_void};
+use vulkanalia::vk::ExtDebugUtilsExtension;
+use std::ffi::{CStr, CString, c_void};
 use log::*;
 
 pub extern "system" fn debug_callback (
@@ -24,4 +25,62 @@ pub extern "system" fn debug_callback (
     // Indicates if the Vulkan call that triggered this callback should be aborted.
     // If the callback returns true, then the call is aborted with a specific error code.
     vk::FALSE
-}
\ No newline at end of file
+}
+
+/// Names the objects we create and labels the passes in the command buffers, so that the
+/// messages of the validation layer and frame debuggers like RenderDoc show those names
+/// instead of raw handles.
+///
+/// The debug utils extension is only enabled along with validation, so this does nothing
+/// unless `VALIDATION_ENABLED` is set. The commands of the extension are loaded by the
+/// instance, which is why it keeps a copy of it.
+#[derive(Clone, Debug, Default)]
+pub struct DebugUtils {
+    instance: Option<Instance>,
+}
+
+impl DebugUtils {
+    pub fn new(instance: &Instance) -> Self {
+        Self {
+            instance: Some(instance.clone()),
+        }
+    }
+
+    /// Names a buffer, image, pipeline, descriptor set or other object of the device.
+    pub unsafe fn set_name<H>(&self, device: &Device, handle: H, name: &str)
+    where
+        H: vk::Handle<Repr = u64>,
+    {
+        let Some(instance) = &self.instance else {
+            return;
+        };
+
+        let name = CString::new(name).unwrap_or_default();
+        let info = vk::DebugUtilsObjectNameInfoEXT::builder()
+            .object_type(H::TYPE)
+            .object_handle(handle.as_raw())
+            .object_name(name.as_bytes_with_nul());
+
+        // A name is only a help for debugging, so failing to set one isn't an error.
+        if let Err(e) = instance.set_debug_utils_object_name_ext(device.handle(), &info) {
+            warn!("Failed to name {:?} '{}': {}", H::TYPE, name.to_string_lossy(), e);
+        }
+    }
+
+    /// Begins a labeled region of a command buffer, which must be ended by `end_label`.
+    pub unsafe fn begin_label(&self, command_buffer: vk::CommandBuffer, name: &str) {
+        let Some(instance) = &self.instance else {
+            return;
+        };
+
+        let name = CString::new(name).unwrap_or_default();
+        let info = vk::DebugUtilsLabelEXT::builder().label_name(name.as_bytes_with_nul());
+        instance.cmd_begin_debug_utils_label_ext(command_buffer, &info);
+    }
+
+    pub unsafe fn end_label(&self, command_buffer: vk::CommandBuffer) {
+        if let Some(instance) = &self.instance {
+            instance.cmd_end_debug_utils_label_ext(command_buffer);
+        }
+    }
+}
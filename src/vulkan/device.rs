@@ -3,10 +3,12 @@ use std::collections::HashSet;
 use super::physical_device::DEVICE_EXTENSIONS;
 use crate::app::AppData;
 use crate::app::{PORTABILITY_MACOS_VERSION, VALIDATION_ENABLED, VALIDATION_LAYER};
+use crate::vulkan::allocator::Allocator;
 use crate::vulkan::queue::*;
 use anyhow::Result;
 use vulkanalia::prelude::v1_3::*;
 
+#[allow(clippy::needless_return)]
 pub unsafe fn create_logical_device(
     entry: &Entry,
     instance: &Instance,
@@ -34,10 +36,14 @@ pub unsafe fn create_logical_device(
         vec![]
     };
 
-    let mut extensions = DEVICE_EXTENSIONS
-        .iter()
-        .map(|e| e.as_ptr())
-        .collect::<Vec<_>>();
+    let mut extensions = if data.headless {
+        vec![]
+    } else {
+        DEVICE_EXTENSIONS
+            .iter()
+            .map(|e| e.as_ptr())
+            .collect::<Vec<_>>()
+    };
 
     // Required by Vulkan SDK on macOS since 1.3.216.
     if cfg!(target_os = "macos") && entry.version()? >= PORTABILITY_MACOS_VERSION {
@@ -46,15 +52,34 @@ pub unsafe fn create_logical_device(
 
     let features = vk::PhysicalDeviceFeatures::builder()
         .sample_rate_shading(true)
-        .sampler_anisotropy(true);
+        .sampler_anisotropy(true)
+        // Only enabled when picking the device found it supported.
+        .pipeline_statistics_query(data.pipeline_statistics);
+
+    // Only enabled when picking the device found them supported.
+    let mut features_12 = vk::PhysicalDeviceVulkan12Features::builder()
+        .shader_sampled_image_array_non_uniform_indexing(true);
 
-    let info = vk::DeviceCreateInfo::builder()
+    let mut features_13 = vk::PhysicalDeviceVulkan13Features::builder()
+        .dynamic_rendering(true)
+        .synchronization2(true);
+
+    let mut info = vk::DeviceCreateInfo::builder()
         .queue_create_infos(&queue_infos)
         .enabled_layer_names(&layers)
         .enabled_extension_names(&extensions)
         .enabled_features(&features);
 
+    if data.non_uniform_indexing {
+        info = info.push_next(&mut features_12);
+    }
+
+    if data.dynamic_rendering {
+        info = info.push_next(&mut features_13);
+    }
+
     let device = instance.create_device(data.physical_device, &info, None)?;
+    data.allocator = Allocator::new(instance, data.physical_device);
     data.graphics_queue = device.get_device_queue(indices.graphics, 0);
     data.present_queue = device.get_device_queue(indices.present, 0);
 
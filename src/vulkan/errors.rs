@@ -1,5 +1,93 @@
+use std::io;
+use std::path::PathBuf;
+
 use thiserror::Error;
+use vulkanalia::vk;
 
 #[derive(Debug, Error)]
 #[error("Missing {0}.")]
 pub struct SuitabilityError(pub &'static str);
+
+/// Reasons why an image cannot be used as a texture.
+#[derive(Debug, Error)]
+pub enum TextureError {
+    #[error("Failed to open texture image {0}: {1}")]
+    Open(PathBuf, io::Error),
+    #[error("Failed to decode texture image: {0}")]
+    Decoding(#[from] png::DecodingError),
+    #[error("Texture image has no pixels ({0}x{1}).")]
+    Empty(u32, u32),
+    #[error("Texture image is {0}x{1}, but the device supports at most {2}x{2}.")]
+    TooLarge(u32, u32, u32),
+    #[error("Unsupported texture image color type {0:?} with bit depth {1:?}.")]
+    UnsupportedColorType(png::ColorType, png::BitDepth),
+}
+
+/// Reasons why the pipeline cache file can't be used by the current device.
+#[derive(Debug, Error)]
+pub enum PipelineCacheError {
+    #[error("The data is too short to hold a header ({0} bytes).")]
+    TooShort(usize),
+    #[error("Unsupported header of size {0} and version {1}.")]
+    UnsupportedHeader(u32, u32),
+    #[error("It was written for another device (vendor {0:#x}, device {1:#x}).")]
+    DeviceMismatch(u32, u32),
+    #[error("It was written by another driver (the pipeline cache UUID differs).")]
+    UuidMismatch,
+}
+
+/// Reasons why a shader couldn't be compiled.
+#[derive(Debug, Error)]
+pub enum ShaderError {
+    #[error("Failed to read shader {0}: {1}")]
+    Read(PathBuf, io::Error),
+    #[error("Failed to compile shader {0}:\n{1}")]
+    Compile(PathBuf, String),
+}
+
+/// Reasons why the interface of a shader doesn't fit the pipeline it's used in.
+#[derive(Debug, Error)]
+pub enum ReflectionError {
+    #[error("Failed to reflect the SPIR-V module: {0}")]
+    Parse(String),
+    #[error("Binding {1} of set {0} is an array without a fixed size.")]
+    UnsizedArray(u32, u32),
+    #[error("Binding {1} of set {0} has an unsupported resource type.")]
+    UnsupportedResource(u32, u32),
+    #[error("The vertex shader input at location {0} has an unsupported type.")]
+    UnsupportedVertexInput(u32),
+    #[error("Binding {1} of set {0} doesn't match the descriptor set layout.")]
+    BindingMismatch(u32, u32),
+    #[error("The shaders use descriptor set {0}, which the pipeline layout doesn't have.")]
+    UnknownSet(usize),
+    #[error("The push constants ({0} bytes) don't fit the pipeline layout.")]
+    PushConstantMismatch(u32),
+    #[error("The vertex shader reads location {0} as {1:?}, but no vertex attribute provides it.")]
+    MissingVertexAttribute(u32, vk::Format),
+    #[error("Vertex attribute {0} of binding {1} is {2:?}, but the vertex shader reads it as {3:?}.")]
+    VertexAttributeMismatch(u32, u32, vk::Format, vk::Format),
+}
+
+/// Reasons why the passes of the render graph can't be put together.
+#[derive(Debug, Error)]
+pub enum RenderGraphError {
+    #[error("There is no pass named {0}.")]
+    UnknownPass(String),
+    #[error("The passes {0:?} depend on each other.")]
+    Cycle(Vec<String>),
+    #[error("Pass {1} reads the transient image {0} before any pass writes it.")]
+    ReadBeforeWrite(String, String),
+    #[error("Pass {1} uses image {0} in more than one way.")]
+    ConflictingAccess(String, String),
+    #[error("The attachments of pass {0} have different sizes.")]
+    ExtentMismatch(String),
+}
+
+/// Reasons why a screenshot can't be taken.
+#[derive(Debug, Error)]
+pub enum ScreenshotError {
+    #[error("The swapchain images can't be copied from (no TRANSFER_SRC usage).")]
+    NotSupported,
+    #[error("Screenshots of {0:?} images aren't supported.")]
+    UnsupportedFormat(vk::Format),
+}
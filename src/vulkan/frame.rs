@@ -0,0 +1,51 @@
+use vulkanalia::prelude::v1_3::*;
+
+use super::queries::DrawList;
+use super::resources::Buffer;
+use super::screenshot::Screenshot;
+
+/// The resources of a frame in flight.
+///
+/// While the GPU renders a frame, the CPU already prepares the next ones. Everything a
+/// frame writes to or records into is therefore owned by its context, and is only reused
+/// once the frame's fence says the GPU is done with it, `MAX_FRAMES_IN_FLIGHT` frames
+/// later. None of it depends on the swapchain, so it survives swapchain recreation.
+#[derive(Debug, Default)]
+pub struct FrameContext {
+    /// Reset as a whole before the command buffer is recorded again (see commands.rs).
+    pub command_pool: vk::CommandPool,
+    pub command_buffer: vk::CommandBuffer,
+
+    /// The view and projection matrices and the lights of the frame.
+    pub uniform_buffer: Buffer,
+    pub light_buffer: Buffer,
+    /// The instance data of the objects, with room for `AppData::instance_capacity`
+    /// instances.
+    pub instance_buffer: Buffer,
+    /// Set 0, which points at the buffers above, the shadow map and the textures
+    /// instances can choose from.
+    pub descriptor_set: vk::DescriptorSet,
+
+    /// Signaled when the swapchain image the frame renders into has been acquired.
+    pub image_available_semaphore: vk::Semaphore,
+    /// Signaled when the frame has been rendered, presentation waits for it.
+    pub render_finished_semaphore: vk::Semaphore,
+    /// Signaled when the GPU has finished executing the frame's commands, after which
+    /// the CPU can reuse the frame's resources.
+    pub command_completion_fence: vk::Fence,
+
+    /// The timestamps written around the passes, null when the queue has none (see
+    /// queries.rs).
+    pub timestamp_pool: vk::QueryPool,
+    /// The passes the timestamps were written around, in the order of the queries.
+    pub timed_passes: Vec<String>,
+    /// The pipeline statistics and occlusion queries of the draws, null when they aren't
+    /// used.
+    pub statistics_pool: vk::QueryPool,
+    pub occlusion_pool: vk::QueryPool,
+    /// The draws of the main pass, planned before the command buffer is recorded.
+    pub draws: DrawList,
+
+    /// The screenshot the frame takes, saved once the frame has finished.
+    pub screenshot: Option<Screenshot>,
+}
@@ -2,58 +2,68 @@
 use vulkanalia::prelude::v1_3::*;
 use anyhow::*;
 use std::fs::File;
+use std::path::Path;
 
 use crate::app::AppData;
 
-use super::{buffers::buffer::{create_buffer, get_memory_type_index}, commands::{begin_single_time_commands, end_single_time_commands}};
+use super::errors::TextureError;
+
+use super::{buffers::buffer::create_buffer, commands::{begin_single_time_commands, end_single_time_commands}};
+use super::resources::{Image, ImageView, Sampler};
 use std::ptr::copy_nonoverlapping as memcpy;
 
-pub unsafe fn create_texture_image(
+/// A texture image on the GPU along with the view used to sample it.
+#[derive(Debug, Default)]
+pub struct Texture {
+    pub image: Image,
+    pub view: ImageView,
+    pub mip_levels: u32,
+}
+
+/// Uploads the pixels of a texture into a mipmapped, sampleable image, named after the
+/// texture.
+pub unsafe fn create_texture(
     instance: &Instance,
     device: &Device,
     data: &mut AppData,
-) -> Result<()> {
+    texture: &TextureData,
+) -> Result<Texture> {
 
-    let image = File::open("resources/viking_room.png")?;
+    let TextureData { name, pixels, width, height, .. } = texture;
+    let (width, height) = (*width, *height);
+    let size = pixels.len() as u64;
+    let format = texture.format();
 
-    let decoder = png::Decoder::new(image);
-    let mut reader = decoder.read_info()?;
+    let max_dimension = instance
+        .get_physical_device_properties(data.physical_device)
+        .limits
+        .max_image_dimension_2d;
 
-    let mut pixels = vec![0; reader.output_buffer_size()];
-    reader.next_frame(&mut pixels)?;
-
-    let size = reader.output_buffer_size() as u64;
-    let (width, height) = reader.info().size();
-
-    if width != 1024 || height != 1024 || reader.info().color_type != png::ColorType::Rgba {
-        panic!("Invalid texture image.");
+    if width > max_dimension || height > max_dimension {
+        return Err(anyhow!(TextureError::TooLarge(width, height, max_dimension)));
     }
 
-    data.mip_levels = (width.max(height) as f32).log2().floor() as u32 + 1;
+    let mip_levels = (width.max(height) as f32).log2().floor() as u32 + 1;
 
-    println!("mip levels: {}", data.mip_levels);
-
-    let (staging_buffer, staging_buffer_memory) = create_buffer(
-        instance, device, data, size, 
+    let staging_buffer = create_buffer(
+        instance, device, data, &format!("{} staging buffer", name), size,
         vk::BufferUsageFlags::TRANSFER_SRC,
         vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT)?;
     
-    let memory = device.map_memory(
-        staging_buffer_memory, 0, size, vk::MemoryMapFlags::empty())?;
+    let memory = data.allocator.mapped(staging_buffer.allocation())?;
 
     memcpy(pixels.as_ptr(), memory.cast(), pixels.len());
 
-    device.unmap_memory(staging_buffer_memory);
-
-    let (texture_image, texture_image_memory) = create_image(
+    let texture_image = create_image(
         instance, 
         device, 
         data, 
+        name,
         width, 
         height,
-        data.mip_levels,
+        mip_levels,
         vk::SampleCountFlags::_1,
-        vk::Format::R8G8B8A8_SRGB, 
+        format, 
         
         // vk::ImageTiling::LINEAR: Texels are laid out in a row-major order like the 
         //   pixels array (first row, second row, etc.). This means the individual texels
@@ -70,24 +80,21 @@ pub unsafe fn create_texture_image(
         vk::MemoryPropertyFlags::DEVICE_LOCAL
     )?;
 
-    data.texture_image = texture_image;
-    data.texture_image_memory = texture_image_memory;
-
     transition_image_layout(
         device, 
         data, 
-        data.texture_image,
-        vk::Format::R8G8B8A8_SRGB, 
+        texture_image.handle(),
+        format, 
         vk::ImageLayout::UNDEFINED, 
         vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-        data.mip_levels,
+        mip_levels,
     )?;
 
     copy_buffer_to_image(
         device, 
         data, 
-        staging_buffer, 
-        data.texture_image,
+        staging_buffer.handle(), 
+        texture_image.handle(),
         width, 
         height
     )?;
@@ -95,28 +102,120 @@ pub unsafe fn create_texture_image(
     //transition_image_layout(
     //    device, 
     //    data, 
-    //    data.texture_image, 
-    //    vk::Format::R8G8B8A8_SRGB, 
+    //    texture_image, 
+    //    format, 
     //    vk::ImageLayout::TRANSFER_DST_OPTIMAL, 
     //    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-    //    data.mip_levels,
+    //    mip_levels,
     //)?;
 
     generate_mipmaps(
         instance,
         device, 
         data, 
-        data.texture_image,
-        vk::Format::R8G8B8A8_SRGB,
+        texture_image.handle(),
+        format,
         width, 
         height, 
-        data.mip_levels
+        mip_levels
     )?;
 
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(staging_buffer_memory, None);
+    let texture_image_view = create_image_view(
+        device, 
+        data,
+        texture_image.handle(), 
+        format, 
+        vk::ImageAspectFlags::COLOR,
+        mip_levels,
+    )?;
 
-    Ok(())
+    Ok(Texture {
+        image: texture_image,
+        view: texture_image_view,
+        mip_levels,
+    })
+}
+
+/// Decoded pixels of a texture, tightly packed in the 8-bit RGBA layout of the
+/// R8G8B8A8 texture images.
+#[derive(Clone, Debug)]
+pub struct TextureData {
+    /// Where the texture comes from, e.g. its file name, which names its image in
+    /// validation messages and frame debuggers.
+    pub name: String,
+    pub pixels: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    /// Whether the pixels are sRGB encoded colors. Textures holding other data, like
+    /// normal maps or metalness and roughness, are stored as linear values instead.
+    pub srgb: bool,
+}
+
+impl TextureData {
+    /// The format of the texture image. Sampling an sRGB image converts the colors to
+    /// linear values, which the lighting calculations need, while UNORM images are
+    /// sampled as they are.
+    pub fn format(&self) -> vk::Format {
+        if self.srgb {
+            vk::Format::R8G8B8A8_SRGB
+        } else {
+            vk::Format::R8G8B8A8_UNORM
+        }
+    }
+}
+
+/// Decodes a PNG file into the pixels of a texture.
+///
+/// PNGs come in many flavours, so everything is first normalized to 8 bits per
+/// channel (16-bit channels are stripped, palettes and low bit depth grayscale
+/// are expanded and transparency chunks become an alpha channel). Images without
+/// an alpha channel are then made fully opaque.
+///
+/// The texture is assumed to hold sRGB colors, loaders of other textures clear `srgb`.
+pub fn load_texture_pixels(path: &Path) -> Result<TextureData> {
+    let file = File::open(path).map_err(|e| TextureError::Open(path.to_path_buf(), e))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info().map_err(TextureError::from)?;
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(TextureError::from)?;
+    buffer.truncate(info.buffer_size());
+
+    let (width, height) = (info.width, info.height);
+    if width == 0 || height == 0 {
+        return Err(anyhow!(TextureError::Empty(width, height)));
+    }
+
+    let pixels = match (info.color_type, info.bit_depth) {
+        (png::ColorType::Rgba, png::BitDepth::Eight) => buffer,
+        (png::ColorType::Rgb, png::BitDepth::Eight) => buffer
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], u8::MAX])
+            .collect(),
+        (png::ColorType::GrayscaleAlpha, png::BitDepth::Eight) => buffer
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        (png::ColorType::Grayscale, png::BitDepth::Eight) => buffer
+            .iter()
+            .flat_map(|p| [*p, *p, *p, u8::MAX])
+            .collect(),
+        (color_type, bit_depth) => {
+            return Err(anyhow!(TextureError::UnsupportedColorType(color_type, bit_depth)))
+        }
+    };
+
+    let name = path.file_name().unwrap_or(path.as_os_str());
+
+    Ok(TextureData {
+        name: name.to_string_lossy().into_owned(),
+        pixels,
+        width,
+        height,
+        srgb: true,
+    })
 }
 
 pub unsafe fn generate_mipmaps(
@@ -260,27 +359,14 @@ pub unsafe fn generate_mipmaps(
 }
 
 
-pub unsafe fn create_texture_image_view(
-    device: &Device,
-    data: &mut AppData,
-) -> Result<()> {
-
-    data.texture_image_view = create_image_view(
-        device, 
-        data.texture_image, 
-        vk::Format::R8G8B8A8_SRGB, 
-        vk::ImageAspectFlags::COLOR,
-        data.mip_levels,
-    )?;
-
-    Ok(())
-}
-
 
+/// Creates a 2D image with memory bound to it. The name shows up in validation messages
+/// and frame debuggers (see `DebugUtils`).
 pub unsafe fn create_image(
     instance: &Instance,
     device: &Device,
     data: &mut AppData,
+    name: &str,
     width: u32,
     height: u32,
     mip_levels: u32,
@@ -289,7 +375,7 @@ pub unsafe fn create_image(
     tiling: vk::ImageTiling,
     usage: vk::ImageUsageFlags,
     properties: vk::MemoryPropertyFlags,
-) -> Result<(vk::Image, vk::DeviceMemory)> {
+) -> Result<Image> {
 
     let info = vk::ImageCreateInfo::builder()
         .image_type(vk::ImageType::_2D)
@@ -311,25 +397,23 @@ pub unsafe fn create_image(
 
     let image = device.create_image(&info, None)?;
 
-    let requirements = device.get_image_memory_requirements(image);
-    let info = vk::MemoryAllocateInfo::builder()
-        .allocation_size(requirements.size)
-        .memory_type_index(get_memory_type_index(instance, data, properties, requirements)?);
-    
-    let memory = device.allocate_memory(&info, None)?;
-    
-    device.bind_image_memory(image, memory, 0)?;
+    let allocation = data
+        .allocator
+        .allocate_image_memory(device, image, tiling, properties)
+        .inspect_err(|_| device.destroy_image(image, None))?;
+    data.debug_utils.set_name(device, image, name);
 
-    Ok((image, memory))
+    Ok(Image::new(image, allocation, &data.deletion_queue))
 }
 
 pub unsafe fn create_image_view(
     device: &Device,
+    data: &AppData,
     image: vk::Image,
     format: vk::Format,
     aspects: vk::ImageAspectFlags,
     mip_levels: u32,
-) -> Result<vk::ImageView> {
+) -> Result<ImageView> {
 
     let subresource_range = vk::ImageSubresourceRange::builder()
         .aspect_mask(aspects)
@@ -346,7 +430,7 @@ pub unsafe fn create_image_view(
 
     let image_view = device.create_image_view(&info, None)?;
     
-    Ok(image_view)
+    Ok(ImageView::new(image_view, &data.deletion_queue))
 }
 
 pub unsafe fn transition_image_layout(
@@ -475,7 +559,8 @@ pub unsafe fn copy_buffer_to_image(
 
 /// A Sampler is a distinct object that provides an interface to extract colors from a texture.
 /// It's not bound to any specific vk::Image or vk::ImageView. It can be applied to any image,
-/// whether it is 1D, 2D or 3D.
+/// whether it is 1D, 2D or 3D. We use the same sampler for every texture slot of every
+/// material, as they all share the texture coordinates and repeat across the surface.
 pub unsafe fn create_texture_sampler(
     device: &Device,
     data: &mut AppData,
@@ -515,45 +600,13 @@ pub unsafe fn create_texture_sampler(
         .compare_op(vk::CompareOp::ALWAYS)
         .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
         .min_lod(0.0)
-        .max_lod(data.mip_levels as f32)
+        // Textures have different numbers of mip levels, so the level of detail is
+        // only limited by the mip levels of the sampled image.
+        .max_lod(vk::LOD_CLAMP_NONE)
         .mip_lod_bias(0.0);
 
-        data.texture_sampler = device.create_sampler(&info, None)?;
+        let sampler = device.create_sampler(&info, None)?;
+        data.texture_sampler = Sampler::new(sampler, &data.deletion_queue);
     
     Ok(())
 }
-
-pub unsafe fn create_color_objects(
-    instance: &Instance,
-    device: &Device,
-    data: &mut AppData,
-) -> Result<()> {
-
-    let (color_image, color_image_memory) = create_image(
-        instance,
-        device,
-        data,
-        data.swapchain_extent.width,
-        data.swapchain_extent.height,
-        1,
-        data.msaa_samples,
-        data.swapchain_format,
-        vk::ImageTiling::OPTIMAL,
-        vk::ImageUsageFlags::COLOR_ATTACHMENT
-            | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
-        vk::MemoryPropertyFlags::DEVICE_LOCAL,
-    )?;
-
-    data.color_image = color_image;
-    data.color_image_memory = color_image_memory;
-
-    data.color_image_view = create_image_view(
-        device,
-        data.color_image,
-        data.swapchain_format,
-        vk::ImageAspectFlags::COLOR,
-        1
-    )?;
-
-    Ok(())
-}
\ No newline at end of file
@@ -1,4 +1,4 @@
-use super::debug::debug_callback;
+use super::debug::{debug_callback, DebugUtils};
 use crate::app::AppData;
 use crate::app::{PORTABILITY_MACOS_VERSION, VALIDATION_ENABLED, VALIDATION_LAYER};
 use anyhow::Result;
@@ -9,8 +9,10 @@ use vulkanalia::vk::ExtDebugUtilsExtension;
 use vulkanalia::window as vk_window;
 use winit::window::Window;
 
+/// Creates the Vulkan instance. When no window is given (headless rendering)
+/// the surface extensions are not requested, as nothing will be presented.
 pub unsafe fn create_instance(
-    window: &Window,
+    window: Option<&Window>,
     entry: &Entry,
     data: &mut AppData,
 ) -> Result<Instance> {
@@ -19,7 +21,10 @@ pub unsafe fn create_instance(
         .application_version(vk::make_version(1, 0, 0))
         .engine_name(b"No Engine\0")
         .engine_version(vk::make_version(1, 0, 0))
-        .api_version(vk::make_version(1, 0, 0));
+        // Vulkan 1.2 made descriptor indexing core, which the instance textures need, and
+        // Vulkan 1.3 dynamic rendering, which is used when the device supports it. This is
+        // the highest version we use, devices supporting only 1.2 are fine.
+        .api_version(vk::make_version(1, 3, 0));
 
     let available_layers = entry
         .enumerate_instance_layer_properties()?
@@ -33,17 +38,20 @@ pub unsafe fn create_instance(
         if validation_layer_available {
             vec![VALIDATION_LAYER.as_ptr()]
         } else {
-            println!("Validation layers requested, but not available.");
+            warn!("Validation layers requested, but not available.");
             vec![]
         }
     } else {
         vec![]
     };
 
-    let mut extensions = vk_window::get_required_instance_extensions(window)
-        .iter()
-        .map(|e| e.as_ptr())
-        .collect::<Vec<_>>();
+    let mut extensions = match window {
+        Some(window) => vk_window::get_required_instance_extensions(window)
+            .iter()
+            .map(|e| e.as_ptr())
+            .collect::<Vec<_>>(),
+        None => vec![],
+    };
 
     if VALIDATION_ENABLED {
         extensions.push(vk::EXT_DEBUG_UTILS_EXTENSION.name.as_ptr());
@@ -94,6 +102,7 @@ pub unsafe fn create_instance(
             .user_callback(Some(debug_callback));
 
         data.messenger = instance.create_debug_utils_messenger_ext(&debug_info, None)?;
+        data.debug_utils = DebugUtils::new(&instance);
     }
 
     Ok(instance)
@@ -0,0 +1,265 @@
+use std::path::Path;
+use std::ptr::copy_nonoverlapping as memcpy;
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_3::*;
+
+use crate::app::AppData;
+
+use super::buffers::buffer::create_buffer;
+use super::image::{create_texture, load_texture_pixels, Texture, TextureData};
+use super::model::Material;
+use super::scene::Mesh;
+use super::vertex::Vec4;
+
+/// The number of textures every material binds: base color, metallic-roughness,
+/// normal, occlusion and emissive, at bindings 1 to 5 of the material descriptor set.
+pub const MATERIAL_TEXTURE_COUNT: usize = 5;
+
+/// The material parameters the fragment shader reads from a material's uniform buffer.
+///
+/// The emissive factor is a vec4 as a vec3 is aligned like a vec4 in the std140 layout.
+/// The four floats after it are packed tightly, so the struct needs no padding.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct MaterialUniform {
+    pub base_color_factor: Vec4,
+    pub emissive_factor: Vec4,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+}
+
+/// Gives every primitive of the mesh that doesn't have a material the default material,
+/// which draws the given texture. Models without materials (like OBJ files without an
+/// MTL file) are drawn entirely with it. The default material is created for the first
+/// mesh that needs it and shared by all the others.
+pub fn assign_default_material(data: &mut AppData, mesh: &mut Mesh, texture: &Path) -> Result<()> {
+    if mesh.primitives.iter().all(|p| p.material.is_some()) {
+        return Ok(());
+    }
+
+    let default_material = match data.default_material {
+        Some(material) => material,
+        None => {
+            data.material_textures.push(load_texture_pixels(texture)?);
+            data.materials.push(Material {
+                name: String::from("default"),
+                base_color_texture: Some(data.material_textures.len() - 1),
+                roughness_factor: 0.8,
+                ..Default::default()
+            });
+            data.default_material = Some(data.materials.len() - 1);
+            data.materials.len() - 1
+        }
+    };
+
+    mesh.primitives
+        .iter_mut()
+        .filter(|p| p.material.is_none())
+        .for_each(|p| p.material = Some(default_material));
+
+    Ok(())
+}
+
+/// Uploads the textures referenced by the materials to the GPU. Only the textures
+/// that were added since the last call are uploaded, so it's called again after
+/// loading another mesh.
+pub unsafe fn create_material_textures(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let material_textures = std::mem::take(&mut data.material_textures);
+    for texture in material_textures.iter().skip(data.textures.len()) {
+        let texture = create_texture(instance, device, data, texture)?;
+        data.textures.push(texture);
+    }
+    data.material_textures = material_textures;
+
+    Ok(())
+}
+
+/// Creates two 1x1 textures, which are sampled in the slots a material doesn't have a
+/// texture for, so the shader can always multiply the factors with a texture:
+/// * A white texture, which leaves the factors unchanged.
+/// * A flat normal map, whose normal points straight out of the surface.
+pub unsafe fn create_default_textures(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let white = TextureData {
+        name: String::from("white texture"),
+        pixels: vec![u8::MAX; 4],
+        width: 1,
+        height: 1,
+        srgb: false,
+    };
+
+    // Normal maps store the XYZ in [-1, 1] as colors in [0, 1], so (0, 0, 1) becomes (0.5, 0.5, 1).
+    let flat_normal = TextureData {
+        name: String::from("flat normal texture"),
+        pixels: vec![128, 128, 255, 255],
+        width: 1,
+        height: 1,
+        srgb: false,
+    };
+
+    data.white_texture = create_texture(instance, device, data, &white)?;
+    data.flat_normal_texture = create_texture(instance, device, data, &flat_normal)?;
+
+    Ok(())
+}
+
+/// Creates a uniform buffer holding the parameters of each material that doesn't have
+/// one yet. Materials don't change while rendering, so the buffers are written once here.
+pub unsafe fn create_material_buffers(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let size = size_of::<MaterialUniform>() as u64;
+
+    for i in data.material_buffers.len()..data.materials.len() {
+        let material = &data.materials[i];
+        let uniform = MaterialUniform {
+            base_color_factor: material.base_color_factor,
+            emissive_factor: material.emissive_factor.extend(0.0),
+            metallic_factor: material.metallic_factor,
+            roughness_factor: material.roughness_factor,
+            normal_scale: material.normal_scale,
+            occlusion_strength: material.occlusion_strength,
+        };
+
+        let name = format!("{} material buffer", material.name);
+        let buffer = create_buffer(
+            instance,
+            device,
+            data,
+            &name,
+            size,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        let memory = data.allocator.mapped(buffer.allocation())?;
+        memcpy(&uniform, memory.cast(), 1);
+
+        data.material_buffers.push(buffer);
+    }
+
+    Ok(())
+}
+
+/// The layout of the per-material descriptor set (set = 1 in the fragment shader).
+///
+/// Binding 0 is the material's uniform buffer and bindings 1 to 5 its base color,
+/// metallic-roughness, normal, occlusion and emissive textures. Keeping the material
+/// in its own set means that switching materials between draws only rebinds this set,
+/// while the per-frame set 0 stays bound.
+///
+/// Like set 0, the bindings are reflected from the shaders.
+pub unsafe fn create_material_descriptor_set_layout(
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let bindings = data.shaders.set_layout_bindings(1);
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+    data.material_descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+    Ok(())
+}
+
+/// The material descriptor sets don't depend on the swapchain, so they get their own
+/// pool that survives swapchain recreation. The pool is sized for the loaded materials,
+/// so it's created again when a mesh brings new ones.
+pub unsafe fn create_material_descriptor_pool(device: &Device, data: &mut AppData) -> Result<()> {
+    let count = data.materials.len() as u32;
+
+    let ubo_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::UNIFORM_BUFFER)
+        .descriptor_count(count);
+
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(count * MATERIAL_TEXTURE_COUNT as u32);
+
+    let pool_sizes = &[ubo_size, sampler_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(count);
+
+    data.material_descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+    Ok(())
+}
+
+/// Allocates and writes one descriptor set for every material.
+pub unsafe fn create_material_descriptor_sets(device: &Device, data: &mut AppData) -> Result<()> {
+    let layouts = vec![data.material_descriptor_set_layout; data.materials.len()];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(data.material_descriptor_pool)
+        .set_layouts(&layouts);
+
+    data.material_descriptor_sets = device.allocate_descriptor_sets(&info)?;
+
+    for (i, material) in data.materials.iter().enumerate() {
+        let name = format!("{} material descriptor set", material.name);
+        data.debug_utils.set_name(device, data.material_descriptor_sets[i], &name);
+
+        let buffer_info = vk::DescriptorBufferInfo::builder()
+            .buffer(data.material_buffers[i].handle())
+            .offset(0)
+            .range(size_of::<MaterialUniform>() as u64);
+
+        let buffer_infos = &[buffer_info];
+        let material_write = vk::WriteDescriptorSet::builder()
+            .dst_set(data.material_descriptor_sets[i])
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(buffer_infos);
+
+        // In the order of the bindings 1 to 5, with the default textures for the
+        // missing ones (see `create_default_textures`).
+        let texture = |texture: Option<usize>, default: &Texture| {
+            texture.map_or(default, |t| &data.textures[t]).view.handle()
+        };
+        let views: [vk::ImageView; MATERIAL_TEXTURE_COUNT] = [
+            texture(material.base_color_texture, &data.white_texture),
+            texture(material.metallic_roughness_texture, &data.white_texture),
+            texture(material.normal_texture, &data.flat_normal_texture),
+            texture(material.occlusion_texture, &data.white_texture),
+            texture(material.emissive_texture, &data.white_texture),
+        ];
+
+        let image_infos = views.map(|view| {
+            [vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(view)
+                .sampler(data.texture_sampler.handle())
+                .build()]
+        });
+
+        let texture_writes = image_infos.iter().zip(1..).map(|(image_info, binding)| {
+            vk::WriteDescriptorSet::builder()
+                .dst_set(data.material_descriptor_sets[i])
+                .dst_binding(binding)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(image_info)
+                .build()
+        });
+
+        let writes = [material_write.build()]
+            .into_iter()
+            .chain(texture_writes)
+            .collect::<Vec<_>>();
+        device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+    }
+
+    Ok(())
+}
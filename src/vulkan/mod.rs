@@ -13,4 +13,18 @@ pub mod synchronization;
 pub mod vertex;
 pub mod buffers;
 pub mod image;
-pub mod model;
\ No newline at end of file
+pub mod model;
+pub mod offscreen;
+pub mod material;
+pub mod shadow;
+pub mod scene;
+pub mod allocator;
+pub mod resources;
+pub mod frame;
+pub mod pipeline_cache;
+pub mod shader;
+pub mod reflection;
+pub mod render_graph;
+pub mod passes;
+pub mod screenshot;
+pub mod queries;
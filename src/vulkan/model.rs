@@ -1,27 +1,249 @@
 
-use std::{collections::HashMap, fs::File, io::BufReader};
+use std::{collections::HashMap, fs::File, io::BufReader, path::Path};
 
 use crate::app::AppData;
-use anyhow::Result;
-use cgmath::{vec2, vec3};
+use anyhow::{anyhow, Result};
+use cgmath::{vec2, vec3, vec4, Deg, InnerSpace, Matrix, SquareMatrix, Zero};
+use log::*;
 
-use super::vertex::Vertex;
+use super::buffers::uniform_buffer::Mat4;
+use super::image::{load_texture_pixels, TextureData};
+use super::scene::Mesh;
+use super::vertex::{Vec3, Vec4, Vertex};
 
+/// A range of the index buffer that is drawn with a single material.
+#[derive(Copy, Clone, Debug)]
+pub struct Primitive {
+    pub first_index: u32,
+    pub index_count: u32,
+    /// Index into `AppData::materials`, or `None` for the default material.
+    pub material: Option<usize>,
+}
+
+/// Describes the surface of the primitives that use it with the metallic-roughness
+/// model of glTF 2.0.
+///
+/// Every factor is multiplied with the matching texture, or used on its own when the
+/// material doesn't have that texture. The textures are indices into
+/// `AppData::material_textures`.
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub name: String,
+    /// The albedo of dielectrics and the specular color of metals, alpha in w.
+    pub base_color_factor: Vec4,
+    pub base_color_texture: Option<usize>,
+    /// 1 for metals and 0 for dielectrics (plastic, wood, stone, ...).
+    pub metallic_factor: f32,
+    /// 0 for a perfect mirror, 1 for a completely rough surface.
+    pub roughness_factor: f32,
+    /// Roughness is read from the green channel and metalness from the blue channel.
+    pub metallic_roughness_texture: Option<usize>,
+    /// A tangent space normal map.
+    pub normal_texture: Option<usize>,
+    /// Scales the X and Y of the normals read from the normal map.
+    pub normal_scale: f32,
+    /// Ambient occlusion in the red channel.
+    pub occlusion_texture: Option<usize>,
+    /// How much of the occlusion is applied, 0 means none.
+    pub occlusion_strength: f32,
+    /// Light emitted by the surface itself, independent of the lights in the scene.
+    pub emissive_factor: Vec3,
+    pub emissive_texture: Option<usize>,
+}
+
+impl Default for Material {
+    /// A white, rough, non-metallic material without any textures.
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            base_color_factor: vec4(1.0, 1.0, 1.0, 1.0),
+            base_color_texture: None,
+            metallic_factor: 0.0,
+            roughness_factor: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            emissive_factor: Vec3::zero(),
+            emissive_texture: None,
+        }
+    }
+}
+
+/// A sphere enclosing every vertex of a model.
+#[derive(Copy, Clone, Debug)]
+pub struct Bounds {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl Default for Bounds {
+    fn default() -> Self {
+        Self {
+            center: Vec3::zero(),
+            radius: 0.0,
+        }
+    }
+}
+
+impl Bounds {
+    /// The sphere around the center of the axis-aligned bounding box of the vertices.
+    /// It isn't the smallest enclosing sphere, but it's close and cheap to compute.
+    pub fn of(vertices: &[Vertex]) -> Self {
+        let Some(first) = vertices.first() else {
+            return Self::default();
+        };
+
+        let (min, max) = vertices.iter().fold((first.pos, first.pos), |(min, max), v| {
+            (
+                vec3(min.x.min(v.pos.x), min.y.min(v.pos.y), min.z.min(v.pos.z)),
+                vec3(max.x.max(v.pos.x), max.y.max(v.pos.y), max.z.max(v.pos.z)),
+            )
+        });
+
+        let center = (min + max) / 2.0;
+        let radius = vertices
+            .iter()
+            .map(|v| (v.pos - center).magnitude())
+            .fold(0.0, f32::max);
+
+        Self { center, radius }
+    }
+
+    /// The bounds after transforming them, e.g. with the model matrix of an object.
+    /// The radius grows with the largest scale of the transform.
+    pub fn transformed(&self, transform: Mat4) -> Self {
+        let scale = [transform.x, transform.y, transform.z]
+            .iter()
+            .map(|axis| axis.truncate().magnitude())
+            .fold(0.0, f32::max);
+
+        Self {
+            center: (transform * self.center.extend(1.0)).truncate(),
+            radius: self.radius * scale,
+        }
+    }
+
+    /// The smallest sphere enclosing both spheres.
+    pub fn union(&self, other: &Bounds) -> Self {
+        let offset = other.center - self.center;
+        let distance = offset.magnitude();
+
+        if distance + other.radius <= self.radius {
+            *self
+        } else if distance + self.radius <= other.radius {
+            *other
+        } else {
+            let radius = (distance + self.radius + other.radius) / 2.0;
+            Self {
+                center: self.center + offset / distance * (radius - self.radius),
+                radius,
+            }
+        }
+    }
+}
+
+/// Loads a model into a mesh, picking the loader from the file extension: `.gltf` and
+/// `.glb` are loaded as glTF 2.0, everything else as OBJ.
+///
+/// The materials and textures of the model are added to the ones already loaded, and
+/// the primitives of the mesh refer to them. The mesh isn't uploaded to the GPU yet.
 pub unsafe fn load_model(
-    data: &mut AppData
+    data: &mut AppData,
+    path: &Path,
+) -> Result<Mesh> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+
+    let mut mesh = Mesh {
+        name: path.display().to_string(),
+        ..Default::default()
+    };
+
+    match extension.as_deref() {
+        Some("gltf") | Some("glb") => load_gltf(data, &mut mesh, path)?,
+        _ => load_obj(data, &mut mesh, path)?,
+    }
+
+    if mesh.primitives.iter().all(|p| p.index_count == 0) {
+        return Err(anyhow!("Model {} contains no triangles.", path.display()));
+    }
+
+    generate_missing_normals(&mut mesh.vertices, &mesh.indices);
+    mesh.bounds = Bounds::of(&mesh.vertices);
+
+    Ok(mesh)
+}
+
+/// Generates smooth normals for the vertices that don't have one.
+///
+/// The normal of every triangle is added to each of its vertices, weighted by the
+/// triangle's area (the length of the cross product is twice the area). Vertices at the
+/// same position, e.g. on either side of a texture seam, share their normal so that the
+/// shading is smooth across the seam. Only vertices with a zero normal are touched, so
+/// normals that came with the model are kept.
+pub fn generate_missing_normals(vertices: &mut [Vertex], indices: &[u32]) {
+    let key = |v: &Vertex| [v.pos.x.to_bits(), v.pos.y.to_bits(), v.pos.z.to_bits()];
+    let mut normals: HashMap<[u32; 3], Vec3> = HashMap::new();
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| vertices[triangle[i] as usize]);
+        let normal = (b.pos - a.pos).cross(c.pos - a.pos);
+
+        for vertex in [a, b, c] {
+            if vertex.normal == Vec3::zero() {
+                *normals.entry(key(&vertex)).or_insert_with(Vec3::zero) += normal;
+            }
+        }
+    }
+
+    for vertex in vertices.iter_mut().filter(|v| v.normal == Vec3::zero()) {
+        vertex.normal = match normals.get(&key(vertex)) {
+            Some(normal) if normal.magnitude2() > 0.0 => normal.normalize(),
+            // Vertices that are only part of degenerate triangles have no direction.
+            _ => vec3(0.0, 0.0, 1.0),
+        };
+    }
+}
+
+/// Loads an OBJ model along with the materials of its MTL file, if it has one.
+///
+/// Every model in the OBJ file becomes a primitive drawn with its own material.
+/// Vertices are still shared between them, as a vertex doesn't depend on the material.
+unsafe fn load_obj(
+    data: &mut AppData,
+    mesh: &mut Mesh,
+    path: &Path,
 ) -> Result<()> {
-    let mut reader = BufReader::new(File::open("resources/viking_room.obj")?);
+    let mut reader = BufReader::new(File::open(path)?);
 
-    // We are interested only in the Vec<Model>, not in the Vec<Material>
-    let (models, _) = tobj::load_obj_buf(
+    // MTL files and the textures they reference are relative to the OBJ file.
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    let (models, materials) = tobj::load_obj_buf(
         &mut reader, 
-        &tobj::LoadOptions { triangulate: true, ..Default::default() }, 
-        |_| Ok(Default::default()),
+        // A single index for all vertex attributes, as that's how we index our vertices.
+        &tobj::LoadOptions { triangulate: true, single_index: true, ..Default::default() },
+        |p| tobj::load_mtl(directory.join(p)),
     )?;
 
+    let materials = materials.unwrap_or_else(|e| {
+        warn!("Failed to load the materials of {}: {}", path.display(), e);
+        vec![]
+    });
+
+    // The material ids of the OBJ file start at 0, ours after the loaded materials.
+    let first_material = data.materials.len();
+    load_obj_materials(data, directory, &materials)?;
+
     let mut unique_vertices = HashMap::new();
 
     for model in models {
+        let first_index = mesh.indices.len() as u32;
+
         for index in &model.mesh.indices {
 
             // Positions are stored as a flat array in the obj format:
@@ -32,6 +254,9 @@ pub unsafe fn load_model(
             // [u1, v1, u2, v2, u3, v3, ...]
             let tex_coord_offset = (2 * index) as usize;
 
+            // Normals are laid out like the positions.
+            let normal_offset = (3 * index) as usize;
+
             let vertex = Vertex {
                 pos: vec3(
                     model.mesh.positions[pos_offset],
@@ -39,26 +264,388 @@ pub unsafe fn load_model(
                     model.mesh.positions[pos_offset + 2],
                 ),
                 color: vec3(1.0, 1.0, 1.0),
-                tex_coord: vec2(
-                    model.mesh.texcoords[tex_coord_offset],
+                // Not every model has texture coordinates, e.g. untextured parts of a model.
+                tex_coord: if model.mesh.texcoords.is_empty() {
+                    vec2(0.0, 0.0)
+                } else {
+                    vec2(
+                        model.mesh.texcoords[tex_coord_offset],
 
-                    // The OBJ format assumes a coordinate system where a vertical coordinate of 0 means the bottom
-                    // of the image, but we've uploaded our image into Vulkan in a top to bottom orientation where 0
-                    // means the top of the image. This can be solved by flipping the vertical component of the texture.
-                    1.0 - model.mesh.texcoords[tex_coord_offset + 1],
-                ),
+                        // The OBJ format assumes a coordinate system where a vertical coordinate of 0 means the bottom
+                        // of the image, but we've uploaded our image into Vulkan in a top to bottom orientation where 0
+                        // means the top of the image. This can be solved by flipping the vertical component of the texture.
+                        1.0 - model.mesh.texcoords[tex_coord_offset + 1],
+                    )
+                },
+                // Models without normals get smooth normals generated after loading.
+                normal: if model.mesh.normals.is_empty() {
+                    Vec3::zero()
+                } else {
+                    vec3(
+                        model.mesh.normals[normal_offset],
+                        model.mesh.normals[normal_offset + 1],
+                        model.mesh.normals[normal_offset + 2],
+                    )
+                },
             };
 
             if let Some(index) = unique_vertices.get(&vertex) {
-                data.indices.push(*index as u32);
+                mesh.indices.push(*index as u32);
             } else {
-                let index = data.vertices.len();
-                data.vertices.push(vertex);
-                data.indices.push(index as u32);
+                let index = mesh.vertices.len();
+                mesh.vertices.push(vertex);
+                mesh.indices.push(index as u32);
                 unique_vertices.insert(vertex, index);
             }
         }
+
+        mesh.primitives.push(Primitive {
+            first_index,
+            index_count: mesh.indices.len() as u32 - first_index,
+            material: model
+                .mesh
+                .material_id
+                .filter(|m| *m < materials.len())
+                .map(|m| first_material + m),
+        });
+    }
+
+    Ok(())
+}
+
+/// Converts the materials of an MTL file to metallic-roughness materials.
+///
+/// MTL files describe Phong materials, which are mapped as follows:
+/// * `Kd`, `d` and `map_Kd` become the base color.
+/// * The PBR extension parameters `Pm` and `Pr` are used for the metalness and
+///   roughness. Without `Pr`, the roughness is derived from the shininess `Ns`.
+/// * `norm`, or `map_Bump` without it, is used as a normal map.
+/// * `Ke` and `map_Ke` become the emissive color.
+///
+/// Textures shared by several materials are only loaded once.
+fn load_obj_materials(
+    data: &mut AppData,
+    directory: &Path,
+    materials: &[tobj::Material],
+) -> Result<()> {
+    let mut textures = HashMap::new();
+    let mut load_texture = |data: &mut AppData, name: &str, srgb: bool| -> Result<Option<usize>> {
+        if name.is_empty() {
+            return Ok(None);
+        }
+
+        if let Some(index) = textures.get(&(name.to_string(), srgb)) {
+            return Ok(Some(*index));
+        }
+
+        let mut texture = load_texture_pixels(&directory.join(name))?;
+        texture.srgb = srgb;
+        data.material_textures.push(texture);
+        let index = data.material_textures.len() - 1;
+        textures.insert((name.to_string(), srgb), index);
+        Ok(Some(index))
+    };
+
+    for material in materials {
+        let param = |key: &str| material.unknown_param.get(key).map(String::as_str);
+        let scalar = |key: &str| param(key).and_then(|v| v.parse::<f32>().ok());
+
+        let base_color_texture = load_texture(data, &material.diffuse_texture, true)?;
+        let normal_texture = param("norm").unwrap_or(&material.normal_texture);
+        let normal_texture = load_texture(data, normal_texture, false)?;
+        let emissive_factor = param("Ke")
+            .map(|v| {
+                v.split_whitespace()
+                    .filter_map(|c| c.parse::<f32>().ok())
+                    .collect::<Vec<_>>()
+            })
+            .filter(|c| c.len() == 3)
+            .map(|c| vec3(c[0], c[1], c[2]));
+        let emissive_texture = load_texture(data, param("map_Ke").unwrap_or_default(), true)?;
+
+        let [r, g, b] = material.diffuse;
+        data.materials.push(Material {
+            name: material.name.clone(),
+            base_color_factor: vec4(r, g, b, material.dissolve),
+            base_color_texture,
+            metallic_factor: scalar("Pm").unwrap_or(0.0),
+            // The usual conversion of a Blinn-Phong exponent to a GGX roughness.
+            roughness_factor: scalar("Pr")
+                .unwrap_or_else(|| (2.0 / (material.shininess.max(0.0) + 2.0)).sqrt()),
+            normal_texture,
+            // A map_Ke without Ke should still glow.
+            emissive_factor: emissive_factor.unwrap_or(if emissive_texture.is_some() {
+                vec3(1.0, 1.0, 1.0)
+            } else {
+                Vec3::zero()
+            }),
+            emissive_texture,
+            ..Default::default()
+        });
+    }
+
+    Ok(())
+}
+
+/// Loads a glTF 2.0 model, either a `.gltf` file with embedded or external buffers
+/// and images, or a binary `.glb` file.
+///
+/// glTF models are made of a hierarchy of nodes, each with its own transform, which
+/// reference meshes made of primitives. Every primitive has its own vertex and index
+/// data and material. The primitives are flattened into our single vertex and index
+/// buffer, with their node transforms baked into the vertex positions.
+unsafe fn load_gltf(
+    data: &mut AppData,
+    mesh: &mut Mesh,
+    path: &Path,
+) -> Result<()> {
+    let (document, buffers, images) = gltf::import(path)?;
+
+    // The material indices of the file start at 0, ours after the loaded ones.
+    let first_material = data.materials.len();
+
+    // Only the base color and emissive textures hold colors, the other textures hold
+    // data that must not be converted from sRGB when sampled. An image can be used both
+    // ways, so it is loaded once for each color space it's used in, like the textures of
+    // OBJ materials.
+    let mut textures = HashMap::new();
+    let mut load_texture =
+        |data: &mut AppData, texture: gltf::Texture, usage: String, srgb: bool| -> Result<usize> {
+            let image = texture.source();
+            if let Some(index) = textures.get(&(image.index(), srgb)) {
+                return Ok(*index);
+            }
+
+            // Images embedded in the file are named after the first material slot they're
+            // used in, unless they have a name of their own.
+            let name = match image.source() {
+                gltf::image::Source::Uri { uri, .. } => uri.to_string(),
+                gltf::image::Source::View { .. } => image.name().map_or(usage, str::to_string),
+            };
+
+            let image = image.index();
+            let mut texture = texture_data_from_gltf_image(&images[image], name)?;
+            texture.srgb = srgb;
+            data.material_textures.push(texture);
+            let index = data.material_textures.len() - 1;
+            textures.insert((image, srgb), index);
+            Ok(index)
+        };
+
+    for m in document.materials() {
+        let pbr = m.pbr_metallic_roughness();
+        let usage = |slot: &str| format!("{} {}", m.name().unwrap_or("material"), slot);
+        let material = Material {
+            name: m.name().unwrap_or_default().to_string(),
+            base_color_factor: pbr.base_color_factor().into(),
+            base_color_texture: (pbr.base_color_texture())
+                .map(|t| load_texture(data, t.texture(), usage("base color"), true))
+                .transpose()?,
+            metallic_factor: pbr.metallic_factor(),
+            roughness_factor: pbr.roughness_factor(),
+            metallic_roughness_texture: (pbr.metallic_roughness_texture())
+                .map(|t| load_texture(data, t.texture(), usage("metallic roughness"), false))
+                .transpose()?,
+            normal_texture: (m.normal_texture())
+                .map(|t| load_texture(data, t.texture(), usage("normal"), false))
+                .transpose()?,
+            normal_scale: m.normal_texture().map_or(1.0, |t| t.scale()),
+            occlusion_texture: (m.occlusion_texture())
+                .map(|t| load_texture(data, t.texture(), usage("occlusion"), false))
+                .transpose()?,
+            occlusion_strength: m.occlusion_texture().map_or(1.0, |t| t.strength()),
+            emissive_factor: m.emissive_factor().into(),
+            emissive_texture: (m.emissive_texture())
+                .map(|t| load_texture(data, t.texture(), usage("emissive"), true))
+                .transpose()?,
+        };
+        data.materials.push(material);
+    }
+
+    // glTF uses a Y-up coordinate system where models face +Z, while our scene treats Z
+    // as up and looks at models from +Y. This maps (x, y, z) to (-x, z, y).
+    let y_up_to_z_up = Mat4::from_angle_z(Deg(180.0)) * Mat4::from_angle_x(Deg(90.0));
+
+    match document.default_scene().or_else(|| document.scenes().next()) {
+        Some(scene) => {
+            for node in scene.nodes() {
+                load_gltf_node(mesh, &buffers, &node, y_up_to_z_up, first_material);
+            }
+        }
+        // A file without scenes is still a valid library of meshes.
+        None => {
+            for gltf_mesh in document.meshes() {
+                load_gltf_mesh(mesh, &buffers, &gltf_mesh, y_up_to_z_up, first_material);
+            }
+        }
     }
 
     Ok(())
+}
+
+fn load_gltf_node(
+    mesh: &mut Mesh,
+    buffers: &[gltf::buffer::Data],
+    node: &gltf::Node,
+    parent_transform: Mat4,
+    first_material: usize,
+) {
+    let transform = parent_transform * Mat4::from(node.transform().matrix());
+
+    if let Some(gltf_mesh) = node.mesh() {
+        load_gltf_mesh(mesh, buffers, &gltf_mesh, transform, first_material);
+    }
+
+    for child in node.children() {
+        load_gltf_node(mesh, buffers, &child, transform, first_material);
+    }
+}
+
+fn load_gltf_mesh(
+    mesh: &mut Mesh,
+    buffers: &[gltf::buffer::Data],
+    gltf_mesh: &gltf::Mesh,
+    transform: Mat4,
+    first_material: usize,
+) {
+    for primitive in gltf_mesh.primitives() {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            warn!(
+                "Skipping primitive of mesh '{}' with unsupported mode {:?}.",
+                gltf_mesh.name().unwrap_or_default(),
+                primitive.mode()
+            );
+            continue;
+        }
+
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+        let Some(positions) = reader.read_positions() else {
+            warn!(
+                "Skipping primitive of mesh '{}' without positions.",
+                gltf_mesh.name().unwrap_or_default()
+            );
+            continue;
+        };
+
+        let mut tex_coords = reader.read_tex_coords(0).map(|t| t.into_f32());
+        let mut colors = reader.read_colors(0).map(|c| c.into_rgb_f32());
+        let mut normals = reader.read_normals();
+
+        // Normals are directions, so they are transformed by the inverse transpose of
+        // the transform. This keeps them perpendicular to the surface under non-uniform scaling.
+        let normal_transform = transform.invert().unwrap_or(transform).transpose();
+
+        let first_vertex = mesh.vertices.len() as u32;
+        for position in positions {
+            let pos = transform * vec4(position[0], position[1], position[2], 1.0);
+            let tex_coord = tex_coords.as_mut().and_then(|t| t.next()).unwrap_or([0.0, 0.0]);
+            let color = colors.as_mut().and_then(|c| c.next()).unwrap_or([1.0, 1.0, 1.0]);
+            // Primitives without normals get smooth normals generated after loading.
+            let normal = normals
+                .as_mut()
+                .and_then(|n| n.next())
+                .map(|n| (normal_transform * vec4(n[0], n[1], n[2], 0.0)).truncate())
+                .filter(|n| n.magnitude2() > 0.0)
+                .map(|n| n.normalize())
+                .unwrap_or_else(Vec3::zero);
+
+            mesh.vertices.push(Vertex {
+                pos: pos.truncate() / pos.w,
+                color: color.into(),
+                // glTF texture coordinates already have their origin in the top left corner.
+                tex_coord: tex_coord.into(),
+                normal,
+            });
+        }
+
+        let vertex_count = mesh.vertices.len() as u32 - first_vertex;
+        let first_index = mesh.indices.len() as u32;
+        match reader.read_indices() {
+            Some(indices) => mesh
+                .indices
+                .extend(indices.into_u32().map(|i| first_vertex + i)),
+            // Non-indexed primitives draw their vertices in order.
+            None => mesh
+                .indices
+                .extend(first_vertex..first_vertex + vertex_count),
+        }
+
+        mesh.primitives.push(Primitive {
+            first_index,
+            index_count: mesh.indices.len() as u32 - first_index,
+            material: primitive.material().index().map(|m| first_material + m),
+        });
+    }
+}
+
+/// Converts an image decoded by the glTF importer to the 8-bit RGBA layout of textures.
+fn texture_data_from_gltf_image(image: &gltf::image::Data, name: String) -> Result<TextureData> {
+    use gltf::image::Format;
+
+    // 16-bit channels are stored as native endian u16, floats as native endian f32.
+    let u16_to_u8 = |b: &[u8]| (u16::from_ne_bytes([b[0], b[1]]) >> 8) as u8;
+    let f32_to_u8 = |b: &[u8]| {
+        (f32::from_ne_bytes([b[0], b[1], b[2], b[3]]).clamp(0.0, 1.0) * 255.0).round() as u8
+    };
+
+    let pixels: Vec<u8> = match image.format {
+        Format::R8G8B8A8 => image.pixels.clone(),
+        Format::R8G8B8 => image
+            .pixels
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], u8::MAX])
+            .collect(),
+        Format::R8G8 => image
+            .pixels
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        Format::R8 => image
+            .pixels
+            .iter()
+            .flat_map(|p| [*p, *p, *p, u8::MAX])
+            .collect(),
+        Format::R16G16B16A16 => image.pixels.chunks_exact(2).map(u16_to_u8).collect(),
+        Format::R16G16B16 => image
+            .pixels
+            .chunks_exact(6)
+            .flat_map(|p| [u16_to_u8(&p[0..]), u16_to_u8(&p[2..]), u16_to_u8(&p[4..]), u8::MAX])
+            .collect(),
+        Format::R16G16 => image
+            .pixels
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let (l, a) = (u16_to_u8(&p[0..]), u16_to_u8(&p[2..]));
+                [l, l, l, a]
+            })
+            .collect(),
+        Format::R16 => image
+            .pixels
+            .chunks_exact(2)
+            .flat_map(|p| {
+                let l = u16_to_u8(p);
+                [l, l, l, u8::MAX]
+            })
+            .collect(),
+        Format::R32G32B32A32FLOAT => image.pixels.chunks_exact(4).map(f32_to_u8).collect(),
+        Format::R32G32B32FLOAT => image
+            .pixels
+            .chunks_exact(12)
+            .flat_map(|p| [f32_to_u8(&p[0..]), f32_to_u8(&p[4..]), f32_to_u8(&p[8..]), u8::MAX])
+            .collect(),
+    };
+
+    if pixels.len() != (image.width * image.height * 4) as usize {
+        return Err(anyhow!("Invalid glTF image data ({}x{}).", image.width, image.height));
+    }
+
+    Ok(TextureData {
+        name,
+        pixels,
+        width: image.width,
+        height: image.height,
+        // Textures holding data instead of colors are marked as linear by `load_gltf`.
+        srgb: true,
+    })
 }
\ No newline at end of file
@@ -0,0 +1,176 @@
+use std::path::Path;
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_3::*;
+
+use crate::app::AppData;
+
+use super::buffers::buffer::create_buffer;
+use super::commands::{begin_single_time_commands, end_single_time_commands};
+use super::image::{create_image, create_image_view};
+use super::screenshot::{to_rgba, write_png};
+
+/// The format of the offscreen color target. It maps directly onto an 8-bit RGBA PNG,
+/// so the rendered pixels can be written out without any conversion.
+pub const OFFSCREEN_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// Creates the image that a headless renderer draws into instead of a swapchain.
+///
+/// Without a window there is no surface and therefore no swapchain to get images from.
+/// Instead, a single color image is created and stored in place of the swapchain images,
+/// so that the render graph and command buffers can be built exactly as they are for a
+/// window. The multisampled color attachment is resolved into this image.
+///
+/// The image can be used as a transfer source so its contents can be copied into a
+/// host-visible buffer and saved to a file (see `save_offscreen_image`).
+pub unsafe fn create_offscreen_target(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    width: u32,
+    height: u32,
+) -> Result<()> {
+    let image = create_image(
+        instance,
+        device,
+        data,
+        "offscreen image",
+        width,
+        height,
+        1,
+        vk::SampleCountFlags::_1,
+        OFFSCREEN_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.swapchain_format = OFFSCREEN_FORMAT;
+    data.swapchain_extent = vk::Extent2D { width, height };
+    data.swapchain_images = vec![image.handle()];
+    data.swapchain_image_views = vec![create_image_view(
+        device,
+        data,
+        image.handle(),
+        OFFSCREEN_FORMAT,
+        vk::ImageAspectFlags::COLOR,
+        1,
+    )?];
+    data.offscreen_image = image;
+    data.capture_supported = true;
+
+    Ok(())
+}
+
+/// Copies the offscreen image into a host-visible buffer and writes it to a PNG file.
+///
+/// The render graph leaves the resolved image in the TRANSFER_SRC_OPTIMAL layout, so it
+/// only needs a barrier to make the color attachment writes visible to the copy.
+pub unsafe fn save_offscreen_image(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    path: &Path,
+) -> Result<()> {
+    // Make sure the last frame has finished rendering before reading the image.
+    device.device_wait_idle()?;
+
+    let image = data.swapchain_images[0];
+    let vk::Extent2D { width, height } = data.swapchain_extent;
+    let size = (width * height * 4) as u64;
+
+    let staging_buffer = create_buffer(
+        instance,
+        device,
+        data,
+        "offscreen staging buffer",
+        size,
+        vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    )?;
+
+    let command_buffer = begin_single_time_commands(device, data)?;
+
+    let subresource = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    // The layout stays the same, the barrier only orders the copy after the rendering.
+    let image_barrier = vk::ImageMemoryBarrier::builder()
+        .image(image)
+        .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .subresource_range(subresource)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_access_mask(vk::AccessFlags::TRANSFER_READ);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[image_barrier],
+    );
+
+    let image_subresource = vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(0)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    let region = vk::BufferImageCopy::builder()
+        .buffer_offset(0)
+        .buffer_row_length(0)
+        .buffer_image_height(0)
+        .image_subresource(image_subresource)
+        .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+        .image_extent(vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        });
+
+    device.cmd_copy_image_to_buffer(
+        command_buffer,
+        image,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        staging_buffer.handle(),
+        &[region],
+    );
+
+    // Makes the copied data visible to the CPU once the commands have finished.
+    let buffer_barrier = vk::BufferMemoryBarrier::builder()
+        .buffer(staging_buffer.handle())
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .offset(0)
+        .size(size)
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::HOST_READ);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::HOST,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[buffer_barrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+
+    end_single_time_commands(device, data, command_buffer)?;
+
+    let memory = data.allocator.mapped(staging_buffer.allocation())?;
+    let pixels = std::slice::from_raw_parts(memory.cast::<u8>(), size as usize);
+
+    let rgba = to_rgba(pixels, data.swapchain_format)?;
+
+    write_png(path, &rgba, data.swapchain_extent)
+}
@@ -0,0 +1,274 @@
+use anyhow::Result;
+use vulkanalia::prelude::v1_3::*;
+
+use crate::app::AppData;
+
+use super::buffers::depth_buffer::get_depth_format;
+use super::render_graph::{
+    Attachment, BufferAccess, ImageAccess, ImageDesc, LoadOp, PassContext, RenderGraph,
+};
+use super::screenshot::record_capture_pass;
+use super::shadow::get_shadow_map_format;
+
+/// The pass that draws the scene into the shadow map, as seen from the light.
+pub const SHADOW_PASS: &str = "shadow";
+
+/// The pass that draws the scene into the swapchain image.
+pub const MAIN_PASS: &str = "main";
+
+/// The pass that copies the swapchain image of frames taking a screenshot.
+pub const CAPTURE_PASS: &str = "capture";
+
+/// Declares the passes that draw a frame and compiles them into `data.render_graph`.
+///
+/// The shadow pass renders the shadow map, which the main pass samples, so the graph runs
+/// it first. The main pass draws into a multisampled color image that is resolved into
+/// the swapchain image, and tests against a depth image. Both are transient: the graph
+/// creates them, and they aren't kept after the pass. The capture pass then copies the
+/// swapchain image when the frame takes a screenshot.
+///
+/// The graph depends on the swapchain and the shadow map, so it is rebuilt along with them.
+/// The old graph must have been destroyed before.
+pub unsafe fn create_render_graph(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let mut graph = RenderGraph::default();
+
+    let swapchain = graph.import_swapchain(data);
+    let color = graph.create_image(
+        "color",
+        ImageDesc {
+            format: data.swapchain_format,
+            extent: data.swapchain_extent,
+            samples: data.msaa_samples,
+        },
+    );
+    let depth = graph.create_image(
+        "depth",
+        ImageDesc {
+            format: get_depth_format(instance, data)?,
+            extent: data.swapchain_extent,
+            samples: data.msaa_samples,
+        },
+    );
+    let shadow_map = graph.import_image(
+        "shadow map",
+        data.shadow_map.handle(),
+        data.shadow_map_view.handle(),
+        get_shadow_map_format(instance, data)?,
+        vk::Extent2D {
+            width: data.shadow_map_size,
+            height: data.shadow_map_size,
+        },
+    );
+
+    let instances = graph.import_buffer("instances", |_, frame| frame.instance_buffer.handle());
+    let uniforms = graph.import_buffer("uniforms", |_, frame| frame.uniform_buffer.handle());
+
+    // The far plane, so everything that is drawn is closer.
+    let depth_clear_value = vk::ClearValue {
+        depth_stencil: vk::ClearDepthStencilValue {
+            depth: 1.0,
+            stencil: 0,
+        },
+    };
+
+    let color_clear_value = vk::ClearValue {
+        color: vk::ClearColorValue {
+            float32: [0.02, 0.02, 0.02, 1.0],
+        },
+    };
+
+    // Contrary to the depth buffer of the main pass, the depth is what we're after.
+    graph
+        .add_pass(SHADOW_PASS, record_shadow_pass)
+        .depth_attachment(Attachment {
+            image: shadow_map,
+            load: LoadOp::Clear(depth_clear_value),
+            store: true,
+            resolve: None,
+        })
+        .buffer(instances, BufferAccess::VertexInput)
+        .buffer(uniforms, BufferAccess::Uniform);
+
+    // Only the resolved color is kept, the multisampled color and the depth are thrown away.
+    graph
+        .add_pass(MAIN_PASS, record_main_pass)
+        .color_attachment(Attachment {
+            image: color,
+            load: LoadOp::Clear(color_clear_value),
+            store: false,
+            resolve: Some(swapchain),
+        })
+        .depth_attachment(Attachment {
+            image: depth,
+            load: LoadOp::Clear(depth_clear_value),
+            store: false,
+            resolve: None,
+        })
+        .image(shadow_map, ImageAccess::Sampled)
+        .buffer(instances, BufferAccess::VertexInput)
+        .buffer(uniforms, BufferAccess::Uniform);
+
+    // The pass only copies when a screenshot is taken, but declaring it for every frame
+    // saves rebuilding the graph for a screenshot. That costs a layout transition.
+    if data.capture_supported {
+        graph
+            .add_pass(CAPTURE_PASS, record_capture_pass)
+            .image(swapchain, ImageAccess::TransferSrc);
+    }
+
+    graph.compile(device, data)?;
+    data.render_graph = graph;
+
+    Ok(())
+}
+
+/// Records the shadow pass, which draws every primitive into the shadow map as seen from
+/// the directional light. Materials don't matter for the depth, so they aren't bound.
+unsafe fn record_shadow_pass(ctx: &PassContext) {
+    let (device, data, command_buffer) = (ctx.device, ctx.data, ctx.command_buffer);
+
+    device.cmd_bind_pipeline(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        data.shadow_pipeline.handle(),
+    );
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        data.shadow_pipeline_layout,
+        0,
+        &[ctx.frame.descriptor_set],
+        &[],
+    );
+    device.cmd_bind_vertex_buffers(
+        command_buffer,
+        1,
+        &[ctx.frame.instance_buffer.handle()],
+        &[0],
+    );
+
+    for batch in ctx.batches {
+        let mesh = &data.scene.meshes[batch.mesh];
+
+        device.cmd_bind_vertex_buffers(command_buffer, 0, &[mesh.vertex_buffer.handle()], &[0]);
+        device.cmd_bind_index_buffer(
+            command_buffer,
+            mesh.index_buffer.handle(),
+            0,
+            vk::IndexType::UINT32,
+        );
+
+        for primitive in &mesh.primitives {
+            device.cmd_draw_indexed(
+                command_buffer,
+                primitive.index_count,
+                batch.instance_count,
+                primitive.first_index,
+                0,
+                batch.first_instance,
+            );
+        }
+    }
+}
+
+/// Records the main pass, which draws every primitive with its material.
+///
+/// The draws follow the frame's plan (see `plan_draws`), which leaves out the objects that
+/// occlusion culling found hidden. The first draws of the plan are counted by pipeline
+/// statistics queries, and the first batches are within occlusion queries.
+unsafe fn record_main_pass(ctx: &PassContext) {
+    let (device, data, command_buffer) = (ctx.device, ctx.data, ctx.command_buffer);
+    let (frame, draws) = (ctx.frame, &ctx.frame.draws);
+
+    // The command buffer tracks state changes (e.g., pipeline bindings) and
+    // ensures dependencies are managed correctly.
+    // The pipeline is meant to operate on attachments, so it is bound once the graph
+    // has begun the pass.
+    device.cmd_bind_pipeline(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        data.pipeline.handle(),
+    );
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        data.pipeline_layout,
+        0,
+        &[frame.descriptor_set],
+        &[],
+    );
+
+    // The instance data of every object stays bound to binding 1, the draws
+    // pick their range of it with the first instance.
+    device.cmd_bind_vertex_buffers(command_buffer, 1, &[frame.instance_buffer.handle()], &[0]);
+
+    let mut draw = 0;
+    for (i, batch) in draws.batches.iter().enumerate() {
+        let mesh = &data.scene.meshes[batch.mesh];
+
+        // The query counts the samples of all the primitives of the object.
+        let occlusion = i < draws.objects.len();
+        if occlusion {
+            device.cmd_begin_query(
+                command_buffer,
+                frame.occlusion_pool,
+                i as u32,
+                vk::QueryControlFlags::empty(),
+            );
+        }
+
+        device.cmd_bind_vertex_buffers(command_buffer, 0, &[mesh.vertex_buffer.handle()], &[0]);
+        device.cmd_bind_index_buffer(
+            command_buffer,
+            mesh.index_buffer.handle(),
+            0,
+            vk::IndexType::UINT32,
+        );
+
+        // Every primitive is drawn separately with its own material bound to set 1,
+        // once for all the objects drawing the mesh.
+        for primitive in &mesh.primitives {
+            let material = primitive.material.expect("Primitive without a material.");
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                data.pipeline_layout,
+                1,
+                &[data.material_descriptor_sets[material]],
+                &[],
+            );
+
+            let statistics = draw < draws.statistics.len();
+            if statistics {
+                device.cmd_begin_query(
+                    command_buffer,
+                    frame.statistics_pool,
+                    draw as u32,
+                    vk::QueryControlFlags::empty(),
+                );
+            }
+
+            device.cmd_draw_indexed(
+                command_buffer,
+                primitive.index_count,
+                batch.instance_count,
+                primitive.first_index,
+                0,
+                batch.first_instance,
+            );
+
+            if statistics {
+                device.cmd_end_query(command_buffer, frame.statistics_pool, draw as u32);
+            }
+            draw += 1;
+        }
+
+        if occlusion {
+            device.cmd_end_query(command_buffer, frame.occlusion_pool, i as u32);
+        }
+    }
+}
@@ -4,13 +4,24 @@ use super::errors::SuitabilityError;
 use super::queue::QueueFamilyIndices;
 use super::swapchain::SwapchainSupport;
 use crate::app::AppData;
+use crate::config::RendererConfig;
 use anyhow::{anyhow, Result};
 use log::*;
 use vulkanalia::prelude::v1_3::*;
 
 pub const DEVICE_EXTENSIONS: &[vk::ExtensionName] = &[vk::KHR_SWAPCHAIN_EXTENSION.name];
 
-pub unsafe fn pick_physical_device(instance: &Instance, data: &mut AppData) -> Result<()> {
+/// Picks the first suitable physical device. The configured MSAA samples cap the number
+/// of samples used for multisampling, which is otherwise the most the device supports.
+/// Dynamic rendering is used when the device supports it, unless the config asks for
+/// render passes. Pipeline statistics queries are used if the config asks for them and
+/// the device supports them.
+/// Occlusion queries are supported by every device, the config decides alone.
+pub unsafe fn pick_physical_device(
+    instance: &Instance,
+    data: &mut AppData,
+    config: &RendererConfig,
+) -> Result<()> {
     for physical_device in instance.enumerate_physical_devices()? {
         let properties = instance.get_physical_device_properties(physical_device);
 
@@ -22,7 +33,26 @@ pub unsafe fn pick_physical_device(instance: &Instance, data: &mut AppData) -> R
         } else {
             info!("Selected physical device  ('{}').", properties.device_name);
             data.physical_device = physical_device;
-            data.msaa_samples = get_max_msaa_samples(instance, data);
+            data.msaa_samples = get_max_msaa_samples(instance, data).min(config.msaa_samples);
+            info!("Using {:?} MSAA samples.", data.msaa_samples);
+            data.dynamic_rendering =
+                !config.legacy_render_pass && supports_dynamic_rendering(instance, physical_device);
+            if data.dynamic_rendering {
+                info!("Using dynamic rendering.");
+            } else {
+                info!("Using render passes.");
+            }
+            let features = instance.get_physical_device_features(physical_device);
+            data.pipeline_statistics =
+                config.pipeline_statistics && features.pipeline_statistics_query == vk::TRUE;
+            if config.pipeline_statistics && !data.pipeline_statistics {
+                warn!("Pipeline statistics queries aren't supported.");
+            }
+            data.occlusion_culling = config.occlusion_culling;
+            data.non_uniform_indexing = supports_non_uniform_indexing(instance, physical_device);
+            if !data.non_uniform_indexing {
+                warn!("Non-uniform indexing isn't supported, instances can't pick a texture.");
+            }
             return Ok(());
         }
     }
@@ -37,14 +67,18 @@ pub unsafe fn check_physical_device(
     // Check if the physical device contains the needed queue indices (for graphics and presentation queues)
     QueueFamilyIndices::get(instance, data, physical_device)?;
 
-    // Check if the physical device supports swapchains
-    let support = SwapchainSupport::get(instance, data, physical_device)?;
-    if support.formats.is_empty() || support.present_modes.is_empty() {
-        return Err(anyhow!(SuitabilityError("Insufficient swapchain support.")));
-    }
+    // A headless renderer draws into an offscreen image and never presents,
+    // so neither swapchain support nor the swapchain extension are needed.
+    if !data.headless {
+        // Check if the physical device supports swapchains
+        let support = SwapchainSupport::get(instance, data, physical_device)?;
+        if support.formats.is_empty() || support.present_modes.is_empty() {
+            return Err(anyhow!(SuitabilityError("Insufficient swapchain support.")));
+        }
 
-    // Check if the physical device has the required extensions
-    check_physical_device_extensions(instance, physical_device)?;
+        // Check if the physical device has the required extensions
+        check_physical_device_extensions(instance, physical_device)?;
+    }
 
     let features = instance.get_physical_device_features(physical_device);
     if features.sampler_anisotropy != vk::TRUE {
@@ -54,6 +88,47 @@ pub unsafe fn check_physical_device(
     Ok(())
 }
 
+/// Whether the physical device can index arrays of sampled images with an index that
+/// differs between the invocations of a draw. Instances need it to pick their own
+/// texture (see `MAX_INSTANCE_TEXTURES`).
+///
+/// The Vulkan 1.2 features can only be queried from a device that supports Vulkan 1.2.
+pub unsafe fn supports_non_uniform_indexing(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+) -> bool {
+    let properties = instance.get_physical_device_properties(physical_device);
+    if properties.api_version < vk::make_version(1, 2, 0) {
+        return false;
+    }
+
+    let mut features_12 = vk::PhysicalDeviceVulkan12Features::builder();
+    let mut features_2 = vk::PhysicalDeviceFeatures2::builder().push_next(&mut features_12);
+    instance.get_physical_device_features2(physical_device, &mut features_2);
+
+    features_12.shader_sampled_image_array_non_uniform_indexing == vk::TRUE
+}
+
+/// Whether the physical device supports the Vulkan 1.3 dynamic rendering and
+/// synchronization2 features, which draw without render pass and framebuffer objects.
+///
+/// The Vulkan 1.3 features can only be queried from a device that supports Vulkan 1.3.
+pub unsafe fn supports_dynamic_rendering(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+) -> bool {
+    let properties = instance.get_physical_device_properties(physical_device);
+    if properties.api_version < vk::make_version(1, 3, 0) {
+        return false;
+    }
+
+    let mut features_13 = vk::PhysicalDeviceVulkan13Features::builder();
+    let mut features_2 = vk::PhysicalDeviceFeatures2::builder().push_next(&mut features_13);
+    instance.get_physical_device_features2(physical_device, &mut features_2);
+
+    features_13.dynamic_rendering == vk::TRUE && features_13.synchronization2 == vk::TRUE
+}
+
 pub unsafe fn check_physical_device_extensions(
     instance: &Instance,
     physical_device: vk::PhysicalDevice,
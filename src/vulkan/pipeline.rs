@@ -1,9 +1,14 @@
 use anyhow::Result;
 use vulkanalia::prelude::v1_3::*;
-use vulkanalia::bytecode::Bytecode;
 
 use crate::app::AppData;
+use crate::config::ShadowSettings;
 
+use super::buffers::instance_buffer::InstanceData;
+use super::errors::ReflectionError;
+use super::passes::{MAIN_PASS, SHADOW_PASS};
+use super::resources::Pipeline;
+use super::shader::Shader;
 use super::vertex::Vertex;
 
 /// The graphics pipeline in Vulkan is a sequence of steps that the GPU follows to 
@@ -20,28 +25,43 @@ use super::vertex::Vertex;
 /// 
 /// Must output data to the color attachment in the right format (same as in the render pass
 /// and swapchain).
+///
+/// The render graph tells which render pass the pipeline draws in (see passes.rs). With
+/// dynamic rendering there is no render pass, so the pipeline names the formats of the
+/// attachments it renders to instead.
+///
+/// The shaders are the last code that compiled (see shader.rs). When the pipeline can't be
+/// created, the current one is left as it is.
 pub unsafe fn create_pipeline(
     device: &Device,
     data: &mut AppData
 ) -> Result<()> {
-    let vert = include_bytes!("shaders/vert.spv");
-    let frag = include_bytes!("shaders/frag.spv");
-
-    let vert_module = create_shader_module(device, vert)?;
-    let frag_module = create_shader_module(device, frag)?;
+    // Both stages are entry points of the same module.
+    let module = create_shader_module(device, data.shaders.code(Shader::Main))?;
 
     let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
         .stage(vk::ShaderStageFlags::VERTEX)
-        .module(vert_module)
-        .name(b"main\0");
-
+        .module(module)
+        .name(b"vs_main\0");
+
+    // Only `fs_main` lets instances pick their texture, which needs non-uniform indexing.
+    let frag_name: &[u8] = if data.non_uniform_indexing {
+        b"fs_main\0"
+    } else {
+        b"fs_main_uniform\0"
+    };
     let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
         .stage(vk::ShaderStageFlags::FRAGMENT)
-        .module(frag_module)
-        .name(b"main\0");
-
-    let binding_descriptions = &[Vertex::binding_description()];
-    let attribute_descriptions = Vertex::attribute_descriptions();
+        .module(module)
+        .name(frag_name);
+
+    // The vertices come from binding 0 and the per-instance data from binding 1. The
+    // attributes must match the inputs of the vertex shader.
+    let binding_descriptions = &[Vertex::binding_description(), InstanceData::binding_description()];
+    let attribute_descriptions = data
+        .shaders
+        .reflection(Shader::Main)
+        .vertex_attributes(&vertex_attributes())?;
     let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
         .vertex_binding_descriptions(binding_descriptions)
         .vertex_attribute_descriptions(&attribute_descriptions);
@@ -106,17 +126,6 @@ pub unsafe fn create_pipeline(
         .attachments(attachments)
         .blend_constants([0.0, 0.0, 0.0, 0.0]);
 
-    // The pipeline layout is like a blueprint that defines:
-    // 1. Descriptor sets: How resources like textures and uniform buffers are accessed 
-    //    by the shaders.
-    // 2. Push constants: Small amounts of data sent to shaders for per-draw customization.
-    let set_layouts = &[data.descriptor_set_layout];
-    let layout_info = vk::PipelineLayoutCreateInfo::builder()
-        .set_layouts(set_layouts);
-
-    data.pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
-
-
     let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
         
         // Specifies if the depth of new fragments should be compared to the depth buffer
@@ -142,8 +151,10 @@ pub unsafe fn create_pipeline(
         .stencil_test_enable(false);
 
 
+    let target = data.render_graph.target(MAIN_PASS)?;
+
     let stages = &[vert_stage, frag_stage];
-    let info = vk::GraphicsPipelineCreateInfo::builder()
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
         .stages(stages)
         .vertex_input_state(&vertex_input_state)
         .input_assembly_state(&input_assembly_state)
@@ -155,29 +166,205 @@ pub unsafe fn create_pipeline(
         .depth_stencil_state(&depth_stencil_state)
 
         // Link this pipeline to the correct render pass.
-        .render_pass(data.render_pass)
+        .render_pass(target.render_pass)
 
         // And the right subpass.
         .subpass(0);
 
-    data.pipeline = device.create_graphics_pipelines(vk::PipelineCache::null(), 
-        &[info], None)?.0[0];
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+        .color_attachment_formats(&target.color_formats)
+        .depth_attachment_format(target.depth_format);
 
-    device.destroy_shader_module(vert_module, None);
-    device.destroy_shader_module(frag_module, None);
+    if data.dynamic_rendering {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    let result = device.create_graphics_pipelines(data.pipeline_cache, &[info], None);
+    device.destroy_shader_module(module, None);
+
+    // Replacing the pipeline leaves the old one to the deletion queue, as frames in flight
+    // may still use it.
+    data.pipeline = Pipeline::new(result?.0[0], &data.deletion_queue);
+    data.debug_utils.set_name(device, data.pipeline.handle(), "main pipeline");
+
+    Ok(())
+}
+
+/// The pipeline layout is like a blueprint that defines:
+/// 1. Descriptor sets: How resources like textures and uniform buffers are accessed 
+///    by the shaders.
+/// 2. Push constants: Small amounts of data sent to shaders for per-draw customization.
+///
+/// The descriptor sets and push constants are the ones the shaders used when they were
+/// loaded. The layout outlives the pipelines that are rebuilt when the shaders change,
+/// which is why a changed shader has to fit it (see shader.rs).
+pub unsafe fn create_pipeline_layout(device: &Device, data: &mut AppData) -> Result<()> {
+    let layout = data.shaders.layout(Shader::Main);
+    let set_layouts = set_layouts(data, layout.set_count())?;
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(&set_layouts)
+        .push_constant_ranges(&layout.push_constants);
+
+    data.pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
 
     Ok(())
 }
 
 unsafe fn create_shader_module(
     device: &Device,
-    bytecode: &[u8],
+    code: &[u32],
 ) -> Result<vk::ShaderModule> {
-    let bytecode = Bytecode::new(bytecode).unwrap();
+    // The code size is in bytes.
     let info = vk::ShaderModuleCreateInfo::builder()
-        .code_size(bytecode.code_size())
-        .code(bytecode.code());
+        .code_size(std::mem::size_of_val(code))
+        .code(code);
 
     let module = device.create_shader_module(&info, None)?;
     Ok(module)
-}
\ No newline at end of file
+}
+/// The pipeline of the shadow pass. It only has a vertex shader, which transforms the
+/// vertices into the light's clip space. Without a fragment shader the rasterizer still
+/// writes the depth, which is all the shadow map needs.
+///
+/// The depth bias of the shadow settings is added to the depth written to the shadow map.
+/// Because of the limited resolution of the shadow map, a surface facing the light would
+/// otherwise be compared with depths slightly in front of it and shadow itself.
+pub unsafe fn create_shadow_pipeline(
+    device: &Device,
+    data: &mut AppData,
+    settings: &ShadowSettings,
+) -> Result<()> {
+    let module = create_shader_module(device, data.shaders.code(Shader::Shadow))?;
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(module)
+        .name(b"vs_main\0");
+
+    // Only the attributes the shader reads are passed, the positions and the model
+    // matrices of the instances.
+    let binding_descriptions = &[Vertex::binding_description(), InstanceData::binding_description()];
+    let attribute_descriptions = data
+        .shaders
+        .reflection(Shader::Shadow)
+        .vertex_attributes(&vertex_attributes())?;
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(binding_descriptions)
+        .vertex_attribute_descriptions(&attribute_descriptions);
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    let size = data.shadow_map_size;
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(size as f32)
+        .height(size as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(vk::Extent2D { width: size, height: size });
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    // Both sides of the triangles cast shadows, as models aren't always closed.
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(true)
+        .depth_bias_constant_factor(settings.depth_bias_constant)
+        .depth_bias_slope_factor(settings.depth_bias_slope)
+        .depth_bias_clamp(0.0);
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(vk::SampleCountFlags::_1);
+
+    // There are no color attachments to blend.
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .attachments(&[] as &[vk::PipelineColorBlendAttachmentState]);
+
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(true)
+        .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    let target = data.render_graph.target(SHADOW_PASS)?;
+
+    let stages = &[vert_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .color_blend_state(&color_blend_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .layout(data.shadow_pipeline_layout)
+        .render_pass(target.render_pass)
+        .subpass(0);
+
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+        .depth_attachment_format(target.depth_format);
+
+    if data.dynamic_rendering {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    let result = device.create_graphics_pipelines(data.pipeline_cache, &[info], None);
+    device.destroy_shader_module(module, None);
+
+    data.shadow_pipeline = Pipeline::new(result?.0[0], &data.deletion_queue);
+    data.debug_utils.set_name(device, data.shadow_pipeline.handle(), "shadow pipeline");
+
+    Ok(())
+}
+
+/// The layout of the shadow pipeline. The shadow pass only uses the matrices of set 0.
+pub unsafe fn create_shadow_pipeline_layout(device: &Device, data: &mut AppData) -> Result<()> {
+    let layout = data.shaders.layout(Shader::Shadow);
+    let set_layouts = set_layouts(data, layout.set_count())?;
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(&set_layouts)
+        .push_constant_ranges(&layout.push_constants);
+
+    data.shadow_pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+
+    Ok(())
+}
+
+/// The layouts of the first `count` descriptor sets, set 0 bound per frame and set 1
+/// per material.
+fn set_layouts(data: &AppData, count: usize) -> Result<Vec<vk::DescriptorSetLayout>> {
+    let set_layouts = [data.descriptor_set_layout, data.material_descriptor_set_layout];
+    match set_layouts.get(..count) {
+        Some(set_layouts) => Ok(set_layouts.to_vec()),
+        None => Err(ReflectionError::UnknownSet(count - 1).into()),
+    }
+}
+
+/// Every vertex attribute the vertex and instance buffers provide. A pipeline uses the
+/// ones its vertex shader reads.
+fn vertex_attributes() -> Vec<vk::VertexInputAttributeDescription> {
+    [
+        &Vertex::attribute_descriptions()[..],
+        &InstanceData::attribute_descriptions()[..],
+    ]
+    .concat()
+}
@@ -0,0 +1,110 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use anyhow::Result;
+use log::*;
+use vulkanalia::prelude::v1_3::*;
+
+use crate::app::AppData;
+
+use super::errors::PipelineCacheError;
+
+/// The size of the header at the start of the pipeline cache data, as laid out by
+/// `vk::PipelineCacheHeaderVersionOne`.
+const HEADER_SIZE: usize = 16 + vk::UUID_SIZE;
+
+/// Creates the pipeline cache that every pipeline is created with.
+///
+/// Creating a pipeline compiles its shaders into code for the GPU, which is slow. The
+/// driver keeps the results in the pipeline cache, so creating the same pipeline again,
+/// e.g. when the swapchain is recreated, is mostly a lookup. The cache is saved to a file
+/// when the app is destroyed (see `save_pipeline_cache`) and loaded from it here, so the
+/// next start benefits from it as well.
+///
+/// The data is only meaningful to the driver and device that wrote it. It starts with a
+/// header naming them, and the file is ignored if it doesn't match the current ones.
+pub unsafe fn create_pipeline_cache(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    path: &Path,
+) -> Result<()> {
+    let properties = instance.get_physical_device_properties(data.physical_device);
+
+    let initial_data = match fs::read(path) {
+        Ok(bytes) => match validate_pipeline_cache(&bytes, &properties) {
+            Ok(()) => {
+                info!("Loaded pipeline cache from {}.", path.display());
+                bytes
+            }
+            Err(error) => {
+                warn!("Ignoring pipeline cache {}: {}", path.display(), error);
+                Vec::new()
+            }
+        },
+        Err(error) if error.kind() == ErrorKind::NotFound => Vec::new(),
+        Err(error) => {
+            warn!(
+                "Failed to read pipeline cache {}: {}",
+                path.display(),
+                error
+            );
+            Vec::new()
+        }
+    };
+
+    let info = vk::PipelineCacheCreateInfo::builder().initial_data(&initial_data);
+
+    data.pipeline_cache = device.create_pipeline_cache(&info, None)?;
+
+    Ok(())
+}
+
+/// Writes the contents of the pipeline cache to a file, to be loaded on the next start.
+pub unsafe fn save_pipeline_cache(device: &Device, data: &AppData, path: &Path) -> Result<()> {
+    let bytes = device.get_pipeline_cache_data(data.pipeline_cache)?;
+    fs::write(path, bytes)?;
+
+    info!("Saved pipeline cache to {}.", path.display());
+
+    Ok(())
+}
+
+/// Checks that the header of the pipeline cache data was written by the given device.
+///
+/// The header holds its own size and version, followed by the vendor and device IDs and
+/// the pipeline cache UUID of the device. Drivers reject data they can't use themselves,
+/// but not all of them do so gracefully.
+pub fn validate_pipeline_cache(
+    bytes: &[u8],
+    properties: &vk::PhysicalDeviceProperties,
+) -> Result<(), PipelineCacheError> {
+    if bytes.len() < HEADER_SIZE {
+        return Err(PipelineCacheError::TooShort(bytes.len()));
+    }
+
+    let field = |i: usize| u32::from_ne_bytes(bytes[4 * i..4 * i + 4].try_into().unwrap());
+    let (header_size, header_version, vendor_id, device_id) =
+        (field(0), field(1), field(2), field(3));
+    let uuid = &bytes[16..HEADER_SIZE];
+
+    if (header_size as usize) < HEADER_SIZE
+        || header_version != vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+    {
+        return Err(PipelineCacheError::UnsupportedHeader(
+            header_size,
+            header_version,
+        ));
+    }
+
+    if vendor_id != properties.vendor_id || device_id != properties.device_id {
+        return Err(PipelineCacheError::DeviceMismatch(vendor_id, device_id));
+    }
+
+    if uuid != properties.pipeline_cache_uuid.as_slice() {
+        return Err(PipelineCacheError::UuidMismatch);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,306 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use log::*;
+use vulkanalia::prelude::v1_3::*;
+
+use crate::app::AppData;
+
+use super::frame::FrameContext;
+use super::queue::QueueFamilyIndices;
+use super::scene::{Batch, ObjectId};
+
+/// The number of passes the timestamp query pool of a frame has room for. A graph with
+/// more passes isn't timed.
+pub const MAX_TIMED_PASSES: usize = 32;
+
+/// The number of draws and objects the statistics and occlusion query pools of a frame
+/// have room for. The draws beyond them aren't counted, and the objects beyond them are
+/// never culled.
+pub const MAX_QUERIED_DRAWS: usize = 4096;
+
+/// The number of frames an object that was hidden is skipped for. It is drawn again after
+/// that, to find out if it still is.
+pub const OCCLUSION_RETEST_INTERVAL: u32 = 8;
+
+/// What the pipeline statistics queries count.
+const STATISTICS: vk::QueryPipelineStatisticFlags =
+    vk::QueryPipelineStatisticFlags::from_bits_truncate(
+        vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.bits()
+            | vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES.bits()
+            | vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.bits(),
+    );
+
+/// The time the GPU spent in a pass of the render graph.
+#[derive(Clone, Debug)]
+pub struct PassTiming {
+    pub pass: String,
+    pub milliseconds: f64,
+}
+
+/// Timestamp queries
+///
+/// A timestamp query records the time at which the GPU reaches a point in a command
+/// buffer, in ticks of a device specific length (`timestampPeriod` nanoseconds). The
+/// render graph writes one before each pass and one after it (see `RenderGraph::execute`),
+/// the difference is the time the GPU spent in the pass.
+///
+/// Every frame in flight gets its own query pool, like its command buffer. The results are
+/// read once the frame's fence is signaled (see `read_pass_timings`), when they are
+/// available, so reading them never stalls the CPU.
+///
+/// Not every queue supports timestamps. The pools stay null on queues that don't, and
+/// nothing is timed.
+pub unsafe fn create_timestamp_query_pools(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+    let families = instance.get_physical_device_queue_family_properties(data.physical_device);
+    let valid_bits = families[indices.graphics as usize].timestamp_valid_bits;
+
+    if valid_bits == 0 {
+        info!("The graphics queue doesn't support timestamps, the passes aren't timed.");
+        return Ok(());
+    }
+
+    let properties = instance.get_physical_device_properties(data.physical_device);
+    data.timestamp_period = properties.limits.timestamp_period;
+    data.timestamp_valid_bits = valid_bits;
+
+    // Two queries per pass, before and after it.
+    let info = vk::QueryPoolCreateInfo::builder()
+        .query_type(vk::QueryType::TIMESTAMP)
+        .query_count(2 * MAX_TIMED_PASSES as u32);
+
+    for frame in &mut data.frames {
+        frame.timestamp_pool = device.create_query_pool(&info, None)?;
+    }
+
+    Ok(())
+}
+
+/// Reads the timings of the passes the frame recorded the last time it was rendered.
+///
+/// The frame's fence must have been waited for, so the queries have their results. Each
+/// recording is read once, later calls return nothing until the frame is recorded again.
+pub unsafe fn read_pass_timings(
+    device: &Device,
+    data: &mut AppData,
+    frame: usize,
+) -> Result<Vec<PassTiming>> {
+    let frame = &mut data.frames[frame];
+    let passes = std::mem::take(&mut frame.timed_passes);
+    if passes.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let ticks = read_query_results(device, frame.timestamp_pool, 2 * passes.len(), 1)?;
+
+    // Only the lower bits are written, so the timestamps wrap around at their width.
+    let mask = match data.timestamp_valid_bits {
+        64 => u64::MAX,
+        bits => (1 << bits) - 1,
+    };
+
+    Ok(passes
+        .into_iter()
+        .zip(ticks.chunks_exact(2))
+        .map(|(pass, t)| PassTiming {
+            pass,
+            milliseconds: (t[1].wrapping_sub(t[0]) & mask) as f64 * data.timestamp_period as f64
+                / 1_000_000.0,
+        })
+        .collect())
+}
+
+/// What the GPU did for a draw of the main pass, i.e. a primitive of a batch.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DrawStatistics {
+    /// Index into `Scene::meshes`, and into the primitives of the mesh.
+    pub mesh: usize,
+    pub primitive: usize,
+    pub vertex_shader_invocations: u64,
+    /// The primitives that were left after clipping.
+    pub clipping_primitives: u64,
+    pub fragment_shader_invocations: u64,
+}
+
+/// The draws the main pass of a frame records, planned by `plan_draws`.
+#[derive(Clone, Debug, Default)]
+pub struct DrawList {
+    /// Each batch is drawn once per primitive of its mesh.
+    pub batches: Vec<Batch>,
+    /// The objects drawn with an occlusion query, which are the first batches: the query
+    /// of a batch has the batch's index.
+    pub objects: Vec<ObjectId>,
+    /// The mesh and primitive of the draws counted by a pipeline statistics query, which
+    /// are the first draws: the query of a draw has the draw's index.
+    pub statistics: Vec<(usize, usize)>,
+}
+
+/// Pipeline statistics and occlusion queries
+///
+/// A pipeline statistics query counts what the stages of the pipeline do between its
+/// beginning and end, here the vertex shader invocations, the primitives left after
+/// clipping and the fragment shader invocations of every draw of the main pass. An
+/// occlusion query counts the samples that passed the depth test, so an object whose
+/// query counted none was hidden behind the others.
+///
+/// Both are optional (see `pick_physical_device`), their pools stay null when they aren't
+/// used. Like the timestamps, every frame in flight has its own pools, which are read once
+/// the frame's fence is signaled.
+pub unsafe fn create_draw_query_pools(device: &Device, data: &mut AppData) -> Result<()> {
+    let statistics_info = vk::QueryPoolCreateInfo::builder()
+        .query_type(vk::QueryType::PIPELINE_STATISTICS)
+        .query_count(MAX_QUERIED_DRAWS as u32)
+        .pipeline_statistics(STATISTICS);
+
+    let occlusion_info = vk::QueryPoolCreateInfo::builder()
+        .query_type(vk::QueryType::OCCLUSION)
+        .query_count(MAX_QUERIED_DRAWS as u32);
+
+    for frame in &mut data.frames {
+        if data.pipeline_statistics {
+            frame.statistics_pool = device.create_query_pool(&statistics_info, None)?;
+        }
+        if data.occlusion_culling {
+            frame.occlusion_pool = device.create_query_pool(&occlusion_info, None)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Plans the draws of the main pass of a frame.
+///
+/// Without occlusion culling, the objects that share a mesh are drawn together, as usual.
+/// With it, every object is drawn on its own within its occlusion query. `hidden` holds
+/// the objects whose last query counted no samples, with the number of frames since: they
+/// are skipped, except every `OCCLUSION_RETEST_INTERVAL` frames. An object that comes back
+/// into view can therefore show up a few frames late.
+///
+/// The shadow pass doesn't use the plan, as hidden objects can still cast visible shadows.
+pub fn plan_draws(data: &AppData, hidden: &mut HashMap<ObjectId, u32>) -> DrawList {
+    let mut draws = DrawList::default();
+
+    if data.occlusion_culling {
+        let objects = data.scene.object_batches();
+        let ids = objects.iter().map(|(id, _)| *id).collect::<HashSet<_>>();
+        hidden.retain(|id, _| ids.contains(id));
+
+        for (id, batch) in objects {
+            if draws.objects.len() < MAX_QUERIED_DRAWS {
+                if let Some(frames) = hidden.get_mut(&id) {
+                    *frames += 1;
+                    if *frames % OCCLUSION_RETEST_INTERVAL != 0 {
+                        continue;
+                    }
+                }
+                draws.objects.push(id);
+            }
+            draws.batches.push(batch);
+        }
+    } else {
+        draws.batches = data.scene.batches();
+    }
+
+    if data.pipeline_statistics {
+        draws.statistics = (draws.batches.iter())
+            .flat_map(|b| (0..data.scene.meshes[b.mesh].primitives.len()).map(|p| (b.mesh, p)))
+            .take(MAX_QUERIED_DRAWS)
+            .collect();
+    }
+
+    draws
+}
+
+/// Resets the statistics and occlusion queries the frame's draws are going to write.
+/// Queries can't be reset within a pass, so this is recorded before the render graph.
+pub unsafe fn cmd_reset_draw_queries(
+    device: &Device,
+    frame: &FrameContext,
+    command_buffer: vk::CommandBuffer,
+) {
+    if !frame.draws.statistics.is_empty() {
+        let count = frame.draws.statistics.len() as u32;
+        device.cmd_reset_query_pool(command_buffer, frame.statistics_pool, 0, count);
+    }
+    if !frame.draws.objects.is_empty() {
+        let count = frame.draws.objects.len() as u32;
+        device.cmd_reset_query_pool(command_buffer, frame.occlusion_pool, 0, count);
+    }
+}
+
+/// Reads the pipeline statistics of the draws the frame recorded the last time it was
+/// rendered. Like `read_pass_timings`, the frame's fence must have been waited for.
+pub unsafe fn read_draw_statistics(
+    device: &Device,
+    data: &mut AppData,
+    frame: usize,
+) -> Result<Vec<DrawStatistics>> {
+    let frame = &mut data.frames[frame];
+    let draws = std::mem::take(&mut frame.draws.statistics);
+    if draws.is_empty() {
+        return Ok(vec![]);
+    }
+
+    // The counts of a query are in the order of the bits of `STATISTICS`.
+    let counts = read_query_results(device, frame.statistics_pool, draws.len(), 3)?;
+
+    Ok(draws
+        .into_iter()
+        .zip(counts.chunks_exact(3))
+        .map(|((mesh, primitive), c)| DrawStatistics {
+            mesh,
+            primitive,
+            vertex_shader_invocations: c[0],
+            clipping_primitives: c[1],
+            fragment_shader_invocations: c[2],
+        })
+        .collect())
+}
+
+/// Reads whether the objects the frame queried the last time it was rendered were
+/// visible. Like `read_pass_timings`, the frame's fence must have been waited for.
+pub unsafe fn read_occlusion(
+    device: &Device,
+    data: &mut AppData,
+    frame: usize,
+) -> Result<Vec<(ObjectId, bool)>> {
+    let frame = &mut data.frames[frame];
+    let objects = std::mem::take(&mut frame.draws.objects);
+    if objects.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let samples = read_query_results(device, frame.occlusion_pool, objects.len(), 1)?;
+
+    Ok(objects
+        .into_iter()
+        .zip(samples)
+        .map(|(id, samples)| (id, samples > 0))
+        .collect())
+}
+
+/// Reads the 64-bit results of the first queries of a pool, `values` per query. The
+/// queries must have finished, so the results aren't waited for.
+unsafe fn read_query_results(
+    device: &Device,
+    pool: vk::QueryPool,
+    queries: usize,
+    values: usize,
+) -> Result<Vec<u64>> {
+    let mut results = vec![0u64; queries * values];
+    device.get_query_pool_results(
+        pool,
+        0,
+        queries as u32,
+        std::slice::from_raw_parts_mut(results.as_mut_ptr().cast::<u8>(), results.len() * 8),
+        (values * 8) as u64,
+        vk::QueryResultFlags::_64,
+    )?;
+
+    Ok(results)
+}
@@ -25,10 +25,16 @@ impl QueueFamilyIndices {
             .position(|p| p.queue_flags.contains(vk::QueueFlags::GRAPHICS))
             .map(|i| i as u32);
 
-        for (index, properties) in properties.iter().enumerate() {
-            if(instance.get_physical_device_surface_support_khr(physical_device, index as u32, data.surface))? {
-                present = Some(index as u32);
-                break;
+        if data.headless {
+            // Without a surface there is nothing to present to, so the graphics
+            // queue stands in for the present queue.
+            present = graphics;
+        } else {
+            for (index, properties) in properties.iter().enumerate() {
+                if(instance.get_physical_device_surface_support_khr(physical_device, index as u32, data.surface))? {
+                    present = Some(index as u32);
+                    break;
+                }
             }
         }
     
@@ -0,0 +1,494 @@
+use std::collections::BTreeMap;
+
+use naga::front::spv;
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+use naga::{AddressSpace, ArraySize, Binding, ImageClass, ScalarKind, ShaderStage, TypeInner};
+use vulkanalia::prelude::v1_3::*;
+
+use super::errors::ReflectionError;
+
+/// A binding of a descriptor set that the shaders declare.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    /// The number of descriptors, more than 1 for arrays.
+    pub count: u32,
+    /// The stages whose entry points use the binding.
+    pub stages: vk::ShaderStageFlags,
+}
+
+/// An input of the vertex shader, which a vertex attribute has to provide.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct VertexInput {
+    pub location: u32,
+    pub format: vk::Format,
+}
+
+/// The interface of a SPIR-V module: the resources its entry points use and the vertex
+/// attributes they read.
+///
+/// The descriptor set layouts and pipeline layouts are built from it instead of being
+/// written by hand, so they can't drift apart from the shaders. The vertex attributes on
+/// the other hand describe how the Rust structs are laid out in the buffers, so they are
+/// only checked against the inputs (see `vertex_attributes`).
+#[derive(Clone, Debug, Default)]
+pub struct ShaderReflection {
+    /// Sorted by set and binding.
+    pub bindings: Vec<DescriptorBinding>,
+    pub push_constants: Vec<vk::PushConstantRange>,
+    /// The inputs of the vertex entry point, sorted by location.
+    pub vertex_inputs: Vec<VertexInput>,
+}
+
+impl ShaderReflection {
+    /// Reflects a SPIR-V module.
+    ///
+    /// The module is parsed with naga, which also tells which entry points use which
+    /// resources. A Vulkan combined image sampler shows up as an image and a sampler with
+    /// the same binding, which is turned back into a single binding.
+    pub fn reflect(code: &[u32]) -> Result<Self, ReflectionError> {
+        // Only the interface is of interest, so capabilities naga can't translate (e.g. the
+        // non-uniform indexing of the instance textures) don't matter.
+        let options = spv::Options {
+            adjust_coordinate_space: false,
+            strict_capabilities: false,
+            ..Default::default()
+        };
+        let module = spv::Frontend::new(code.iter().copied(), &options)
+            .parse()
+            .map_err(|e| ReflectionError::Parse(e.to_string()))?;
+
+        // The combined image samplers break the WebGPU rule of one resource per binding.
+        let flags = ValidationFlags::all() - ValidationFlags::BINDINGS;
+        let info = Validator::new(flags, Capabilities::all())
+            .validate(&module)
+            .map_err(|e| ReflectionError::Parse(e.into_inner().to_string()))?;
+
+        let mut reflection = Self::default();
+        let mut bindings = BTreeMap::<(u32, u32), DescriptorBinding>::new();
+
+        for (handle, global) in module.global_variables.iter() {
+            let stages = module
+                .entry_points
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| !info.get_entry_point(*i)[handle].is_empty())
+                .fold(vk::ShaderStageFlags::empty(), |s, (_, e)| {
+                    s | stage_flags(e.stage)
+                });
+
+            if global.space == AddressSpace::Immediate {
+                // Push constants used by no entry point don't need a range.
+                if !stages.is_empty() {
+                    let size = module.types[global.ty].inner.size(module.to_ctx());
+                    reflection.push_constants.push(
+                        vk::PushConstantRange::builder()
+                            .stage_flags(stages)
+                            .offset(0)
+                            .size(size)
+                            .build(),
+                    );
+                }
+                continue;
+            }
+
+            let Some(resource) = &global.binding else {
+                continue;
+            };
+
+            let (ty, count) = match module.types[global.ty].inner {
+                TypeInner::BindingArray { base, size } => match size {
+                    ArraySize::Constant(size) => (&module.types[base].inner, size.get()),
+                    _ => {
+                        return Err(ReflectionError::UnsizedArray(
+                            resource.group,
+                            resource.binding,
+                        ))
+                    }
+                },
+                ref inner => (inner, 1),
+            };
+
+            let descriptor_type = match (global.space, ty) {
+                (AddressSpace::Uniform, _) => vk::DescriptorType::UNIFORM_BUFFER,
+                (AddressSpace::Storage { .. }, _) => vk::DescriptorType::STORAGE_BUFFER,
+                (_, TypeInner::Sampler { .. }) => vk::DescriptorType::SAMPLER,
+                (
+                    _,
+                    TypeInner::Image {
+                        class: ImageClass::Storage { .. },
+                        ..
+                    },
+                ) => vk::DescriptorType::STORAGE_IMAGE,
+                (_, TypeInner::Image { .. }) => vk::DescriptorType::SAMPLED_IMAGE,
+                _ => {
+                    return Err(ReflectionError::UnsupportedResource(
+                        resource.group,
+                        resource.binding,
+                    ))
+                }
+            };
+
+            let binding = DescriptorBinding {
+                set: resource.group,
+                binding: resource.binding,
+                descriptor_type,
+                count,
+                stages,
+            };
+
+            bindings
+                .entry((resource.group, resource.binding))
+                .and_modify(|b| {
+                    use vk::DescriptorType as T;
+                    if matches!(
+                        (b.descriptor_type, descriptor_type),
+                        (T::SAMPLED_IMAGE, T::SAMPLER) | (T::SAMPLER, T::SAMPLED_IMAGE)
+                    ) {
+                        b.descriptor_type = T::COMBINED_IMAGE_SAMPLER;
+                    }
+                    b.stages |= stages;
+                })
+                .or_insert(binding);
+        }
+        reflection.bindings = bindings.into_values().collect();
+
+        for entry_point in module.entry_points.iter() {
+            if entry_point.stage != ShaderStage::Vertex {
+                continue;
+            }
+
+            // The inputs are either arguments of their own or members of struct arguments.
+            let mut inputs = Vec::new();
+            for argument in &entry_point.function.arguments {
+                match &module.types[argument.ty].inner {
+                    TypeInner::Struct { members, .. } => inputs.extend(
+                        members
+                            .iter()
+                            .map(|m| (m.binding.as_ref(), &module.types[m.ty].inner)),
+                    ),
+                    inner => inputs.push((argument.binding.as_ref(), inner)),
+                }
+            }
+
+            for (binding, inner) in inputs {
+                if let Some(&Binding::Location { location, .. }) = binding {
+                    let format = vertex_format(inner)
+                        .ok_or(ReflectionError::UnsupportedVertexInput(location))?;
+                    reflection
+                        .vertex_inputs
+                        .push(VertexInput { location, format });
+                }
+            }
+        }
+        reflection.vertex_inputs.sort_by_key(|i| i.location);
+
+        Ok(reflection)
+    }
+
+    /// Combines the descriptor bindings and push constants of modules that share descriptor
+    /// set layouts. A binding must have the same type and count in every module, its
+    /// stages are combined.
+    pub fn merge(reflections: &[&ShaderReflection]) -> Result<Self, ReflectionError> {
+        let mut merged = Self::default();
+        let mut bindings = BTreeMap::<(u32, u32), DescriptorBinding>::new();
+
+        for reflection in reflections {
+            for b in &reflection.bindings {
+                let existing = bindings.entry((b.set, b.binding)).or_insert(*b);
+                if (existing.descriptor_type, existing.count) != (b.descriptor_type, b.count) {
+                    return Err(ReflectionError::BindingMismatch(b.set, b.binding));
+                }
+                existing.stages |= b.stages;
+            }
+
+            merged
+                .push_constants
+                .extend_from_slice(&reflection.push_constants);
+        }
+
+        merged.bindings = bindings.into_values().collect();
+
+        Ok(merged)
+    }
+
+    /// The number of descriptor sets a pipeline layout needs for the module, which is one
+    /// more than the highest set it uses.
+    pub fn set_count(&self) -> usize {
+        self.bindings
+            .iter()
+            .map(|b| b.set as usize + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The bindings of a descriptor set layout.
+    pub fn set_layout_bindings(&self, set: u32) -> Vec<vk::DescriptorSetLayoutBinding> {
+        self.bindings
+            .iter()
+            .filter(|b| b.set == set)
+            .map(|b| {
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(b.binding)
+                    .descriptor_type(b.descriptor_type)
+                    .descriptor_count(b.count)
+                    .stage_flags(b.stages)
+                    .build()
+            })
+            .collect()
+    }
+
+    /// Checks that the module can be used with pipeline layouts built from another
+    /// interface, i.e. that it uses no binding or push constant the layouts don't have.
+    pub fn check_compatible(&self, layout: &ShaderReflection) -> Result<(), ReflectionError> {
+        for b in &self.bindings {
+            let compatible = layout.bindings.iter().any(|l| {
+                (l.set, l.binding, l.descriptor_type, l.count)
+                    == (b.set, b.binding, b.descriptor_type, b.count)
+                    && l.stages.contains(b.stages)
+            });
+            if !compatible {
+                return Err(ReflectionError::BindingMismatch(b.set, b.binding));
+            }
+        }
+
+        for p in &self.push_constants {
+            let compatible = layout.push_constants.iter().any(|l| {
+                l.offset <= p.offset
+                    && p.offset + p.size <= l.offset + l.size
+                    && l.stage_flags.contains(p.stage_flags)
+            });
+            if !compatible {
+                return Err(ReflectionError::PushConstantMismatch(p.size));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Picks the vertex attributes for the inputs of the vertex shader from the ones the
+    /// Rust structs provide (e.g. `Vertex::attribute_descriptions`). Attributes the shader
+    /// doesn't read are left out, but every input needs an attribute of the same format.
+    pub fn vertex_attributes(
+        &self,
+        provided: &[vk::VertexInputAttributeDescription],
+    ) -> Result<Vec<vk::VertexInputAttributeDescription>, ReflectionError> {
+        self.vertex_inputs
+            .iter()
+            .map(|input| {
+                let attribute = provided
+                    .iter()
+                    .find(|a| a.location == input.location)
+                    .ok_or(ReflectionError::MissingVertexAttribute(
+                        input.location,
+                        input.format,
+                    ))?;
+
+                if attribute.format != input.format {
+                    return Err(ReflectionError::VertexAttributeMismatch(
+                        input.location,
+                        attribute.binding,
+                        attribute.format,
+                        input.format,
+                    ));
+                }
+
+                Ok(*attribute)
+            })
+            .collect()
+    }
+}
+
+fn stage_flags(stage: ShaderStage) -> vk::ShaderStageFlags {
+    match stage {
+        ShaderStage::Vertex => vk::ShaderStageFlags::VERTEX,
+        ShaderStage::Task => vk::ShaderStageFlags::TASK_EXT,
+        ShaderStage::Mesh => vk::ShaderStageFlags::MESH_EXT,
+        ShaderStage::Fragment => vk::ShaderStageFlags::FRAGMENT,
+        ShaderStage::Compute => vk::ShaderStageFlags::COMPUTE,
+        ShaderStage::RayGeneration => vk::ShaderStageFlags::RAYGEN_KHR,
+        ShaderStage::Miss => vk::ShaderStageFlags::MISS_KHR,
+        ShaderStage::AnyHit => vk::ShaderStageFlags::ANY_HIT_KHR,
+        ShaderStage::ClosestHit => vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+    }
+}
+
+/// The format of the 32-bit scalars and vectors that vertex attributes can provide.
+fn vertex_format(inner: &TypeInner) -> Option<vk::Format> {
+    use vk::Format as F;
+
+    let (size, scalar) = match *inner {
+        TypeInner::Scalar(scalar) => (1, scalar),
+        TypeInner::Vector { size, scalar } => (size as u32, scalar),
+        _ => return None,
+    };
+
+    if scalar.width != 4 {
+        return None;
+    }
+
+    let formats = match scalar.kind {
+        ScalarKind::Float => [
+            F::R32_SFLOAT,
+            F::R32G32_SFLOAT,
+            F::R32G32B32_SFLOAT,
+            F::R32G32B32A32_SFLOAT,
+        ],
+        ScalarKind::Sint => [
+            F::R32_SINT,
+            F::R32G32_SINT,
+            F::R32G32B32_SINT,
+            F::R32G32B32A32_SINT,
+        ],
+        ScalarKind::Uint => [
+            F::R32_UINT,
+            F::R32G32_UINT,
+            F::R32G32B32_UINT,
+            F::R32G32B32A32_UINT,
+        ],
+        _ => return None,
+    };
+
+    Some(formats[size as usize - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::vulkan::shader::compile_wgsl;
+
+    fn reflect(source: &str) -> ShaderReflection {
+        let code = compile_wgsl(source, Path::new("test.wgsl"), true).unwrap();
+        ShaderReflection::reflect(&code).unwrap()
+    }
+
+    fn attribute(location: u32, format: vk::Format) -> vk::VertexInputAttributeDescription {
+        vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(location)
+            .format(format)
+            .build()
+    }
+
+    const VERTEX: &str = "
+        @vertex
+        fn vs_main(@location(0) pos: vec3<f32>, @location(2) uv: vec2<f32>) -> @builtin(position) vec4<f32> {
+            return vec4<f32>(pos + vec3<f32>(uv, 0.0), 1.0);
+        }
+    ";
+
+    #[test]
+    fn vertex_attributes_are_picked_by_location() {
+        let reflection = reflect(VERTEX);
+        let provided = [
+            attribute(0, vk::Format::R32G32B32_SFLOAT),
+            attribute(1, vk::Format::R32G32B32_SFLOAT),
+            attribute(2, vk::Format::R32G32_SFLOAT),
+        ];
+
+        let attributes = reflection.vertex_attributes(&provided).unwrap();
+        let locations = attributes.iter().map(|a| a.location).collect::<Vec<_>>();
+        assert_eq!(locations, [0, 2]);
+
+        let result = reflection.vertex_attributes(&provided[..2]);
+        assert!(matches!(
+            result,
+            Err(ReflectionError::MissingVertexAttribute(
+                2,
+                vk::Format::R32G32_SFLOAT
+            ))
+        ));
+
+        let result = reflection.vertex_attributes(&[
+            attribute(0, vk::Format::R32G32B32_SFLOAT),
+            attribute(2, vk::Format::R32G32B32_SFLOAT),
+        ]);
+        assert!(matches!(
+            result,
+            Err(ReflectionError::VertexAttributeMismatch(
+                2,
+                0,
+                vk::Format::R32G32B32_SFLOAT,
+                vk::Format::R32G32_SFLOAT
+            ))
+        ));
+    }
+
+    #[test]
+    fn merge_combines_stages_and_rejects_mismatched_bindings() {
+        let vertex = reflect(
+            "
+            @group(0) @binding(0) var<uniform> offset: vec4<f32>;
+
+            @vertex
+            fn vs_main() -> @builtin(position) vec4<f32> {
+                return offset;
+            }
+            ",
+        );
+        let fragment = reflect(
+            "
+            @group(0) @binding(0) var<uniform> color: vec4<f32>;
+
+            @fragment
+            fn fs_main() -> @location(0) vec4<f32> {
+                return color;
+            }
+            ",
+        );
+        let texture = reflect(
+            "
+            @group(0) @binding(0) var image: texture_2d<f32>;
+
+            @fragment
+            fn fs_main() -> @location(0) vec4<f32> {
+                return textureLoad(image, vec2<i32>(0, 0), 0);
+            }
+            ",
+        );
+
+        let merged = ShaderReflection::merge(&[&vertex, &fragment]).unwrap();
+        assert_eq!(merged.bindings.len(), 1);
+        assert_eq!(
+            merged.bindings[0].stages,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT
+        );
+
+        let result = ShaderReflection::merge(&[&vertex, &texture]);
+        assert!(matches!(
+            result,
+            Err(ReflectionError::BindingMismatch(0, 0))
+        ));
+    }
+
+    #[test]
+    fn texture_and_sampler_with_one_binding_are_a_combined_image_sampler() {
+        let reflection = reflect(
+            "
+            @group(1) @binding(1) var image: texture_2d<f32>;
+            @group(1) @binding(1) var image_sampler: sampler;
+
+            @fragment
+            fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+                return textureSample(image, image_sampler, uv);
+            }
+            ",
+        );
+
+        assert_eq!(
+            reflection.bindings,
+            [DescriptorBinding {
+                set: 1,
+                binding: 1,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                count: 1,
+                stages: vk::ShaderStageFlags::FRAGMENT,
+            }]
+        );
+        assert_eq!(reflection.set_count(), 2);
+    }
+}
@@ -0,0 +1,1710 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use log::*;
+use vulkanalia::prelude::v1_3::*;
+
+use crate::app::AppData;
+
+use super::allocator::ResourceKind;
+use super::errors::RenderGraphError;
+use super::frame::FrameContext;
+use super::framebuffer::create_framebuffer;
+use super::image::create_image_view;
+use super::queries::MAX_TIMED_PASSES;
+use super::render_pass::create_render_pass;
+use super::resources::{Image, ImageView, Memory};
+use super::scene::Batch;
+
+/// An image of the render graph.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ImageId(usize);
+
+/// A buffer of the render graph.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BufferId(usize);
+
+/// Records the commands of a pass. The attachments of the pass are bound when it's called.
+pub type RecordPass = unsafe fn(&PassContext);
+
+/// What a pass records its commands with.
+pub struct PassContext<'a> {
+    pub device: &'a Device,
+    pub data: &'a AppData,
+    pub frame: &'a FrameContext,
+    pub command_buffer: vk::CommandBuffer,
+    /// The index of the swapchain image the frame draws into.
+    pub image_index: usize,
+    /// The objects of the scene grouped by mesh, the same for every pass of the frame.
+    pub batches: &'a [Batch],
+}
+
+/// An image the graph creates. Its contents only live within a frame, so images that
+/// aren't used by the same passes may share their memory.
+#[derive(Copy, Clone, Debug)]
+pub struct ImageDesc {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub samples: vk::SampleCountFlags,
+}
+
+/// How a pass uses an image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageAccess {
+    /// Drawn into as a color attachment, or resolved into.
+    ColorAttachment,
+    /// Depth tested and written as the depth attachment.
+    DepthAttachment,
+    /// Sampled by the fragment shader.
+    Sampled,
+    /// Copied from.
+    TransferSrc,
+    /// Copied into.
+    TransferDst,
+    /// Presented to the screen after the frame, see `RenderGraph::export`.
+    Present,
+}
+
+/// How a pass uses a buffer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BufferAccess {
+    /// Read as vertex or instance data.
+    VertexInput,
+    /// Read as indices.
+    Index,
+    /// Read as uniform buffer by the vertex and fragment shaders.
+    Uniform,
+    /// Copied from.
+    TransferSrc,
+    /// Copied into.
+    TransferDst,
+}
+
+/// What happens to the contents of an attachment when a pass begins.
+#[derive(Copy, Clone, Debug)]
+pub enum LoadOp {
+    Load,
+    Clear(vk::ClearValue),
+    DontCare,
+}
+
+/// An attachment a pass draws into.
+#[derive(Copy, Clone, Debug)]
+pub struct Attachment {
+    pub image: ImageId,
+    pub load: LoadOp,
+    /// Whether the contents are kept after the pass, for later passes or frames.
+    pub store: bool,
+    /// A single sampled image the multisampled color attachment is resolved into.
+    pub resolve: Option<ImageId>,
+}
+
+/// An attachment as the render pass or dynamic rendering sees it, in the layout it has
+/// for the whole pass.
+#[derive(Copy, Clone, Debug)]
+pub struct PassAttachment {
+    pub format: vk::Format,
+    pub samples: vk::SampleCountFlags,
+    pub layout: vk::ImageLayout,
+    pub load_op: vk::AttachmentLoadOp,
+    pub store_op: vk::AttachmentStoreOp,
+    pub clear_value: vk::ClearValue,
+}
+
+/// The attachments a pipeline drawing in a pass is created for.
+#[derive(Clone, Debug)]
+pub struct PassTarget {
+    /// Null with dynamic rendering, which names the formats instead.
+    pub render_pass: vk::RenderPass,
+    pub color_formats: Vec<vk::Format>,
+    /// `UNDEFINED` without a depth attachment.
+    pub depth_format: vk::Format,
+}
+
+/// The passes that draw a frame and the images and buffers they read and write.
+///
+/// Each pass declares how it uses the resources, and the graph works out the rest:
+///
+/// 1. The passes run in an order where every pass comes after the passes writing what it
+///    reads. Otherwise they keep the order they were added in.
+/// 2. The transient images are created by the graph. Images that no pass uses at the
+///    same time share their memory (aliasing).
+/// 3. Before every pass, barriers move the images into the layouts the pass needs and
+///    make it wait for the passes before it that wrote what it uses, or that read what it
+///    writes. The first use of a resource in a frame waits for its last use in the
+///    previous frame.
+/// 4. The attachments are bound with dynamic rendering, or with a render pass and
+///    framebuffer created for the pass when the device doesn't support it.
+///
+/// Imported images are expected in the layout of their last use (or their export) when
+/// the first frame starts, unless their first use discards the contents. The graph
+/// depends on the swapchain, so it is declared and compiled again when the swapchain is
+/// recreated (see passes.rs).
+#[derive(Debug, Default)]
+pub struct RenderGraph {
+    images: Vec<GraphImage>,
+    buffers: Vec<GraphBuffer>,
+    passes: Vec<Pass>,
+    /// The indices of the passes in the order they run in.
+    order: Vec<usize>,
+    /// The barriers after the last pass, which leave the exported images in the layouts
+    /// they're used in after the frame.
+    final_barriers: Barriers,
+    /// The memory of the transient images.
+    memory: Vec<Memory>,
+    dynamic_rendering: bool,
+}
+
+#[derive(Debug)]
+struct GraphImage {
+    name: String,
+    source: ImageSource,
+    format: vk::Format,
+    extent: vk::Extent2D,
+    samples: vk::SampleCountFlags,
+    /// Every way the passes use the image.
+    usage: vk::ImageUsageFlags,
+    /// How the image is used after the frame.
+    export: Option<ImageAccess>,
+    /// A single image, or one per swapchain image.
+    handles: Vec<vk::Image>,
+    views: Vec<vk::ImageView>,
+    /// The memory slot the image shares with others, for transient images.
+    slot: Option<usize>,
+    /// Owned by the graph for transient images.
+    image: Option<Image>,
+    view: Option<ImageView>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ImageSource {
+    Transient,
+    /// The swapchain images are acquired before the frame draws into them.
+    Swapchain {
+        acquired: bool,
+    },
+    Imported,
+}
+
+#[derive(Debug)]
+struct GraphBuffer {
+    name: String,
+    /// The buffer of a frame, looked up when the frame is recorded.
+    handle: fn(&AppData, &FrameContext) -> vk::Buffer,
+}
+
+#[derive(Debug)]
+struct Pass {
+    name: String,
+    record: RecordPass,
+    color_attachments: Vec<Attachment>,
+    depth_attachment: Option<Attachment>,
+    /// Every image the pass uses, including the attachments.
+    images: Vec<ImageUse>,
+    buffers: Vec<(BufferId, BufferAccess)>,
+    barriers: Barriers,
+    extent: vk::Extent2D,
+    /// Only without dynamic rendering, one framebuffer per swapchain image when the pass
+    /// draws into the swapchain.
+    render_pass: vk::RenderPass,
+    framebuffers: Vec<vk::Framebuffer>,
+}
+
+impl Pass {
+    fn draws(&self) -> bool {
+        !self.color_attachments.is_empty() || self.depth_attachment.is_some()
+    }
+
+    fn writes_image(&self, image: ImageId) -> bool {
+        self.images
+            .iter()
+            .any(|u| u.image == image && !u.access.state(vk::Format::UNDEFINED).writes.is_empty())
+    }
+
+    fn writes_buffer(&self, buffer: BufferId) -> bool {
+        self.buffers
+            .iter()
+            .any(|(b, access)| *b == buffer && !access.state().writes.is_empty())
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct ImageUse {
+    image: ImageId,
+    access: ImageAccess,
+    /// Whether the pass overwrites the contents without reading them.
+    discard: bool,
+}
+
+/// Memory shared by transient images whose lifetimes don't overlap.
+#[derive(Debug)]
+struct Slot {
+    images: Vec<usize>,
+    requirements: vk::MemoryRequirements,
+    /// The position of the last pass using one of the images.
+    last: usize,
+}
+
+#[derive(Debug, Default)]
+struct Barriers {
+    images: Vec<ImageBarrier>,
+    buffers: Vec<BufferBarrier>,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct ImageBarrier {
+    image: ImageId,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src: (vk::PipelineStageFlags2, vk::AccessFlags2),
+    dst: (vk::PipelineStageFlags2, vk::AccessFlags2),
+}
+
+#[derive(Copy, Clone, Debug)]
+struct BufferBarrier {
+    buffer: BufferId,
+    src: (vk::PipelineStageFlags2, vk::AccessFlags2),
+    dst: (vk::PipelineStageFlags2, vk::AccessFlags2),
+}
+
+/// The layout, stages and memory accesses of a use of a resource. Only the stages and
+/// accesses of Vulkan 1.0 are used, so they also work with `cmd_pipeline_barrier`.
+#[derive(Copy, Clone, Debug)]
+struct AccessState {
+    layout: vk::ImageLayout,
+    stages: vk::PipelineStageFlags2,
+    reads: vk::AccessFlags2,
+    writes: vk::AccessFlags2,
+}
+
+/// How the swapchain image was used before the frame: it is acquired, which the frame's
+/// commands wait for at the color attachment output stage (see `App::render`).
+const ACQUIRED: AccessState = AccessState {
+    layout: vk::ImageLayout::UNDEFINED,
+    stages: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+    reads: vk::AccessFlags2::NONE,
+    writes: vk::AccessFlags2::NONE,
+};
+
+/// The stages that read and write depth attachments.
+const FRAGMENT_TESTS: vk::PipelineStageFlags2 = vk::PipelineStageFlags2::from_bits_truncate(
+    vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS.bits()
+        | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS.bits(),
+);
+
+impl ImageAccess {
+    /// The state of an image with the given format during the access. Only the layout
+    /// of sampled images depends on the format.
+    fn state(self, format: vk::Format) -> AccessState {
+        use vk::AccessFlags2 as A;
+        use vk::ImageLayout as L;
+        use vk::PipelineStageFlags2 as S;
+
+        let (layout, stages, reads, writes) = match self {
+            ImageAccess::ColorAttachment => (
+                L::COLOR_ATTACHMENT_OPTIMAL,
+                S::COLOR_ATTACHMENT_OUTPUT,
+                A::COLOR_ATTACHMENT_READ,
+                A::COLOR_ATTACHMENT_WRITE,
+            ),
+            ImageAccess::DepthAttachment => (
+                L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                FRAGMENT_TESTS,
+                A::DEPTH_STENCIL_ATTACHMENT_READ,
+                A::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+            ImageAccess::Sampled if is_depth_format(format) => (
+                L::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                S::FRAGMENT_SHADER,
+                A::SHADER_READ,
+                A::NONE,
+            ),
+            ImageAccess::Sampled => (
+                L::SHADER_READ_ONLY_OPTIMAL,
+                S::FRAGMENT_SHADER,
+                A::SHADER_READ,
+                A::NONE,
+            ),
+            ImageAccess::TransferSrc => (
+                L::TRANSFER_SRC_OPTIMAL,
+                S::ALL_TRANSFER,
+                A::TRANSFER_READ,
+                A::NONE,
+            ),
+            ImageAccess::TransferDst => (
+                L::TRANSFER_DST_OPTIMAL,
+                S::ALL_TRANSFER,
+                A::NONE,
+                A::TRANSFER_WRITE,
+            ),
+            // Presenting waits for the frame's semaphore, which is signaled after every
+            // command, so no stage after the transition has to wait for it.
+            ImageAccess::Present => (L::PRESENT_SRC_KHR, S::NONE, A::NONE, A::NONE),
+        };
+
+        AccessState {
+            layout,
+            stages,
+            reads,
+            writes,
+        }
+    }
+
+    fn usage(self) -> vk::ImageUsageFlags {
+        match self {
+            ImageAccess::ColorAttachment => vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            ImageAccess::DepthAttachment => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            ImageAccess::Sampled => vk::ImageUsageFlags::SAMPLED,
+            ImageAccess::TransferSrc => vk::ImageUsageFlags::TRANSFER_SRC,
+            ImageAccess::TransferDst => vk::ImageUsageFlags::TRANSFER_DST,
+            ImageAccess::Present => vk::ImageUsageFlags::empty(),
+        }
+    }
+}
+
+impl BufferAccess {
+    fn state(self) -> AccessState {
+        use vk::AccessFlags2 as A;
+        use vk::PipelineStageFlags2 as S;
+
+        let (stages, reads, writes) = match self {
+            BufferAccess::VertexInput => (S::VERTEX_INPUT, A::VERTEX_ATTRIBUTE_READ, A::NONE),
+            BufferAccess::Index => (S::VERTEX_INPUT, A::INDEX_READ, A::NONE),
+            BufferAccess::Uniform => (
+                S::VERTEX_SHADER | S::FRAGMENT_SHADER,
+                A::UNIFORM_READ,
+                A::NONE,
+            ),
+            BufferAccess::TransferSrc => (S::ALL_TRANSFER, A::TRANSFER_READ, A::NONE),
+            BufferAccess::TransferDst => (S::ALL_TRANSFER, A::NONE, A::TRANSFER_WRITE),
+        };
+
+        AccessState {
+            layout: vk::ImageLayout::UNDEFINED,
+            stages,
+            reads,
+            writes,
+        }
+    }
+}
+
+/// Adds the resources a pass uses, see `RenderGraph::add_pass`.
+pub struct PassBuilder<'a> {
+    graph: &'a mut RenderGraph,
+    pass: usize,
+}
+
+impl PassBuilder<'_> {
+    /// Draws into a color attachment, which is resolved at the end of the pass if it has
+    /// a resolve image.
+    pub fn color_attachment(self, attachment: Attachment) -> Self {
+        let discard = !matches!(attachment.load, LoadOp::Load);
+        self.graph.use_image(
+            self.pass,
+            attachment.image,
+            ImageAccess::ColorAttachment,
+            discard,
+        );
+
+        // The resolve overwrites the whole image.
+        if let Some(resolve) = attachment.resolve {
+            self.graph
+                .use_image(self.pass, resolve, ImageAccess::ColorAttachment, true);
+        }
+
+        self.graph.passes[self.pass]
+            .color_attachments
+            .push(attachment);
+        self
+    }
+
+    pub fn depth_attachment(self, attachment: Attachment) -> Self {
+        let discard = !matches!(attachment.load, LoadOp::Load);
+        self.graph.use_image(
+            self.pass,
+            attachment.image,
+            ImageAccess::DepthAttachment,
+            discard,
+        );
+
+        self.graph.passes[self.pass].depth_attachment = Some(attachment);
+        self
+    }
+
+    /// Uses an image in another way than as an attachment, e.g. samples it.
+    pub fn image(self, image: ImageId, access: ImageAccess) -> Self {
+        self.graph.use_image(self.pass, image, access, false);
+        self
+    }
+
+    pub fn buffer(self, buffer: BufferId, access: BufferAccess) -> Self {
+        self.graph.passes[self.pass].buffers.push((buffer, access));
+        self
+    }
+}
+
+impl RenderGraph {
+    /// Adds an image the graph creates when it's compiled.
+    pub fn create_image(&mut self, name: &str, desc: ImageDesc) -> ImageId {
+        self.add_image(
+            name,
+            ImageSource::Transient,
+            desc.format,
+            desc.extent,
+            desc.samples,
+            Vec::new(),
+            Vec::new(),
+        )
+    }
+
+    /// Adds the swapchain images, or the offscreen image when headless. A frame draws
+    /// into the one it's executed with. Afterwards, the image is left ready to be
+    /// presented, or copied into a buffer when headless (see `save_offscreen_image`).
+    pub fn import_swapchain(&mut self, data: &AppData) -> ImageId {
+        let image = self.add_image(
+            "swapchain",
+            ImageSource::Swapchain {
+                acquired: !data.headless,
+            },
+            data.swapchain_format,
+            data.swapchain_extent,
+            vk::SampleCountFlags::_1,
+            data.swapchain_images.clone(),
+            data.swapchain_image_views
+                .iter()
+                .map(|v| v.handle())
+                .collect(),
+        );
+
+        let access = if data.headless {
+            ImageAccess::TransferSrc
+        } else {
+            ImageAccess::Present
+        };
+        self.export(image, access);
+
+        image
+    }
+
+    /// Adds an image that is created and owned by someone else, e.g. the shadow map.
+    pub fn import_image(
+        &mut self,
+        name: &str,
+        image: vk::Image,
+        view: vk::ImageView,
+        format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> ImageId {
+        self.add_image(
+            name,
+            ImageSource::Imported,
+            format,
+            extent,
+            vk::SampleCountFlags::_1,
+            vec![image],
+            vec![view],
+        )
+    }
+
+    /// Adds a buffer, which may be a different one in every frame (e.g. the instance
+    /// buffers of the frames in flight).
+    ///
+    /// Buffers are usually written by the host before the frame is submitted, which the
+    /// submission makes visible. So only the passes of a frame that write a buffer get
+    /// barriers between them and the passes using it.
+    pub fn import_buffer(
+        &mut self,
+        name: &str,
+        handle: fn(&AppData, &FrameContext) -> vk::Buffer,
+    ) -> BufferId {
+        self.buffers.push(GraphBuffer {
+            name: name.to_string(),
+            handle,
+        });
+        BufferId(self.buffers.len() - 1)
+    }
+
+    /// Sets how an image is used after the frame, e.g. presented. The graph leaves it in
+    /// the layout of the access at the end of the frame.
+    pub fn export(&mut self, image: ImageId, access: ImageAccess) {
+        let image = &mut self.images[image.0];
+        image.export = Some(access);
+        image.usage |= access.usage();
+    }
+
+    /// Adds a pass, which records its commands with `record`. The resources it uses are
+    /// added to the returned builder.
+    pub fn add_pass(&mut self, name: &str, record: RecordPass) -> PassBuilder<'_> {
+        self.passes.push(Pass {
+            name: name.to_string(),
+            record,
+            color_attachments: Vec::new(),
+            depth_attachment: None,
+            images: Vec::new(),
+            buffers: Vec::new(),
+            barriers: Barriers::default(),
+            extent: vk::Extent2D::default(),
+            render_pass: vk::RenderPass::null(),
+            framebuffers: Vec::new(),
+        });
+
+        PassBuilder {
+            pass: self.passes.len() - 1,
+            graph: self,
+        }
+    }
+
+    fn add_image(
+        &mut self,
+        name: &str,
+        source: ImageSource,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        samples: vk::SampleCountFlags,
+        handles: Vec<vk::Image>,
+        views: Vec<vk::ImageView>,
+    ) -> ImageId {
+        self.images.push(GraphImage {
+            name: name.to_string(),
+            source,
+            format,
+            extent,
+            samples,
+            usage: vk::ImageUsageFlags::empty(),
+            export: None,
+            handles,
+            views,
+            slot: None,
+            image: None,
+            view: None,
+        });
+        ImageId(self.images.len() - 1)
+    }
+
+    fn use_image(&mut self, pass: usize, image: ImageId, access: ImageAccess, discard: bool) {
+        self.images[image.0].usage |= access.usage();
+        self.passes[pass].images.push(ImageUse {
+            image,
+            access,
+            discard,
+        });
+    }
+
+    /// Orders the passes, creates the transient images and works out the barriers. Without
+    /// dynamic rendering, the render passes and framebuffers are created as well.
+    pub unsafe fn compile(&mut self, device: &Device, data: &mut AppData) -> Result<()> {
+        self.dynamic_rendering = data.dynamic_rendering;
+        self.order = self.sort_passes()?;
+        self.check_passes()?;
+        self.create_images(device, data)?;
+        self.create_barriers();
+
+        if !self.dynamic_rendering {
+            self.create_render_passes(device)?;
+        }
+
+        debug!(
+            "Compiled the render graph: {}.",
+            self.order
+                .iter()
+                .map(|&p| self.passes[p].name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        Ok(())
+    }
+
+    /// Sorts the passes so that every pass comes after the ones writing what it reads,
+    /// keeping the order they were added in where it doesn't matter.
+    fn sort_passes(&self) -> Result<Vec<usize>, RenderGraphError> {
+        let dependencies = (self.passes.iter().enumerate())
+            .map(|(reader, pass)| {
+                (0..self.passes.len())
+                    .filter(|&writer| writer != reader)
+                    .filter(|&writer| {
+                        let other = &self.passes[writer];
+                        let images = (pass.images.iter())
+                            .filter(|u| !pass.writes_image(u.image))
+                            .any(|u| other.writes_image(u.image));
+                        let buffers = (pass.buffers.iter())
+                            .filter(|(b, _)| !pass.writes_buffer(*b))
+                            .any(|(b, _)| other.writes_buffer(*b));
+                        images || buffers
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let mut order = Vec::with_capacity(self.passes.len());
+        let mut done = vec![false; self.passes.len()];
+        while order.len() < self.passes.len() {
+            let next = (0..self.passes.len())
+                .find(|&p| !done[p] && dependencies[p].iter().all(|&d| done[d]))
+                .ok_or_else(|| {
+                    let remaining = (0..self.passes.len())
+                        .filter(|&p| !done[p])
+                        .map(|p| self.passes[p].name.clone())
+                        .collect();
+                    RenderGraphError::Cycle(remaining)
+                })?;
+
+            done[next] = true;
+            order.push(next);
+        }
+
+        Ok(order)
+    }
+
+    /// Checks that no pass uses an image in two ways or draws into attachments of
+    /// different sizes, and that transient images are written before they're read.
+    fn check_passes(&mut self) -> Result<(), RenderGraphError> {
+        let mut written = vec![false; self.images.len()];
+
+        for &p in &self.order {
+            let pass = &self.passes[p];
+
+            for (i, u) in pass.images.iter().enumerate() {
+                let image = &self.images[u.image.0];
+                if pass.images[..i]
+                    .iter()
+                    .any(|other| other.image == u.image && other.access != u.access)
+                {
+                    return Err(RenderGraphError::ConflictingAccess(
+                        image.name.clone(),
+                        pass.name.clone(),
+                    ));
+                }
+
+                if image.source == ImageSource::Transient && !written[u.image.0] && !u.discard {
+                    return Err(RenderGraphError::ReadBeforeWrite(
+                        image.name.clone(),
+                        pass.name.clone(),
+                    ));
+                }
+            }
+
+            for u in &pass.images {
+                written[u.image.0] |= pass.writes_image(u.image);
+            }
+        }
+
+        for pass in &mut self.passes {
+            let mut extents = (pass.color_attachments.iter())
+                .chain(&pass.depth_attachment)
+                .flat_map(|a| [Some(a.image), a.resolve])
+                .flatten()
+                .map(|image| self.images[image.0].extent);
+
+            if let Some(extent) = extents.next() {
+                if extents.any(|e| e != extent) {
+                    return Err(RenderGraphError::ExtentMismatch(pass.name.clone()));
+                }
+                pass.extent = extent;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates the transient images that the passes use and binds them to the memory of
+    /// the slots `assign_slots` puts them in.
+    unsafe fn create_images(&mut self, device: &Device, data: &mut AppData) -> Result<()> {
+        let lifetimes = self.lifetimes();
+        let mut requirements = HashMap::new();
+        for (i, image) in self.images.iter_mut().enumerate() {
+            if image.source != ImageSource::Transient || lifetimes[i].is_none() {
+                continue;
+            }
+
+            // Images only used as attachments never leave the GPU's tile memory on some
+            // devices.
+            let attachment_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT;
+            let mut usage = image.usage;
+            if attachment_usage.contains(usage) {
+                usage |= vk::ImageUsageFlags::TRANSIENT_ATTACHMENT;
+            }
+
+            let info = vk::ImageCreateInfo::builder()
+                .image_type(vk::ImageType::_2D)
+                .extent(vk::Extent3D {
+                    width: image.extent.width,
+                    height: image.extent.height,
+                    depth: 1,
+                })
+                .array_layers(1)
+                .mip_levels(1)
+                .format(image.format)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(usage)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .samples(image.samples)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+            let handle = device.create_image(&info, None)?;
+            image.image = Some(Image::aliased(handle, &data.deletion_queue));
+            data.debug_utils.set_name(device, handle, &image.name);
+            image.handles = vec![handle];
+            requirements.insert(i, device.get_image_memory_requirements(handle));
+        }
+
+        for slot in self.assign_slots(&requirements) {
+            let allocation = data.allocator.allocate_memory(
+                device,
+                slot.requirements,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                ResourceKind::Optimal,
+            )?;
+            self.memory
+                .push(Memory::new(allocation, &data.deletion_queue));
+
+            for &i in &slot.images {
+                let image = &mut self.images[i];
+                device.bind_image_memory(image.handles[0], allocation.memory, allocation.offset)?;
+
+                let aspects = if is_depth_format(image.format) {
+                    vk::ImageAspectFlags::DEPTH
+                } else {
+                    vk::ImageAspectFlags::COLOR
+                };
+                let view =
+                    create_image_view(device, data, image.handles[0], image.format, aspects, 1)?;
+                image.views = vec![view.handle()];
+                image.view = Some(view);
+            }
+
+            if slot.images.len() > 1 {
+                debug!(
+                    "The images {:?} share their memory.",
+                    slot.images
+                        .iter()
+                        .map(|&i| &self.images[i].name)
+                        .collect::<Vec<_>>()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The first and last position in the order of the passes that use each image, `None`
+    /// for images no pass uses.
+    fn lifetimes(&self) -> Vec<Option<(usize, usize)>> {
+        let mut lifetimes = vec![None::<(usize, usize)>; self.images.len()];
+        for (position, &p) in self.order.iter().enumerate() {
+            for u in &self.passes[p].images {
+                let lifetime = &mut lifetimes[u.image.0];
+                *lifetime =
+                    Some(lifetime.map_or((position, position), |(first, _)| (first, position)));
+            }
+        }
+        lifetimes
+    }
+
+    /// Assigns the transient images with the given memory requirements to memory slots.
+    /// An image whose first use comes after the last use of every image in a slot shares
+    /// the slot's memory with them, if the memory types allow it.
+    fn assign_slots(&mut self, requirements: &HashMap<usize, vk::MemoryRequirements>) -> Vec<Slot> {
+        let lifetimes = self.lifetimes();
+        let mut transient = (requirements.keys())
+            .filter_map(|&i| lifetimes[i].map(|lifetime| (i, lifetime)))
+            .collect::<Vec<_>>();
+        transient.sort_by_key(|&(i, (first, _))| (first, i));
+
+        let mut slots = Vec::<Slot>::new();
+        for (i, (first, last)) in transient {
+            let requirements = requirements[&i];
+            let slot = slots.iter().position(|s| {
+                s.last < first
+                    && s.requirements.memory_type_bits & requirements.memory_type_bits != 0
+            });
+
+            match slot {
+                Some(index) => {
+                    let slot = &mut slots[index];
+                    slot.images.push(i);
+                    slot.last = last;
+                    slot.requirements.size = slot.requirements.size.max(requirements.size);
+                    slot.requirements.alignment =
+                        slot.requirements.alignment.max(requirements.alignment);
+                    slot.requirements.memory_type_bits &= requirements.memory_type_bits;
+                    self.images[i].slot = Some(index);
+                }
+                None => {
+                    slots.push(Slot {
+                        images: vec![i],
+                        requirements,
+                        last,
+                    });
+                    self.images[i].slot = Some(slots.len() - 1);
+                }
+            }
+        }
+
+        slots
+    }
+
+    /// Works out the barriers before every pass and after the last one.
+    ///
+    /// The uses of an image follow each other in the order of the passes, with the export
+    /// after them. The images of a memory slot share a sequence of uses, as the memory is
+    /// what has to be synchronized, and each of them discards the contents of the previous.
+    /// The first use in a frame follows the last one, which happened in the previous frame.
+    fn create_barriers(&mut self) {
+        #[derive(Copy, Clone)]
+        struct Use {
+            position: usize,
+            image: usize,
+            state: AccessState,
+            discard: bool,
+        }
+
+        #[derive(Copy, Clone, PartialEq, Eq, Hash)]
+        enum Key {
+            Image(usize),
+            Slot(usize),
+        }
+
+        let key = |image: usize| self.images[image].slot.map_or(Key::Image(image), Key::Slot);
+        let mut keys = Vec::new();
+        let mut uses = HashMap::<Key, Vec<Use>>::new();
+
+        let mut image_uses = (self.order.iter().enumerate())
+            .flat_map(|(position, &p)| self.passes[p].images.iter().map(move |u| (position, *u)))
+            .map(|(position, u)| Use {
+                position,
+                image: u.image.0,
+                state: u.access.state(self.images[u.image.0].format),
+                discard: u.discard,
+            })
+            .collect::<Vec<_>>();
+
+        image_uses.extend(self.images.iter().enumerate().filter_map(|(i, image)| {
+            image.export.map(|access| Use {
+                position: self.order.len(),
+                image: i,
+                state: access.state(image.format),
+                discard: false,
+            })
+        }));
+
+        for u in image_uses {
+            let key = key(u.image);
+            if !uses.contains_key(&key) {
+                keys.push(key);
+            }
+            uses.entry(key).or_default().push(u);
+        }
+
+        let mut barriers = HashMap::<usize, Barriers>::new();
+        for key in keys {
+            let uses = &uses[&key];
+
+            for (j, current) in uses.iter().enumerate() {
+                let image = &self.images[current.image];
+                let (previous, previous_image) = match (j, image.source) {
+                    (0, ImageSource::Swapchain { acquired: true }) => (ACQUIRED, current.image),
+                    (0, _) => {
+                        let last = uses[uses.len() - 1];
+                        (last.state, last.image)
+                    }
+                    _ => (uses[j - 1].state, uses[j - 1].image),
+                };
+
+                let discard = current.discard
+                    || previous_image != current.image
+                    || (j == 0 && image.source == ImageSource::Transient);
+                let old_layout = if discard {
+                    vk::ImageLayout::UNDEFINED
+                } else {
+                    previous.layout
+                };
+
+                // Reads after reads in the same layout don't need to wait for each other.
+                if old_layout == current.state.layout
+                    && previous.writes.is_empty()
+                    && current.state.writes.is_empty()
+                {
+                    continue;
+                }
+
+                barriers
+                    .entry(current.position)
+                    .or_default()
+                    .images
+                    .push(ImageBarrier {
+                        image: ImageId(current.image),
+                        old_layout,
+                        new_layout: current.state.layout,
+                        // Only writes have to be made available, reads just have to finish.
+                        src: (previous.stages, previous.writes),
+                        dst: (
+                            current.state.stages,
+                            current.state.reads | current.state.writes,
+                        ),
+                    });
+            }
+        }
+
+        for (b, _) in self.buffers.iter().enumerate() {
+            let buffer = BufferId(b);
+            let uses = (self.order.iter().enumerate())
+                .flat_map(|(position, &p)| {
+                    (self.passes[p].buffers.iter())
+                        .filter(move |(other, _)| *other == buffer)
+                        .map(move |(_, access)| (position, access.state()))
+                })
+                .collect::<Vec<_>>();
+
+            for pair in uses.windows(2) {
+                let ((_, previous), (position, current)) = (pair[0], pair[1]);
+                if previous.writes.is_empty() && current.writes.is_empty() {
+                    continue;
+                }
+
+                barriers
+                    .entry(position)
+                    .or_default()
+                    .buffers
+                    .push(BufferBarrier {
+                        buffer,
+                        src: (previous.stages, previous.writes),
+                        dst: (current.stages, current.reads | current.writes),
+                    });
+            }
+        }
+
+        for (position, barriers) in barriers {
+            match self.order.get(position) {
+                Some(&p) => self.passes[p].barriers = barriers,
+                None => self.final_barriers = barriers,
+            }
+        }
+    }
+
+    /// Creates a render pass for every pass that draws, and its framebuffers.
+    unsafe fn create_render_passes(&mut self, device: &Device) -> Result<()> {
+        for p in 0..self.passes.len() {
+            let pass = &self.passes[p];
+            if !pass.draws() {
+                continue;
+            }
+
+            let color_attachments = (pass.color_attachments.iter())
+                .map(|a| self.pass_attachment(a, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL))
+                .collect::<Vec<_>>();
+            let resolve_attachments = (pass.color_attachments.iter())
+                .map(|a| a.resolve.map(|image| self.resolve_attachment(image)))
+                .collect::<Vec<_>>();
+            let depth_attachment = (pass.depth_attachment.as_ref()).map(|a| {
+                self.pass_attachment(a, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            });
+
+            let render_pass = create_render_pass(
+                device,
+                &color_attachments,
+                &resolve_attachments,
+                depth_attachment.as_ref(),
+            )?;
+            self.passes[p].render_pass = render_pass;
+
+            // A framebuffer for every swapchain image when the pass draws into one.
+            let pass = &self.passes[p];
+            let count = (pass.images.iter())
+                .map(|u| self.images[u.image.0].views.len())
+                .max()
+                .unwrap_or(1);
+
+            let mut framebuffers = Vec::with_capacity(count);
+            for index in 0..count {
+                let views = self.attachment_views(pass, index);
+                framebuffers.push(create_framebuffer(
+                    device,
+                    render_pass,
+                    &views,
+                    pass.extent,
+                )?);
+            }
+            self.passes[p].framebuffers = framebuffers;
+        }
+
+        Ok(())
+    }
+
+    fn pass_attachment(&self, attachment: &Attachment, layout: vk::ImageLayout) -> PassAttachment {
+        let image = &self.images[attachment.image.0];
+        let (load_op, clear_value) = match attachment.load {
+            LoadOp::Load => (vk::AttachmentLoadOp::LOAD, vk::ClearValue::default()),
+            LoadOp::Clear(value) => (vk::AttachmentLoadOp::CLEAR, value),
+            LoadOp::DontCare => (vk::AttachmentLoadOp::DONT_CARE, vk::ClearValue::default()),
+        };
+
+        PassAttachment {
+            format: image.format,
+            samples: image.samples,
+            layout,
+            load_op,
+            store_op: if attachment.store {
+                vk::AttachmentStoreOp::STORE
+            } else {
+                vk::AttachmentStoreOp::DONT_CARE
+            },
+            clear_value,
+        }
+    }
+
+    fn resolve_attachment(&self, image: ImageId) -> PassAttachment {
+        let image = &self.images[image.0];
+
+        PassAttachment {
+            format: image.format,
+            samples: image.samples,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            load_op: vk::AttachmentLoadOp::DONT_CARE,
+            store_op: vk::AttachmentStoreOp::STORE,
+            clear_value: vk::ClearValue::default(),
+        }
+    }
+
+    /// The view of an image in the frame drawing into the swapchain image with the index.
+    fn view(&self, image: ImageId, image_index: usize) -> vk::ImageView {
+        let views = &self.images[image.0].views;
+        views[image_index.min(views.len() - 1)]
+    }
+
+    fn handle(&self, image: ImageId, image_index: usize) -> vk::Image {
+        let handles = &self.images[image.0].handles;
+        handles[image_index.min(handles.len() - 1)]
+    }
+
+    /// The views of the attachments of a pass, in the order of the render pass.
+    fn attachment_views(&self, pass: &Pass, image_index: usize) -> Vec<vk::ImageView> {
+        (pass.color_attachments.iter())
+            .chain(&pass.depth_attachment)
+            .map(|a| a.image)
+            .chain(pass.color_attachments.iter().filter_map(|a| a.resolve))
+            .map(|image| self.view(image, image_index))
+            .collect()
+    }
+
+    /// The render pass and attachment formats a pipeline drawing in a pass is created for.
+    pub fn target(&self, pass: &str) -> Result<PassTarget, RenderGraphError> {
+        let pass = (self.passes.iter())
+            .find(|p| p.name == pass)
+            .ok_or_else(|| RenderGraphError::UnknownPass(pass.to_string()))?;
+
+        Ok(PassTarget {
+            render_pass: pass.render_pass,
+            color_formats: (pass.color_attachments.iter())
+                .map(|a| self.images[a.image.0].format)
+                .collect(),
+            depth_format: (pass.depth_attachment.as_ref())
+                .map_or(vk::Format::UNDEFINED, |a| self.images[a.image.0].format),
+        })
+    }
+
+    /// Whether `execute` writes timestamps around the passes of the frame. They aren't
+    /// timed without a query pool or with too many passes for it.
+    pub fn is_timed(&self, frame: &FrameContext) -> bool {
+        !frame.timestamp_pool.is_null() && self.order.len() <= MAX_TIMED_PASSES
+    }
+
+    /// The passes that `execute` writes timestamps around for the frame, in the order of
+    /// the queries. Empty when the frame isn't timed.
+    pub fn timed_passes(&self, frame: &FrameContext) -> Vec<String> {
+        if !self.is_timed(frame) {
+            return vec![];
+        }
+
+        (self.order.iter())
+            .map(|&p| self.passes[p].name.clone())
+            .collect()
+    }
+
+    /// Records the passes of a frame into its command buffer, which is recording.
+    ///
+    /// A timestamp is written before the barriers of each pass and after the pass has
+    /// ended (see queries.rs), so the time of a pass includes waiting for the passes it
+    /// depends on. Each pass, with its barriers, is also labeled with its name for
+    /// validation messages and frame debuggers (see `DebugUtils`).
+    pub unsafe fn execute(
+        &self,
+        device: &Device,
+        data: &AppData,
+        frame: &FrameContext,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+    ) {
+        let batches = data.scene.batches();
+        let context = PassContext {
+            device,
+            data,
+            frame,
+            command_buffer,
+            image_index,
+            batches: &batches,
+        };
+
+        let timed = self.is_timed(frame);
+        if timed {
+            // The queries must be reset before they are written again.
+            device.cmd_reset_query_pool(
+                command_buffer,
+                frame.timestamp_pool,
+                0,
+                2 * self.order.len() as u32,
+            );
+        }
+
+        for (i, &p) in self.order.iter().enumerate() {
+            let pass = &self.passes[p];
+            if timed {
+                device.cmd_write_timestamp(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    frame.timestamp_pool,
+                    2 * i as u32,
+                );
+            }
+
+            data.debug_utils.begin_label(command_buffer, &pass.name);
+            self.cmd_barriers(&context, &pass.barriers);
+
+            if pass.draws() {
+                if self.dynamic_rendering {
+                    self.cmd_begin_rendering(&context, pass);
+                } else {
+                    self.cmd_begin_render_pass(&context, pass);
+                }
+            }
+
+            (pass.record)(&context);
+
+            if pass.draws() {
+                if self.dynamic_rendering {
+                    device.cmd_end_rendering(command_buffer);
+                } else {
+                    device.cmd_end_render_pass(command_buffer);
+                }
+            }
+            data.debug_utils.end_label(command_buffer);
+
+            if timed {
+                device.cmd_write_timestamp(
+                    command_buffer,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    frame.timestamp_pool,
+                    2 * i as u32 + 1,
+                );
+            }
+        }
+
+        self.cmd_barriers(&context, &self.final_barriers);
+    }
+
+    /// Records barriers with synchronization2 when dynamic rendering is used, as the
+    /// device supports both then. Otherwise they are recorded in a single Vulkan 1.0
+    /// barrier waiting for all their source stages.
+    unsafe fn cmd_barriers(&self, context: &PassContext, barriers: &Barriers) {
+        if barriers.images.is_empty() && barriers.buffers.is_empty() {
+            return;
+        }
+
+        let image_barriers = (barriers.images.iter()).map(|b| {
+            vk::ImageMemoryBarrier2::builder()
+                .src_stage_mask(b.src.0)
+                .src_access_mask(b.src.1)
+                .dst_stage_mask(b.dst.0)
+                .dst_access_mask(b.dst.1)
+                .old_layout(b.old_layout)
+                .new_layout(b.new_layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(self.handle(b.image, context.image_index))
+                .subresource_range(subresource_range(self.images[b.image.0].format))
+                .build()
+        });
+
+        let buffer_barriers = (barriers.buffers.iter()).map(|b| {
+            vk::BufferMemoryBarrier2::builder()
+                .src_stage_mask(b.src.0)
+                .src_access_mask(b.src.1)
+                .dst_stage_mask(b.dst.0)
+                .dst_access_mask(b.dst.1)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer((self.buffers[b.buffer.0].handle)(
+                    context.data,
+                    context.frame,
+                ))
+                .offset(0)
+                .size(vk::WHOLE_SIZE as u64)
+                .build()
+        });
+
+        if self.dynamic_rendering {
+            let image_barriers = image_barriers.collect::<Vec<_>>();
+            let buffer_barriers = buffer_barriers.collect::<Vec<_>>();
+            let info = vk::DependencyInfo::builder()
+                .image_memory_barriers(&image_barriers)
+                .buffer_memory_barriers(&buffer_barriers);
+
+            context
+                .device
+                .cmd_pipeline_barrier2(context.command_buffer, &info);
+            return;
+        }
+
+        // The Vulkan 1.0 stages and accesses have the same bits as their synchronization2
+        // counterparts.
+        let stages = |s: vk::PipelineStageFlags2| {
+            vk::PipelineStageFlags::from_bits_truncate(s.bits() as u32)
+        };
+        let access = |a: vk::AccessFlags2| vk::AccessFlags::from_bits_truncate(a.bits() as u32);
+
+        let mut src_stages = vk::PipelineStageFlags::empty();
+        let mut dst_stages = vk::PipelineStageFlags::empty();
+
+        let image_barriers = image_barriers
+            .map(|b| {
+                src_stages |= stages(b.src_stage_mask);
+                dst_stages |= stages(b.dst_stage_mask);
+                vk::ImageMemoryBarrier::builder()
+                    .src_access_mask(access(b.src_access_mask))
+                    .dst_access_mask(access(b.dst_access_mask))
+                    .old_layout(b.old_layout)
+                    .new_layout(b.new_layout)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .image(b.image)
+                    .subresource_range(b.subresource_range)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let buffer_barriers = buffer_barriers
+            .map(|b| {
+                src_stages |= stages(b.src_stage_mask);
+                dst_stages |= stages(b.dst_stage_mask);
+                vk::BufferMemoryBarrier::builder()
+                    .src_access_mask(access(b.src_access_mask))
+                    .dst_access_mask(access(b.dst_access_mask))
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .buffer(b.buffer)
+                    .offset(b.offset)
+                    .size(b.size)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        // Vulkan 1.0 has no stage for "nothing", the top and bottom of the pipe are used
+        // instead.
+        if src_stages.is_empty() {
+            src_stages = vk::PipelineStageFlags::TOP_OF_PIPE;
+        }
+        if dst_stages.is_empty() {
+            dst_stages = vk::PipelineStageFlags::BOTTOM_OF_PIPE;
+        }
+
+        context.device.cmd_pipeline_barrier(
+            context.command_buffer,
+            src_stages,
+            dst_stages,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &buffer_barriers,
+            &image_barriers,
+        );
+    }
+
+    /// Binds the attachments of a pass with dynamic rendering (core in Vulkan 1.3), which
+    /// draws into image views without render pass and framebuffer objects.
+    unsafe fn cmd_begin_rendering(&self, context: &PassContext, pass: &Pass) {
+        let attachment_info = |attachment: &Attachment, layout: vk::ImageLayout| {
+            let a = self.pass_attachment(attachment, layout);
+            let mut info = vk::RenderingAttachmentInfo::builder()
+                .image_view(self.view(attachment.image, context.image_index))
+                .image_layout(a.layout)
+                .load_op(a.load_op)
+                .store_op(a.store_op)
+                .clear_value(a.clear_value);
+
+            // The resolve replaces the resolve attachments of a render pass.
+            if let Some(resolve) = attachment.resolve {
+                info = info
+                    .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+                    .resolve_image_view(self.view(resolve, context.image_index))
+                    .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+            }
+
+            info.build()
+        };
+
+        let color_attachments = (pass.color_attachments.iter())
+            .map(|a| attachment_info(a, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL))
+            .collect::<Vec<_>>();
+        let depth_attachment = (pass.depth_attachment.as_ref())
+            .map(|a| attachment_info(a, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL));
+
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::default())
+            .extent(pass.extent);
+
+        let mut info = vk::RenderingInfo::builder()
+            .render_area(render_area)
+            .layer_count(1)
+            .color_attachments(&color_attachments);
+
+        if let Some(depth_attachment) = &depth_attachment {
+            info = info.depth_attachment(depth_attachment);
+        }
+
+        context
+            .device
+            .cmd_begin_rendering(context.command_buffer, &info);
+    }
+
+    unsafe fn cmd_begin_render_pass(&self, context: &PassContext, pass: &Pass) {
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::default())
+            .extent(pass.extent);
+
+        // The order of clear values should be identical to the order of attachments.
+        let clear_values = (pass.color_attachments.iter())
+            .chain(&pass.depth_attachment)
+            .map(|a| match a.load {
+                LoadOp::Clear(value) => value,
+                _ => vk::ClearValue::default(),
+            })
+            .collect::<Vec<_>>();
+
+        let framebuffers = &pass.framebuffers;
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(pass.render_pass)
+            .framebuffer(framebuffers[context.image_index.min(framebuffers.len() - 1)])
+            .render_area(render_area)
+            .clear_values(&clear_values);
+
+        context.device.cmd_begin_render_pass(
+            context.command_buffer,
+            &info,
+            vk::SubpassContents::INLINE,
+        );
+    }
+
+    /// Destroys the render passes and framebuffers and drops the transient images. The
+    /// graph is empty afterwards.
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        for pass in &self.passes {
+            pass.framebuffers
+                .iter()
+                .for_each(|f| device.destroy_framebuffer(*f, None));
+            device.destroy_render_pass(pass.render_pass, None);
+        }
+
+        *self = Self::default();
+    }
+}
+
+fn is_depth_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::D16_UNORM
+            | vk::Format::X8_D24_UNORM_PACK32
+            | vk::Format::D32_SFLOAT
+            | vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D32_SFLOAT_S8_UINT
+    )
+}
+
+/// The whole of a single mip level image. The layout of a depth image with a stencil
+/// component can only be transitioned along with the stencil.
+fn subresource_range(format: vk::Format) -> vk::ImageSubresourceRange {
+    let aspect_mask = match format {
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ if is_depth_format(format) => vk::ImageAspectFlags::DEPTH,
+        _ => vk::ImageAspectFlags::COLOR,
+    };
+
+    vk::ImageSubresourceRange::builder()
+        .aspect_mask(aspect_mask)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vk::ImageLayout as L;
+
+    unsafe fn record(_: &PassContext) {}
+
+    fn desc() -> ImageDesc {
+        ImageDesc {
+            format: vk::Format::R8G8B8A8_UNORM,
+            extent: vk::Extent2D {
+                width: 16,
+                height: 16,
+            },
+            samples: vk::SampleCountFlags::_1,
+        }
+    }
+
+    fn clear(image: ImageId) -> Attachment {
+        Attachment {
+            image,
+            load: LoadOp::Clear(vk::ClearValue::default()),
+            store: true,
+            resolve: None,
+        }
+    }
+
+    /// The same memory requirements for every transient image.
+    fn requirements(graph: &RenderGraph) -> HashMap<usize, vk::MemoryRequirements> {
+        (0..graph.images.len())
+            .filter(|&i| graph.images[i].source == ImageSource::Transient)
+            .map(|i| {
+                let requirements = vk::MemoryRequirements {
+                    size: 1024,
+                    alignment: 256,
+                    memory_type_bits: 0b11,
+                };
+                (i, requirements)
+            })
+            .collect()
+    }
+
+    /// Compiles the graph like `compile`, without creating anything.
+    fn schedule(graph: &mut RenderGraph) -> Result<(), RenderGraphError> {
+        graph.order = graph.sort_passes()?;
+        graph.check_passes()?;
+        graph.assign_slots(&requirements(graph));
+        graph.create_barriers();
+        Ok(())
+    }
+
+    fn order(graph: &RenderGraph) -> Vec<&str> {
+        (graph.order.iter())
+            .map(|&p| graph.passes[p].name.as_str())
+            .collect()
+    }
+
+    fn layouts(barriers: &Barriers, image: ImageId) -> Vec<(vk::ImageLayout, vk::ImageLayout)> {
+        (barriers.images.iter())
+            .filter(|b| b.image == image)
+            .map(|b| (b.old_layout, b.new_layout))
+            .collect()
+    }
+
+    #[test]
+    fn passes_run_after_the_passes_writing_what_they_read() {
+        let mut graph = RenderGraph::default();
+        let image = graph.create_image("image", desc());
+        let output = graph.create_image("output", desc());
+
+        graph
+            .add_pass("reader", record)
+            .image(image, ImageAccess::Sampled)
+            .color_attachment(clear(output));
+        graph.add_pass("unrelated", record);
+        graph
+            .add_pass("writer", record)
+            .color_attachment(clear(image));
+
+        schedule(&mut graph).unwrap();
+        assert_eq!(order(&graph), ["unrelated", "writer", "reader"]);
+    }
+
+    #[test]
+    fn passes_reading_what_each_other_write_are_a_cycle() {
+        let mut graph = RenderGraph::default();
+        let a = graph.create_image("a", desc());
+        let b = graph.create_image("b", desc());
+
+        graph
+            .add_pass("first", record)
+            .image(a, ImageAccess::Sampled)
+            .color_attachment(clear(b));
+        graph
+            .add_pass("second", record)
+            .image(b, ImageAccess::Sampled)
+            .color_attachment(clear(a));
+        graph.add_pass("independent", record);
+
+        match schedule(&mut graph) {
+            Err(RenderGraphError::Cycle(passes)) => assert_eq!(passes, ["first", "second"]),
+            result => panic!("expected a cycle, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn transient_images_must_be_written_before_they_are_read() {
+        let mut graph = RenderGraph::default();
+        let image = graph.create_image("image", desc());
+        graph
+            .add_pass("reader", record)
+            .image(image, ImageAccess::Sampled);
+
+        match schedule(&mut graph) {
+            Err(RenderGraphError::ReadBeforeWrite(image, pass)) => {
+                assert_eq!((image.as_str(), pass.as_str()), ("image", "reader"))
+            }
+            result => panic!("expected a read before write, got {:?}", result),
+        }
+
+        // Imported images keep their contents from the previous frame.
+        let mut graph = RenderGraph::default();
+        let image = graph.import_image(
+            "image",
+            vk::Image::null(),
+            vk::ImageView::null(),
+            desc().format,
+            desc().extent,
+        );
+        graph
+            .add_pass("reader", record)
+            .image(image, ImageAccess::Sampled);
+
+        schedule(&mut graph).unwrap();
+    }
+
+    #[test]
+    fn a_pass_uses_an_image_in_one_way() {
+        let mut graph = RenderGraph::default();
+        let image = graph.create_image("image", desc());
+        graph
+            .add_pass("pass", record)
+            .color_attachment(clear(image))
+            .image(image, ImageAccess::Sampled);
+
+        match schedule(&mut graph) {
+            Err(RenderGraphError::ConflictingAccess(image, pass)) => {
+                assert_eq!((image.as_str(), pass.as_str()), ("image", "pass"))
+            }
+            result => panic!("expected a conflicting access, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn images_with_disjoint_lifetimes_share_a_slot() {
+        let mut graph = RenderGraph::default();
+        let a = graph.create_image("a", desc());
+        let b = graph.create_image("b", desc());
+        let c = graph.create_image("c", desc());
+        let unused = graph.create_image("unused", desc());
+
+        graph.add_pass("first", record).color_attachment(clear(a));
+        graph
+            .add_pass("second", record)
+            .image(a, ImageAccess::Sampled)
+            .color_attachment(clear(b));
+        graph
+            .add_pass("third", record)
+            .image(b, ImageAccess::Sampled)
+            .color_attachment(clear(c));
+
+        graph.order = graph.sort_passes().unwrap();
+        graph.check_passes().unwrap();
+
+        let mut memory = requirements(&graph);
+        memory.get_mut(&c.0).unwrap().size = 4096;
+        memory.get_mut(&c.0).unwrap().alignment = 512;
+        let slots = graph.assign_slots(&memory);
+
+        // `b` is used along with both, but `c` is only used after `a`.
+        assert_eq!(slots.len(), 2);
+        assert_eq!(slots[0].images, [a.0, c.0]);
+        assert_eq!(slots[1].images, [b.0]);
+        assert_eq!(graph.images[c.0].slot, Some(0));
+        assert_eq!(graph.images[unused.0].slot, None);
+
+        // The slot fits every image in it.
+        assert_eq!(slots[0].requirements.size, 4096);
+        assert_eq!(slots[0].requirements.alignment, 512);
+
+        // Images that can't live in the same memory type never share.
+        let mut memory = requirements(&graph);
+        memory.get_mut(&c.0).unwrap().memory_type_bits = 0b100;
+        let slots = graph.assign_slots(&memory);
+        assert_eq!(slots.len(), 3);
+    }
+
+    #[test]
+    fn the_shadow_map_and_swapchain_are_transitioned_for_each_use() {
+        let data = AppData {
+            swapchain_extent: desc().extent,
+            ..Default::default()
+        };
+        let mut graph = RenderGraph::default();
+
+        let swapchain = graph.import_swapchain(&data);
+        let color = graph.create_image("color", desc());
+        let shadow_map = graph.import_image(
+            "shadow map",
+            vk::Image::null(),
+            vk::ImageView::null(),
+            vk::Format::D32_SFLOAT,
+            desc().extent,
+        );
+
+        // The passes of `create_render_graph`, added in the other order.
+        graph
+            .add_pass("main", record)
+            .color_attachment(Attachment {
+                store: false,
+                resolve: Some(swapchain),
+                ..clear(color)
+            })
+            .image(shadow_map, ImageAccess::Sampled);
+        graph
+            .add_pass("shadow", record)
+            .depth_attachment(clear(shadow_map));
+
+        schedule(&mut graph).unwrap();
+        assert_eq!(order(&graph), ["shadow", "main"]);
+
+        // The shadow map is cleared, so its contents from the previous frame are
+        // discarded, and it's sampled after it's drawn.
+        let shadow = &graph.passes[1].barriers;
+        assert_eq!(
+            layouts(shadow, shadow_map),
+            [(L::UNDEFINED, L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)]
+        );
+        let main = &graph.passes[0].barriers;
+        assert_eq!(
+            layouts(main, shadow_map),
+            [(
+                L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                L::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+            )]
+        );
+
+        // The swapchain image is resolved into once it's acquired, and presented after
+        // the frame.
+        assert_eq!(
+            layouts(main, swapchain),
+            [(L::UNDEFINED, L::COLOR_ATTACHMENT_OPTIMAL)]
+        );
+        let acquire = main.images.iter().find(|b| b.image == swapchain).unwrap();
+        assert_eq!(
+            acquire.src.0,
+            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT
+        );
+        assert_eq!(
+            layouts(&graph.final_barriers, swapchain),
+            [(L::COLOR_ATTACHMENT_OPTIMAL, L::PRESENT_SRC_KHR)]
+        );
+        assert!(layouts(&graph.final_barriers, shadow_map).is_empty());
+    }
+}
@@ -0,0 +1,300 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use vulkanalia::prelude::v1_3::*;
+
+use crate::app::MAX_FRAMES_IN_FLIGHT;
+
+use super::allocator::{Allocation, Allocator};
+
+/// A handle that was dropped by its owner and waits to be destroyed.
+#[derive(Copy, Clone, Debug)]
+enum Garbage {
+    Buffer(vk::Buffer, Allocation),
+    /// An image along with its memory, if it owns it.
+    Image(vk::Image, Option<Allocation>),
+    /// Memory that several resources are bound to.
+    Memory(Allocation),
+    ImageView(vk::ImageView),
+    Sampler(vk::Sampler),
+    Pipeline(vk::Pipeline),
+}
+
+impl Garbage {
+    unsafe fn destroy(self, device: &Device, allocator: &mut Allocator) {
+        match self {
+            Garbage::Buffer(buffer, allocation) => {
+                device.destroy_buffer(buffer, None);
+                allocator.free(device, &allocation);
+            }
+            Garbage::Image(image, allocation) => {
+                device.destroy_image(image, None);
+                if let Some(allocation) = allocation {
+                    allocator.free(device, &allocation);
+                }
+            }
+            Garbage::Memory(allocation) => allocator.free(device, &allocation),
+            Garbage::ImageView(view) => device.destroy_image_view(view, None),
+            Garbage::Sampler(sampler) => device.destroy_sampler(sampler, None),
+            Garbage::Pipeline(pipeline) => device.destroy_pipeline(pipeline, None),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct DeletionQueueState {
+    /// The number of frames started so far.
+    frame: u64,
+    /// The dropped handles along with the frame they were dropped in, oldest first.
+    pending: VecDeque<(u64, Garbage)>,
+}
+
+/// Destroys the resources that were dropped, once the GPU is done with them.
+///
+/// A resource may still be used by the command buffers of the frames in flight when its
+/// owner drops it, e.g. when the shadow map is rebuilt or the instance buffers grow. So
+/// instead of destroying it right away, the resource is queued along with the current
+/// frame. Once `MAX_FRAMES_IN_FLIGHT` more frames have started, the fences of the frames
+/// that could have used it have been waited for and it is destroyed (see `next_frame`).
+///
+/// The queue is shared by every resource created from `AppData`, cloning it only clones
+/// the reference.
+#[derive(Clone, Debug, Default)]
+pub struct DeletionQueue(Rc<RefCell<DeletionQueueState>>);
+
+impl DeletionQueue {
+    fn push(&self, garbage: Garbage) {
+        let mut state = self.0.borrow_mut();
+        let frame = state.frame;
+        state.pending.push_back((frame, garbage));
+    }
+
+    /// Starts a new frame, destroying the resources that were dropped at least
+    /// `MAX_FRAMES_IN_FLIGHT` frames ago. Called after waiting for the fence of the frame.
+    pub unsafe fn next_frame(&self, device: &Device, allocator: &mut Allocator) {
+        let mut state = self.0.borrow_mut();
+        state.frame += 1;
+
+        while let Some((dropped, garbage)) = state.pending.front().copied() {
+            if dropped + MAX_FRAMES_IN_FLIGHT as u64 > state.frame {
+                break;
+            }
+
+            state.pending.pop_front();
+            garbage.destroy(device, allocator);
+        }
+    }
+
+    /// Destroys every resource that was dropped, whether the GPU may still use it or not.
+    /// Only call this once the device is idle.
+    pub unsafe fn flush(&self, device: &Device, allocator: &mut Allocator) {
+        for (_, garbage) in self.0.borrow_mut().pending.drain(..) {
+            garbage.destroy(device, allocator);
+        }
+    }
+
+    /// The number of resources waiting to be destroyed.
+    pub fn len(&self) -> usize {
+        self.0.borrow().pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A buffer along with its memory. Dropping it hands both to the deletion queue.
+#[derive(Debug, Default)]
+pub struct Buffer {
+    handle: vk::Buffer,
+    allocation: Allocation,
+    queue: Option<DeletionQueue>,
+}
+
+impl Buffer {
+    pub fn new(handle: vk::Buffer, allocation: Allocation, queue: &DeletionQueue) -> Self {
+        Self {
+            handle,
+            allocation,
+            queue: Some(queue.clone()),
+        }
+    }
+
+    pub fn handle(&self) -> vk::Buffer {
+        self.handle
+    }
+
+    pub fn allocation(&self) -> &Allocation {
+        &self.allocation
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        if let Some(queue) = self.queue.take() {
+            queue.push(Garbage::Buffer(self.handle, self.allocation));
+        }
+    }
+}
+
+/// An image along with its memory. Dropping it hands both to the deletion queue.
+///
+/// The swapchain images are owned by the swapchain, so they are kept as plain handles.
+#[derive(Debug, Default)]
+pub struct Image {
+    handle: vk::Image,
+    /// `None` for images bound to memory they share with others (see `Memory`).
+    allocation: Option<Allocation>,
+    queue: Option<DeletionQueue>,
+}
+
+impl Image {
+    pub fn new(handle: vk::Image, allocation: Allocation, queue: &DeletionQueue) -> Self {
+        Self {
+            handle,
+            allocation: Some(allocation),
+            queue: Some(queue.clone()),
+        }
+    }
+
+    /// An image bound to memory it doesn't own, like the images of the render graph
+    /// that alias each other. The memory is freed when its `Memory` is dropped.
+    pub fn aliased(handle: vk::Image, queue: &DeletionQueue) -> Self {
+        Self {
+            handle,
+            allocation: None,
+            queue: Some(queue.clone()),
+        }
+    }
+
+    pub fn handle(&self) -> vk::Image {
+        self.handle
+    }
+
+    pub fn allocation(&self) -> Option<&Allocation> {
+        self.allocation.as_ref()
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        if let Some(queue) = self.queue.take() {
+            queue.push(Garbage::Image(self.handle, self.allocation));
+        }
+    }
+}
+
+/// Memory that resources are bound to without owning it, handed to the deletion queue
+/// when dropped.
+#[derive(Debug, Default)]
+pub struct Memory {
+    allocation: Allocation,
+    queue: Option<DeletionQueue>,
+}
+
+impl Memory {
+    pub fn new(allocation: Allocation, queue: &DeletionQueue) -> Self {
+        Self {
+            allocation,
+            queue: Some(queue.clone()),
+        }
+    }
+
+    pub fn allocation(&self) -> &Allocation {
+        &self.allocation
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        if let Some(queue) = self.queue.take() {
+            queue.push(Garbage::Memory(self.allocation));
+        }
+    }
+}
+
+/// An image view, handed to the deletion queue when dropped.
+#[derive(Debug, Default)]
+pub struct ImageView {
+    handle: vk::ImageView,
+    queue: Option<DeletionQueue>,
+}
+
+impl ImageView {
+    pub fn new(handle: vk::ImageView, queue: &DeletionQueue) -> Self {
+        Self {
+            handle,
+            queue: Some(queue.clone()),
+        }
+    }
+
+    pub fn handle(&self) -> vk::ImageView {
+        self.handle
+    }
+}
+
+impl Drop for ImageView {
+    fn drop(&mut self) {
+        if let Some(queue) = self.queue.take() {
+            queue.push(Garbage::ImageView(self.handle));
+        }
+    }
+}
+
+/// A sampler, handed to the deletion queue when dropped.
+#[derive(Debug, Default)]
+pub struct Sampler {
+    handle: vk::Sampler,
+    queue: Option<DeletionQueue>,
+}
+
+impl Sampler {
+    pub fn new(handle: vk::Sampler, queue: &DeletionQueue) -> Self {
+        Self {
+            handle,
+            queue: Some(queue.clone()),
+        }
+    }
+
+    pub fn handle(&self) -> vk::Sampler {
+        self.handle
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        if let Some(queue) = self.queue.take() {
+            queue.push(Garbage::Sampler(self.handle));
+        }
+    }
+}
+
+/// A graphics pipeline, handed to the deletion queue when dropped. Its layout isn't
+/// owned by it, as several pipelines may share a layout.
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    handle: vk::Pipeline,
+    queue: Option<DeletionQueue>,
+}
+
+impl Pipeline {
+    pub fn new(handle: vk::Pipeline, queue: &DeletionQueue) -> Self {
+        Self {
+            handle,
+            queue: Some(queue.clone()),
+        }
+    }
+
+    pub fn handle(&self) -> vk::Pipeline {
+        self.handle
+    }
+}
+
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        if let Some(queue) = self.queue.take() {
+            queue.push(Garbage::Pipeline(self.handle));
+        }
+    }
+}
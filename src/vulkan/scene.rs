@@ -0,0 +1,199 @@
+use std::path::Path;
+
+use anyhow::Result;
+use cgmath::vec4;
+use vulkanalia::prelude::v1_3::*;
+
+use crate::app::AppData;
+
+use super::buffers::index_buffer::create_index_buffer;
+use super::buffers::instance_buffer::InstanceData;
+use super::buffers::uniform_buffer::Mat4;
+use super::buffers::vertex_buffer::create_vertex_buffer;
+use super::material::{assign_default_material, create_material_textures};
+use super::model::{load_model, Bounds, Primitive};
+use super::resources::Buffer;
+use super::vertex::{Vec4, Vertex};
+
+/// The geometry of a model loaded from a file, with its own vertex and index buffers.
+/// A mesh is loaded once and can be drawn by any number of objects.
+#[derive(Debug, Default)]
+pub struct Mesh {
+    /// Where the mesh was loaded from.
+    pub name: String,
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    /// The ranges of the index buffer that make up the mesh, each with its own material.
+    pub primitives: Vec<Primitive>,
+    /// Encloses the vertices of the mesh in model space.
+    pub bounds: Bounds,
+    pub vertex_buffer: Buffer,
+    pub index_buffer: Buffer,
+}
+
+/// Identifies an object of the scene. Unlike an index into `Scene::objects`, it stays
+/// valid when other objects are removed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ObjectId(u64);
+
+/// An instance of a mesh placed in the scene by its model matrix.
+#[derive(Copy, Clone, Debug)]
+pub struct SceneObject {
+    pub id: ObjectId,
+    /// Index into `Scene::meshes`.
+    pub mesh: usize,
+    /// The model matrix, which transforms the mesh from model space into world space.
+    pub transform: Mat4,
+    /// Multiplied with the base color of the object, white leaves it unchanged.
+    pub tint: Vec4,
+    /// Draws the object with this texture of `AppData::textures` instead of the base
+    /// color textures of its materials. Only the first `MAX_INSTANCE_TEXTURES` can be used.
+    pub texture: Option<usize>,
+}
+
+impl SceneObject {
+    pub fn instance_data(&self) -> InstanceData {
+        InstanceData {
+            transform: self.transform,
+            tint: self.tint,
+            texture_index: self.texture.map_or(-1, |t| t as i32),
+        }
+    }
+}
+
+/// The objects that draw the same mesh, which are drawn together with a single
+/// instanced draw per primitive. Their instance data is the range of the instance
+/// buffer starting at `first_instance`.
+#[derive(Copy, Clone, Debug)]
+pub struct Batch {
+    pub mesh: usize,
+    pub first_instance: u32,
+    pub instance_count: u32,
+}
+
+/// The meshes that are loaded and the objects that draw them.
+///
+/// The objects are drawn as instances of their meshes. The instance data (transform,
+/// tint and texture) is written to the instance buffer and the draws are recorded every
+/// frame, so objects can be added, removed and changed freely.
+#[derive(Debug, Default)]
+pub struct Scene {
+    pub meshes: Vec<Mesh>,
+    pub objects: Vec<SceneObject>,
+    next_object_id: u64,
+}
+
+impl Scene {
+    /// Adds an object that draws the given mesh with the given model matrix.
+    pub fn add_object(&mut self, mesh: usize, transform: Mat4) -> ObjectId {
+        let id = ObjectId(self.next_object_id);
+        self.next_object_id += 1;
+        self.objects.push(SceneObject {
+            id,
+            mesh,
+            transform,
+            tint: vec4(1.0, 1.0, 1.0, 1.0),
+            texture: None,
+        });
+        id
+    }
+
+    /// Removes an object, returning it if it was in the scene.
+    pub fn remove_object(&mut self, id: ObjectId) -> Option<SceneObject> {
+        let index = self.objects.iter().position(|o| o.id == id)?;
+        Some(self.objects.remove(index))
+    }
+
+    pub fn object(&self, id: ObjectId) -> Option<&SceneObject> {
+        self.objects.iter().find(|o| o.id == id)
+    }
+
+    pub fn object_mut(&mut self, id: ObjectId) -> Option<&mut SceneObject> {
+        self.objects.iter_mut().find(|o| o.id == id)
+    }
+
+    /// The objects grouped by mesh, in the order of `instance_data`.
+    pub fn batches(&self) -> Vec<Batch> {
+        let mut batches: Vec<Batch> = Vec::new();
+
+        for object in self.sorted_objects() {
+            match batches.last_mut() {
+                Some(batch) if batch.mesh == object.mesh => batch.instance_count += 1,
+                last => {
+                    let first_instance = last.map_or(0, |b| b.first_instance + b.instance_count);
+                    batches.push(Batch {
+                        mesh: object.mesh,
+                        first_instance,
+                        instance_count: 1,
+                    });
+                }
+            }
+        }
+
+        batches
+    }
+
+    /// Every object as a batch of its own, in the order of the batches, so that the objects
+    /// can be drawn one by one (see queries.rs).
+    pub fn object_batches(&self) -> Vec<(ObjectId, Batch)> {
+        (self.sorted_objects().iter().enumerate())
+            .map(|(i, o)| {
+                let batch = Batch {
+                    mesh: o.mesh,
+                    first_instance: i as u32,
+                    instance_count: 1,
+                };
+                (o.id, batch)
+            })
+            .collect()
+    }
+
+    /// The instance data of every object, grouped by mesh like the batches.
+    pub fn instance_data(&self) -> Vec<InstanceData> {
+        self.sorted_objects()
+            .iter()
+            .map(|o| o.instance_data())
+            .collect()
+    }
+
+    fn sorted_objects(&self) -> Vec<&SceneObject> {
+        let mut objects = self.objects.iter().collect::<Vec<_>>();
+        // A stable sort keeps the objects of a mesh in the order they were added.
+        objects.sort_by_key(|o| o.mesh);
+        objects
+    }
+
+    /// Encloses every object in world space.
+    pub fn bounds(&self) -> Bounds {
+        self.objects
+            .iter()
+            .map(|o| self.meshes[o.mesh].bounds.transformed(o.transform))
+            .reduce(|a, b| a.union(&b))
+            .unwrap_or_default()
+    }
+}
+
+/// Loads a model as a new mesh of the scene and uploads it to the GPU, returning the
+/// index of the mesh. The mesh isn't drawn until an object is added for it.
+///
+/// The textures of the model are uploaded as well. Its materials need uniform buffers
+/// and descriptor sets, which the caller creates afterwards (see `App::load_mesh`).
+/// Primitives without a material get the default material with the given texture.
+pub unsafe fn load_mesh(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    path: &Path,
+    texture: &Path,
+) -> Result<usize> {
+    let mut mesh = load_model(data, path)?;
+    assign_default_material(data, &mut mesh, texture)?;
+    create_material_textures(instance, device, data)?;
+    create_vertex_buffer(instance, device, data, &mut mesh)?;
+    create_index_buffer(instance, device, data, &mut mesh)?;
+
+    data.scene.meshes.push(mesh);
+
+    Ok(data.scene.meshes.len() - 1)
+}
+
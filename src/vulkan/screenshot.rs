@@ -0,0 +1,170 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_3::*;
+
+use crate::app::AppData;
+
+use super::buffers::buffer::create_buffer;
+use super::errors::ScreenshotError;
+use super::render_graph::PassContext;
+use super::resources::Buffer;
+
+/// A screenshot that a frame in flight takes of the image it renders into.
+///
+/// The capture pass (see passes.rs) copies the swapchain image into the host-visible
+/// buffer after the frame is rendered and before it is presented. Once the frame's fence
+/// is signaled, the pixels are read (see `read_screenshot`) and written to a PNG file.
+#[derive(Debug)]
+pub struct Screenshot {
+    pub path: PathBuf,
+    pub extent: vk::Extent2D,
+    buffer: Buffer,
+    format: vk::Format,
+}
+
+/// Creates the buffer a screenshot of the current swapchain images is copied into.
+///
+/// The swapchain images must support being copied from, which is checked when the
+/// swapchain is created (see `AppData::capture_supported`).
+pub unsafe fn create_screenshot(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    path: PathBuf,
+) -> Result<Screenshot> {
+    if !data.capture_supported {
+        return Err(ScreenshotError::NotSupported.into());
+    }
+
+    let format = data.swapchain_format;
+    if channel_order(format).is_none() {
+        return Err(ScreenshotError::UnsupportedFormat(format).into());
+    }
+
+    let extent = data.swapchain_extent;
+    let size = (extent.width * extent.height * 4) as u64;
+    let buffer = create_buffer(
+        instance,
+        device,
+        data,
+        "screenshot buffer",
+        size,
+        vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    )?;
+
+    Ok(Screenshot {
+        path,
+        extent,
+        buffer,
+        format,
+    })
+}
+
+/// Records the copy of the frame's swapchain image into the buffer of its screenshot.
+/// Frames without a screenshot record nothing, but the graph still moves the image
+/// into the TRANSFER_SRC_OPTIMAL layout for the pass and back out of it.
+pub unsafe fn record_capture_pass(ctx: &PassContext) {
+    let Some(screenshot) = &ctx.frame.screenshot else {
+        return;
+    };
+
+    let image_subresource = vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(0)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    let region = vk::BufferImageCopy::builder()
+        .buffer_offset(0)
+        .buffer_row_length(0)
+        .buffer_image_height(0)
+        .image_subresource(image_subresource)
+        .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+        .image_extent(vk::Extent3D {
+            width: screenshot.extent.width,
+            height: screenshot.extent.height,
+            depth: 1,
+        });
+
+    ctx.device.cmd_copy_image_to_buffer(
+        ctx.command_buffer,
+        ctx.data.swapchain_images[ctx.image_index],
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        screenshot.buffer.handle(),
+        &[region],
+    );
+
+    // Makes the copied data visible to the CPU once the commands have finished.
+    let buffer_barrier = vk::BufferMemoryBarrier::builder()
+        .buffer(screenshot.buffer.handle())
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .offset(0)
+        .size(vk::WHOLE_SIZE as u64)
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::HOST_READ);
+
+    ctx.device.cmd_pipeline_barrier(
+        ctx.command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::HOST,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[buffer_barrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+}
+
+/// Reads the pixels of a screenshot as 8-bit RGBA. The frame that took it must have
+/// finished.
+pub unsafe fn read_screenshot(data: &AppData, screenshot: &Screenshot) -> Result<Vec<u8>> {
+    let size = (screenshot.extent.width * screenshot.extent.height * 4) as usize;
+    let memory = data.allocator.mapped(screenshot.buffer.allocation())?;
+    let pixels = std::slice::from_raw_parts(memory.cast::<u8>(), size);
+
+    Ok(to_rgba(pixels, screenshot.format)?)
+}
+
+/// Converts the pixels of an image with 4 bytes per pixel to 8-bit RGBA.
+///
+/// The swapchain is usually BGRA, so the channels are swapped into RGBA. The bytes are
+/// kept as they are otherwise: the sRGB formats store them gamma encoded, and the bytes
+/// of the UNORM formats are shown on the screen as if they were. The window is opaque,
+/// so the alpha is too.
+pub fn to_rgba(pixels: &[u8], format: vk::Format) -> Result<Vec<u8>, ScreenshotError> {
+    let order = channel_order(format).ok_or(ScreenshotError::UnsupportedFormat(format))?;
+
+    Ok(pixels
+        .chunks_exact(4)
+        .flat_map(|p| [p[order[0]], p[order[1]], p[order[2]], u8::MAX])
+        .collect())
+}
+
+/// Writes 8-bit RGBA pixels (see `to_rgba`) to a PNG file, marked as sRGB.
+pub fn write_png(path: &Path, rgba: &[u8], extent: vk::Extent2D) -> Result<()> {
+    let writer = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(writer, extent.width, extent.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// The byte offsets of the red, green and blue channels of a pixel, for the formats that
+/// can be written to a PNG file.
+fn channel_order(format: vk::Format) -> Option<[usize; 3]> {
+    match format {
+        vk::Format::R8G8B8A8_SRGB | vk::Format::R8G8B8A8_UNORM => Some([0, 1, 2]),
+        vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM => Some([2, 1, 0]),
+        _ => None,
+    }
+}
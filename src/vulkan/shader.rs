@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+
+use anyhow::{anyhow, Result};
+use log::*;
+use naga::back::spv;
+use naga::front::wgsl;
+use naga::valid::{Capabilities, ModuleInfo, ValidationFlags, Validator};
+use naga::{Expression, Module, TypeInner};
+use notify::event::{EventKind, ModifyKind};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use vulkanalia::prelude::v1_3::*;
+
+use super::errors::ShaderError;
+use super::reflection::ShaderReflection;
+
+/// The directory the shaders are built into the binary from. It's used instead of the
+/// built-in shaders when it exists, i.e. when running from a checkout of the sources, so
+/// the shaders can be edited without rebuilding.
+pub const SHADER_SOURCE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/vulkan/shaders");
+
+/// The shaders of the renderer. Each is a WGSL file in the shader directory, which holds
+/// the entry points of every stage of a pipeline.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Shader {
+    /// The vertex and fragment shaders of the main pipeline.
+    Main,
+    /// The vertex shader of the shadow pipeline.
+    Shadow,
+}
+
+impl Shader {
+    pub const ALL: [Shader; 2] = [Shader::Main, Shader::Shadow];
+
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Shader::Main => "shader.wgsl",
+            Shader::Shadow => "shadow.wgsl",
+        }
+    }
+
+    /// The WGSL source built into the binary, used when there is no shader directory.
+    pub fn builtin_source(self) -> &'static str {
+        match self {
+            Shader::Main => include_str!("shaders/shader.wgsl"),
+            Shader::Shadow => include_str!("shaders/shadow.wgsl"),
+        }
+    }
+}
+
+/// The SPIR-V code of the shaders, compiled from their WGSL source at runtime.
+///
+/// The shaders are compiled with naga, so no external compiler is needed. They are read
+/// from a directory if there is one, otherwise the sources built into the binary are
+/// used. When the library watches the shader directory, `poll` recompiles the shaders
+/// whose files changed. A shader that fails to compile keeps its previous code, so the pipelines
+/// using it can still be created.
+///
+/// The descriptor set and pipeline layouts are built from the reflected interfaces of the
+/// shaders as they were loaded (see reflection.rs). They aren't rebuilt when a shader
+/// changes, so a recompiled shader must fit them.
+#[derive(Debug, Default)]
+pub struct ShaderLibrary {
+    /// `None` when the built-in sources are used.
+    dir: Option<PathBuf>,
+    /// Whether the device supports non-uniform indexing, see `compile_wgsl`.
+    non_uniform_indexing: bool,
+    compiled: HashMap<Shader, CompiledShader>,
+    /// The shaders that were replaced by the last `poll`, see `revert`.
+    previous: HashMap<Shader, CompiledShader>,
+    /// The bindings of every shader, which the descriptor set layouts are built from.
+    descriptor_sets: ShaderReflection,
+    /// The interface each pipeline layout is built for, the bindings of the sets the
+    /// shader uses and its push constants.
+    layouts: HashMap<Shader, ShaderReflection>,
+    watcher: Option<ShaderWatcher>,
+}
+
+#[derive(Debug)]
+struct CompiledShader {
+    code: Vec<u32>,
+    reflection: ShaderReflection,
+}
+
+/// Receives the events of the file system watcher from its thread.
+#[derive(Debug)]
+struct ShaderWatcher {
+    /// Stops watching when dropped.
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+}
+
+impl ShaderLibrary {
+    /// Compiles every shader in the given directory, or the built-in shaders without one.
+    /// Unlike later recompilations, a compile error fails here, as there is no previous
+    /// code to fall back to. Only a directory can be watched.
+    pub fn load(dir: Option<&Path>, watch: bool, non_uniform_indexing: bool) -> Result<Self> {
+        let mut library = Self {
+            dir: dir.map(Path::to_path_buf),
+            non_uniform_indexing,
+            ..Default::default()
+        };
+
+        for shader in Shader::ALL {
+            let compiled = library.compile(shader)?;
+            library.compiled.insert(shader, compiled);
+        }
+
+        let reflections = Shader::ALL.map(|s| &library.compiled[&s].reflection);
+        library.descriptor_sets = ShaderReflection::merge(&reflections)?;
+        for shader in Shader::ALL {
+            let reflection = &library.compiled[&shader].reflection;
+            let sets = reflection.set_count() as u32;
+            let layout = ShaderReflection {
+                bindings: (library.descriptor_sets.bindings.iter())
+                    .filter(|b| b.set < sets)
+                    .copied()
+                    .collect(),
+                push_constants: reflection.push_constants.clone(),
+                vertex_inputs: Vec::new(),
+            };
+            library.layouts.insert(shader, layout);
+        }
+
+        if let (Some(dir), true) = (dir, watch) {
+            let (sender, events) = channel();
+            let mut watcher = notify::recommended_watcher(sender)?;
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+            library.watcher = Some(ShaderWatcher {
+                _watcher: watcher,
+                events,
+            });
+            info!("Watching {} for shader changes.", dir.display());
+        }
+
+        Ok(library)
+    }
+
+    /// The file of a shader, or just its name for a built-in shader.
+    fn path(&self, shader: Shader) -> PathBuf {
+        match &self.dir {
+            Some(dir) => dir.join(shader.file_name()),
+            None => PathBuf::from(shader.file_name()),
+        }
+    }
+
+    fn compile(&self, shader: Shader) -> Result<CompiledShader> {
+        let path = self.path(shader);
+        let code = match &self.dir {
+            Some(_) => compile_shader(&path, self.non_uniform_indexing)?,
+            None => compile_wgsl(shader.builtin_source(), &path, self.non_uniform_indexing)?,
+        };
+        let reflection = ShaderReflection::reflect(&code)
+            .map_err(|e| anyhow!("Failed to reflect shader {}: {}", path.display(), e))?;
+
+        Ok(CompiledShader { code, reflection })
+    }
+
+    /// The SPIR-V code of a shader.
+    pub fn code(&self, shader: Shader) -> &[u32] {
+        &self.compiled[&shader].code
+    }
+
+    /// The interface of the current code of a shader.
+    pub fn reflection(&self, shader: Shader) -> &ShaderReflection {
+        &self.compiled[&shader].reflection
+    }
+
+    /// The interface the pipeline layout of a shader is built for.
+    pub fn layout(&self, shader: Shader) -> &ShaderReflection {
+        &self.layouts[&shader]
+    }
+
+    /// The bindings of a descriptor set layout, as used by all the shaders together.
+    pub fn set_layout_bindings(&self, set: u32) -> Vec<vk::DescriptorSetLayoutBinding> {
+        self.descriptor_sets.set_layout_bindings(set)
+    }
+
+    /// Recompiles the shaders whose files changed since the last call and returns the
+    /// ones that compiled and fit their pipeline layouts. Errors are logged and the shader
+    /// keeps its code.
+    pub fn poll(&mut self) -> Vec<Shader> {
+        self.previous.clear();
+
+        let Some(watcher) = &self.watcher else {
+            return Vec::new();
+        };
+
+        // Editors often write a file in several steps, each of which sends an event.
+        let mut changed = Vec::new();
+        for event in watcher.events.try_iter() {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    warn!("Failed to watch the shaders: {}", e);
+                    continue;
+                }
+            };
+
+            if !matches!(
+                event.kind,
+                EventKind::Create(_)
+                    | EventKind::Modify(
+                        ModifyKind::Data(_) | ModifyKind::Name(_) | ModifyKind::Any
+                    )
+            ) {
+                continue;
+            }
+
+            for shader in Shader::ALL {
+                let name = shader.file_name();
+                let touched = event
+                    .paths
+                    .iter()
+                    .any(|p| p.file_name().is_some_and(|n| n == name));
+                if touched && !changed.contains(&shader) {
+                    changed.push(shader);
+                }
+            }
+        }
+
+        changed.retain(|&shader| {
+            let path = self.path(shader);
+            let compiled = self.compile(shader).and_then(|c| {
+                c.reflection
+                    .check_compatible(&self.layouts[&shader])
+                    .map_err(|e| {
+                        anyhow!("{} doesn't fit its pipeline layout: {}", path.display(), e)
+                    })?;
+                Ok(c)
+            });
+
+            match compiled {
+                Ok(compiled) => {
+                    info!("Recompiled {}.", path.display());
+                    if let Some(previous) = self.compiled.insert(shader, compiled) {
+                        self.previous.insert(shader, previous);
+                    }
+                    true
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    false
+                }
+            }
+        });
+
+        changed
+    }
+
+    /// Restores the code a shader had before the last `poll`, e.g. when the pipeline
+    /// couldn't be created from the new code.
+    pub fn revert(&mut self, shader: Shader) {
+        if let Some(previous) = self.previous.remove(&shader) {
+            self.compiled.insert(shader, previous);
+        }
+    }
+}
+
+/// Compiles a WGSL shader file into SPIR-V.
+pub fn compile_shader(path: &Path, non_uniform_indexing: bool) -> Result<Vec<u32>, ShaderError> {
+    let source = fs::read_to_string(path).map_err(|e| ShaderError::Read(path.to_path_buf(), e))?;
+    compile_wgsl(&source, path, non_uniform_indexing)
+}
+
+/// Compiles WGSL source into SPIR-V. The path is only used in the error messages.
+///
+/// naga parses the WGSL into its own intermediate representation, validates it and
+/// writes it out as SPIR-V. The errors point at the offending lines of the source.
+///
+/// Without `non_uniform_indexing`, the entry points that index a binding array with a
+/// non-uniform index are left out, as the device can't run them. The pipelines use the
+/// other entry points instead (e.g. `fs_main_uniform`).
+pub fn compile_wgsl(
+    source: &str,
+    path: &Path,
+    non_uniform_indexing: bool,
+) -> Result<Vec<u32>, ShaderError> {
+    let file = path.display().to_string();
+    let compile_error = |message: String| ShaderError::Compile(path.to_path_buf(), message);
+
+    let mut module = wgsl::parse_str(source)
+        .map_err(|e| compile_error(e.emit_to_string_with_path(source, &file)))?;
+
+    // A combined image sampler is declared as a texture and a sampler with the same
+    // binding, which is valid in Vulkan but not in WebGPU, so naga mustn't reject it.
+    let flags = ValidationFlags::all() - ValidationFlags::BINDINGS;
+    let mut validator = Validator::new(flags, Capabilities::all());
+    let mut info = validator
+        .validate(&module)
+        .map_err(|e| compile_error(e.emit_to_string_with_path(source, &file)))?;
+
+    if !non_uniform_indexing {
+        let mut keep = (0..module.entry_points.len())
+            .map(|i| !indexes_non_uniformly(&module, &info, i))
+            .collect::<Vec<_>>()
+            .into_iter();
+        module.entry_points.retain(|_| keep.next().unwrap_or(true));
+        info = validator
+            .validate(&module)
+            .map_err(|e| compile_error(e.emit_to_string_with_path(source, &file)))?;
+    }
+
+    // The shaders are written for Vulkan's clip space, so naga mustn't flip the y axis
+    // like it does for WGSL meant for WebGPU. SPIR-V 1.0 runs on every Vulkan version,
+    // non-uniform indexing is added as an extension that Vulkan 1.2 includes.
+    let options = spv::Options {
+        lang_version: (1, 0),
+        flags: spv::WriterFlags::empty(),
+        ..Default::default()
+    };
+    spv::write_vec(&module, &info, &options, None).map_err(|e| compile_error(e.to_string()))
+}
+
+/// Whether an entry point indexes a binding array with an index that naga found to be
+/// non-uniform. Only the code of the entry point itself is looked at, not the functions
+/// it calls.
+fn indexes_non_uniformly(module: &Module, info: &ModuleInfo, entry_point: usize) -> bool {
+    let function = &module.entry_points[entry_point].function;
+    let function_info = info.get_entry_point(entry_point);
+
+    function.expressions.iter().any(|(_, expression)| {
+        let Expression::Access { base, index } = *expression else {
+            return false;
+        };
+        let Expression::GlobalVariable(global) = function.expressions[base] else {
+            return false;
+        };
+
+        let ty = module.global_variables[global].ty;
+        matches!(module.types[ty].inner, TypeInner::BindingArray { .. })
+            && function_info[index].uniformity.non_uniform_result.is_some()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_shaders_compile_and_fit_their_layouts() {
+        for non_uniform_indexing in [true, false] {
+            let library = ShaderLibrary::load(None, false, non_uniform_indexing).unwrap();
+            for shader in Shader::ALL {
+                assert!(!library.code(shader).is_empty());
+                library
+                    .reflection(shader)
+                    .check_compatible(library.layout(shader))
+                    .unwrap();
+            }
+        }
+    }
+
+    /// Whether the code declares the ShaderNonUniform capability (5301), which an
+    /// `OpCapability` (opcode 17, 2 words) does.
+    fn uses_non_uniform(code: &[u32]) -> bool {
+        code.windows(2).any(|w| w == [2 << 16 | 17, 5301])
+    }
+
+    /// Whether the code has an entry point with the name, as a nul-terminated string.
+    fn has_entry_point(code: &[u32], name: &str) -> bool {
+        let bytes = code
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect::<Vec<_>>();
+        let name = format!("{}\0", name);
+        bytes.windows(name.len()).any(|w| w == name.as_bytes())
+    }
+
+    #[test]
+    fn non_uniform_entry_points_are_left_out_without_non_uniform_indexing() {
+        let path = Path::new(Shader::Main.file_name());
+        let source = Shader::Main.builtin_source();
+
+        let code = compile_wgsl(source, path, true).unwrap();
+        assert!(uses_non_uniform(&code));
+        assert!(has_entry_point(&code, "fs_main"));
+        assert!(has_entry_point(&code, "fs_main_uniform"));
+
+        let code = compile_wgsl(source, path, false).unwrap();
+        assert!(!uses_non_uniform(&code));
+        assert!(!has_entry_point(&code, "fs_main"));
+        assert!(has_entry_point(&code, "fs_main_uniform"));
+        assert!(has_entry_point(&code, "vs_main"));
+    }
+}
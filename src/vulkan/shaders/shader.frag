@@ -1,12 +0,0 @@
-#version 450
-
-layout (location = 0) in vec3 color;
-layout (location = 1) in vec2 texCoord;
-
-layout (location = 0) out vec4 outColor;
-
-layout(set = 0, binding = 1) uniform sampler2D texSampler;
-
-void main() {
-    outColor = texture(texSampler, texCoord);
-}
\ No newline at end of file
@@ -1,21 +0,0 @@
-#version 450
-
-layout (set = 0, binding = 0) uniform UniformBufferObject {
-    mat4 model;
-    mat4 view;
-    mat4 proj;
-} ubo;
-
-layout (location = 0) in vec3 inPosition;
-layout (location = 1) in vec3 inColor;
-layout (location = 2) in vec2 inTexCoord;
-
-layout (location = 0) out vec3 outColor;
-layout (location = 1) out vec2 outTexCoord;
-
-void main() {
-    int m = 5 / 0;
-    gl_Position = ubo.proj * ubo.view * ubo.model * vec4(inPosition, 1.0);
-    outColor = inColor;
-    outTexCoord = inTexCoord;
-}
\ No newline at end of file
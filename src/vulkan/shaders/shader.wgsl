@@ -0,0 +1,284 @@
+// The shaders of the main pipeline, which draws the objects of the scene with physically
+// based shading, the directional light's shadow and the point lights.
+//
+// Vulkan's combined image samplers are declared as a texture and a sampler with the same
+// binding, which share the descriptor.
+
+const MAX_POINT_LIGHTS: u32 = 4u;
+const MAX_INSTANCE_TEXTURES: u32 = 8u;
+const PI: f32 = 3.14159265359;
+
+struct UniformBufferObject {
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    light_space: mat4x4<f32>,
+}
+
+struct DirectionalLight {
+    direction: vec4<f32>,
+    color: vec4<f32>,     // rgb = color, a = intensity
+}
+
+struct PointLight {
+    position: vec4<f32>,  // xyz = position, w = range
+    color: vec4<f32>,     // rgb = color, a = intensity
+}
+
+struct Lights {
+    camera_position: vec4<f32>,
+    ambient: vec4<f32>,   // rgb = color, a = intensity
+    directional: DirectionalLight,
+    shadow: vec4<f32>,    // x = PCF radius, y = size of a shadow map texel
+    point_lights: array<PointLight, MAX_POINT_LIGHTS>,
+    point_light_count: u32,
+}
+
+struct Material {
+    base_color_factor: vec4<f32>,
+    emissive_factor: vec4<f32>,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+}
+
+@group(0) @binding(0) var<uniform> ubo: UniformBufferObject;
+@group(0) @binding(1) var<uniform> lights: Lights;
+@group(0) @binding(2) var shadow_map: texture_depth_2d;
+@group(0) @binding(2) var shadow_sampler: sampler_comparison;
+// The textures an instance can draw with instead of the base color textures.
+@group(0) @binding(3) var instance_textures: binding_array<texture_2d<f32>, MAX_INSTANCE_TEXTURES>;
+@group(0) @binding(3) var instance_samplers: binding_array<sampler, MAX_INSTANCE_TEXTURES>;
+
+@group(1) @binding(0) var<uniform> material: Material;
+@group(1) @binding(1) var base_color_texture: texture_2d<f32>;
+@group(1) @binding(1) var base_color_sampler: sampler;
+@group(1) @binding(2) var metallic_roughness_texture: texture_2d<f32>;
+@group(1) @binding(2) var metallic_roughness_sampler: sampler;
+@group(1) @binding(3) var normal_texture: texture_2d<f32>;
+@group(1) @binding(3) var normal_sampler: sampler;
+@group(1) @binding(4) var occlusion_texture: texture_2d<f32>;
+@group(1) @binding(4) var occlusion_sampler: sampler;
+@group(1) @binding(5) var emissive_texture: texture_2d<f32>;
+@group(1) @binding(5) var emissive_sampler: sampler;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec3<f32>,
+    @location(2) tex_coord: vec2<f32>,
+    @location(3) normal: vec3<f32>,
+}
+
+// Per instance, from the instance buffer. The model matrix takes up locations 4 to 7,
+// a column each.
+struct InstanceInput {
+    @location(4) model_0: vec4<f32>,
+    @location(5) model_1: vec4<f32>,
+    @location(6) model_2: vec4<f32>,
+    @location(7) model_3: vec4<f32>,
+    @location(8) tint: vec4<f32>,
+    @location(9) texture_index: i32,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+    @location(1) tex_coord: vec2<f32>,
+    @location(2) world_position: vec3<f32>,
+    @location(3) normal: vec3<f32>,
+    @location(4) light_space_position: vec4<f32>,
+    @location(5) tint: vec4<f32>,
+    @location(6) @interpolate(flat) texture_index: i32,
+}
+
+// The inverse transpose of a 3x3 matrix, which keeps normals perpendicular to the surface
+// under non-uniform scaling. Its columns are the cross products of the other two columns.
+fn inverse_transpose(m: mat3x3<f32>) -> mat3x3<f32> {
+    let c0 = cross(m[1], m[2]);
+    let c1 = cross(m[2], m[0]);
+    let c2 = cross(m[0], m[1]);
+    return mat3x3<f32>(c0, c1, c2) * (1.0 / dot(m[0], c0));
+}
+
+@vertex
+fn vs_main(vertex: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    let world_position = model * vec4<f32>(vertex.position, 1.0);
+
+    var out: VertexOutput;
+    out.position = ubo.proj * ubo.view * world_position;
+    out.color = vertex.color;
+    out.tex_coord = vertex.tex_coord;
+    out.world_position = world_position.xyz;
+    out.normal = inverse_transpose(mat3x3<f32>(model[0].xyz, model[1].xyz, model[2].xyz)) * vertex.normal;
+    out.light_space_position = ubo.light_space * world_position;
+    out.tint = instance.tint;
+    out.texture_index = instance.texture_index;
+    return out;
+}
+
+// Perturbs the surface normal with the normal map. The vertices don't have tangents,
+// so the tangent frame is reconstructed from the screen space derivatives of the
+// position and texture coordinates.
+fn get_normal(in: VertexOutput, normal_sample: vec3<f32>) -> vec3<f32> {
+    let N = normalize(in.normal);
+
+    let dp1 = dpdx(in.world_position);
+    let dp2 = dpdy(in.world_position);
+    let duv1 = dpdx(in.tex_coord);
+    let duv2 = dpdy(in.tex_coord);
+
+    let dp2perp = cross(dp2, N);
+    let dp1perp = cross(N, dp1);
+    let T = dp2perp * duv1.x + dp1perp * duv2.x;
+    let B = dp2perp * duv1.y + dp1perp * duv2.y;
+
+    // Without usable texture coordinates there is no tangent frame to map the normal into.
+    let scale = max(dot(T, T), dot(B, B));
+    if (scale <= 0.0) {
+        return N;
+    }
+
+    let invmax = inverseSqrt(scale);
+    let TBN = mat3x3<f32>(T * invmax, B * invmax, N);
+
+    let mapped = normal_sample * 2.0 - 1.0;
+    return normalize(TBN * vec3<f32>(mapped.xy * material.normal_scale, mapped.z));
+}
+
+// How much of the directional light reaches the fragment, from 0 (in shadow) to 1 (lit).
+//
+// Percentage-closer filtering (PCF): the depth of the fragment is compared with the
+// shadow map texels around it and the results are averaged, which softens the edges
+// of the shadows.
+fn get_shadow(light_space_position: vec4<f32>) -> f32 {
+    // Orthographic projection, so w is 1 and the position is already in NDC.
+    let ndc = light_space_position.xyz / light_space_position.w;
+    let uv = ndc.xy * 0.5 + 0.5;
+
+    // Beyond the far plane of the light, nothing can cast a shadow.
+    if (ndc.z > 1.0) {
+        return 1.0;
+    }
+
+    let radius = i32(lights.shadow.x);
+    let texel = lights.shadow.y;
+
+    // The shadow map has a single mip level, so it is sampled without derivatives, which
+    // are undefined after the early return above.
+    var lit = 0.0;
+    for (var x = -radius; x <= radius; x++) {
+        for (var y = -radius; y <= radius; y++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            lit += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, ndc.z);
+        }
+    }
+
+    let samples = f32((2 * radius + 1) * (2 * radius + 1));
+    return lit / samples;
+}
+
+// Trowbridge-Reitz GGX normal distribution: how many microfacets face the halfway vector.
+fn distribution_ggx(NdotH: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = NdotH * NdotH * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Smith's method with Schlick-GGX: how much of the microfacets is shadowed or masked.
+fn geometry_smith(NdotV: f32, NdotL: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    let ggxV = NdotV / (NdotV * (1.0 - k) + k);
+    let ggxL = NdotL / (NdotL * (1.0 - k) + k);
+    return ggxV * ggxL;
+}
+
+// Schlick's approximation of the Fresnel equations: how much light is reflected
+// rather than refracted, which increases at grazing angles.
+fn fresnel_schlick(cos_theta: f32, F0: vec3<f32>) -> vec3<f32> {
+    return F0 + (1.0 - F0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// The Cook-Torrance BRDF for light arriving from L with the given radiance.
+fn cook_torrance(N: vec3<f32>, V: vec3<f32>, L: vec3<f32>, radiance: vec3<f32>, albedo: vec3<f32>, metallic: f32, roughness: f32) -> vec3<f32> {
+    let H = normalize(V + L);
+    let NdotL = max(dot(N, L), 0.0);
+    let NdotV = max(dot(N, V), 1e-4);
+    let NdotH = max(dot(N, H), 0.0);
+
+    // Dielectrics reflect about 4% of the light head on, metals reflect their color.
+    let F0 = mix(vec3<f32>(0.04), albedo, metallic);
+    let F = fresnel_schlick(max(dot(H, V), 0.0), F0);
+    let D = distribution_ggx(NdotH, roughness);
+    let G = geometry_smith(NdotV, NdotL, roughness);
+
+    let specular = D * G * F / (4.0 * NdotV * max(NdotL, 1e-4));
+
+    // The light that isn't reflected is refracted and scattered diffusely, except by metals,
+    // which absorb it.
+    let kD = (1.0 - F) * (1.0 - metallic);
+
+    return (kD * albedo / PI + specular) * radiance * NdotL;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // The instance texture index differs between the instances of a draw, which naga
+    // marks as non-uniform.
+    let index = u32(max(in.texture_index, 0));
+    let instance_color = textureSample(instance_textures[index], instance_samplers[index], in.tex_coord);
+    return shade(in, instance_color);
+}
+
+// `fs_main` for devices that can't index the instance textures with a non-uniform index.
+// Instances can't pick a texture there, so the instance color is never used.
+@fragment
+fn fs_main_uniform(in: VertexOutput) -> @location(0) vec4<f32> {
+    let instance_color = textureSample(instance_textures[0], instance_samplers[0], in.tex_coord);
+    return shade(in, instance_color);
+}
+
+// Shades a fragment with the material, or with the instance's texture instead of the base
+// color texture.
+fn shade(in: VertexOutput, instance_color: vec4<f32>) -> vec4<f32> {
+    // Every texture is sampled outside of a branch, as sampling in non-uniform control
+    // flow has no derivatives.
+    let material_color = textureSample(base_color_texture, base_color_sampler, in.tex_coord);
+    let metallic_roughness = textureSample(metallic_roughness_texture, metallic_roughness_sampler, in.tex_coord);
+    let normal_sample = textureSample(normal_texture, normal_sampler, in.tex_coord).xyz;
+    let occlusion_sample = textureSample(occlusion_texture, occlusion_sampler, in.tex_coord).r;
+    let emissive_sample = textureSample(emissive_texture, emissive_sampler, in.tex_coord).rgb;
+
+    // The vertex color (glTF's COLOR_0) is white when the model has none.
+    let base_color = material.base_color_factor * vec4<f32>(in.color, 1.0) * in.tint
+        * select(material_color, instance_color, in.texture_index >= 0);
+    let metallic = clamp(material.metallic_factor * metallic_roughness.b, 0.0, 1.0);
+    // Very low roughness makes the highlights of point and directional lights vanish.
+    let roughness = clamp(material.roughness_factor * metallic_roughness.g, 0.04, 1.0);
+    let occlusion = mix(1.0, occlusion_sample, material.occlusion_strength);
+    let emissive = material.emissive_factor.rgb * emissive_sample;
+
+    let N = get_normal(in, normal_sample);
+    let V = normalize(lights.camera_position.xyz - in.world_position);
+
+    var color = lights.ambient.rgb * lights.ambient.a * base_color.rgb * occlusion;
+
+    let L = normalize(-lights.directional.direction.xyz);
+    var radiance = lights.directional.color.rgb * lights.directional.color.a * get_shadow(in.light_space_position);
+    color += cook_torrance(N, V, L, radiance, base_color.rgb, metallic, roughness);
+
+    for (var i = 0u; i < min(lights.point_light_count, MAX_POINT_LIGHTS); i++) {
+        let light = lights.point_lights[i];
+        let to_light = light.position.xyz - in.world_position;
+        let distance = length(to_light);
+        // Fades smoothly to zero at the range of the light.
+        let falloff = clamp(1.0 - pow(distance / light.position.w, 4.0), 0.0, 1.0);
+        let attenuation = falloff * falloff / (distance * distance + 1.0);
+        radiance = light.color.rgb * light.color.a * attenuation;
+        color += cook_torrance(N, V, to_light / distance, radiance, base_color.rgb, metallic, roughness);
+    }
+
+    return vec4<f32>(color + emissive, base_color.a);
+}
@@ -0,0 +1,24 @@
+// The shader of the shadow pipeline, which only transforms the vertices into the light's
+// clip space. There is no fragment shader, the rasterizer still writes the depth.
+
+struct UniformBufferObject {
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    light_space: mat4x4<f32>,
+}
+
+@group(0) @binding(0) var<uniform> ubo: UniformBufferObject;
+
+// The model matrix of the instance, a column per location.
+struct InstanceInput {
+    @location(4) model_0: vec4<f32>,
+    @location(5) model_1: vec4<f32>,
+    @location(6) model_2: vec4<f32>,
+    @location(7) model_3: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    let model = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    return ubo.light_space * model * vec4<f32>(position, 1.0);
+}
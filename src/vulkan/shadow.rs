@@ -0,0 +1,138 @@
+use anyhow::{anyhow, Result};
+use cgmath::{vec3, EuclideanSpace, InnerSpace, Point3};
+use vulkanalia::prelude::v1_3::*;
+
+use crate::app::AppData;
+
+use super::buffers::depth_buffer::get_supported_format;
+use super::buffers::uniform_buffer::{Mat4, CORRECTION};
+use super::image::{create_image, create_image_view};
+use super::model::Bounds;
+use super::resources::Sampler;
+use super::vertex::Vec3;
+
+/// Picks the format of the shadow map. Besides being a depth attachment, the shadow map
+/// is sampled with linear filtering in the main pass, which not every depth format supports.
+/// D32_SFLOAT is preferred: the light's projection encloses the whole scene, which can
+/// hold several models, and the extra precision over D16_UNORM keeps shadow acne down
+/// without a larger depth bias. D16_UNORM is the fallback, as it is supported almost
+/// everywhere and enough for the depth range of a single model.
+pub unsafe fn get_shadow_map_format(instance: &Instance, data: &AppData) -> Result<vk::Format> {
+    let candidates = &[vk::Format::D32_SFLOAT, vk::Format::D16_UNORM];
+
+    get_supported_format(
+        instance,
+        data,
+        candidates,
+        vk::ImageTiling::OPTIMAL,
+        vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+    )
+    .map_err(|_| anyhow!("Failed to find a supported shadow map format!"))
+}
+
+/// Creates the shadow map of the directional light: a square depth image that the shadow
+/// pass renders the scene into, as seen from the light, along with its view.
+///
+/// The main pass then samples it to find out whether a fragment is hidden from the light
+/// by something closer to it. The shadow map doesn't depend on the swapchain, but it is
+/// rebuilt when its size changes, and the render graph imports it (see passes.rs).
+pub unsafe fn create_shadow_map(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    size: u32,
+) -> Result<()> {
+    let format = get_shadow_map_format(instance, data)?;
+
+    let shadow_map = create_image(
+        instance,
+        device,
+        data,
+        "shadow map",
+        size,
+        size,
+        1,
+        vk::SampleCountFlags::_1,
+        format,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.shadow_map_view = create_image_view(
+        device,
+        data,
+        shadow_map.handle(),
+        format,
+        vk::ImageAspectFlags::DEPTH,
+        1,
+    )?;
+    data.shadow_map = shadow_map;
+    data.shadow_map_size = size;
+
+    Ok(())
+}
+
+/// The shadow map is sampled with a comparison sampler. Instead of returning the depth
+/// stored in the shadow map, sampling compares it with a reference depth (the depth of
+/// the fragment as seen from the light) and returns 1 when the fragment is lit and 0
+/// when it's in shadow.
+///
+/// With linear filtering the results of the comparisons with the 4 nearest texels are
+/// blended, which the hardware does for free and smooths the PCF filter in the shader.
+pub unsafe fn create_shadow_sampler(device: &Device, data: &mut AppData) -> Result<()> {
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+        // Everything outside of the shadow map is outside of the light's view, so it
+        // is treated as lit by the white border (the maximum depth).
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+        .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
+        .anisotropy_enable(false)
+        .max_anisotropy(1.0)
+        .unnormalized_coordinates(false)
+        // A fragment is lit when its depth is less than or equal to the stored one.
+        .compare_enable(true)
+        .compare_op(vk::CompareOp::LESS_OR_EQUAL)
+        .min_lod(0.0)
+        .max_lod(0.0)
+        .mip_lod_bias(0.0);
+
+    let sampler = device.create_sampler(&info, None)?;
+    data.shadow_sampler = Sampler::new(sampler, &data.deletion_queue);
+
+    Ok(())
+}
+
+/// The view and projection of the directional light.
+///
+/// A directional light has no position, its rays are parallel. So the shadow map is
+/// rendered with an orthographic projection, looking along the light direction at the
+/// given bounds. The projection box is fitted tightly around the bounds to make the
+/// best use of the shadow map's resolution.
+pub fn light_space_matrix(direction: Vec3, bounds: Bounds) -> Mat4 {
+    let direction = direction.normalize();
+    // Keeps the projection valid for empty or single point models.
+    let radius = bounds.radius.max(0.01);
+
+    let center = Point3::from_vec(bounds.center);
+    let eye = center - direction * 2.0 * radius;
+
+    // The up vector can be anything that isn't parallel to the light direction.
+    let up = if direction.z.abs() > 0.99 {
+        vec3(0.0, 1.0, 0.0)
+    } else {
+        vec3(0.0, 0.0, 1.0)
+    };
+
+    let view = Mat4::look_at_rh(eye, center, up);
+    // The bounds are between radius and 3 * radius in front of the eye.
+    let proj = cgmath::ortho(-radius, radius, -radius, radius, radius, 3.0 * radius);
+
+    CORRECTION * proj * view
+}
@@ -1,5 +1,6 @@
 use crate::app::AppData;
 use anyhow::Result;
+use log::*;
 use vulkanalia::prelude::v1_3::*;
 use vulkanalia::vk::KhrSwapchainExtension;
 use vulkanalia::{vk, vk::KhrSurfaceExtension};
@@ -59,12 +60,13 @@ pub unsafe fn create_swapchain(
     instance: &Instance,
     device: &Device,
     data: &mut AppData,
+    preferred_present_mode: vk::PresentModeKHR,
 ) -> Result<()> {
     let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
     let support = SwapchainSupport::get(instance, data, data.physical_device)?;
 
     let surface_format = get_swapchain_surface_format(&support.formats);
-    let present_mode = get_swapchain_present_mode(&support.present_modes);
+    let present_mode = get_swapchain_present_mode(&support.present_modes, preferred_present_mode);
     let extent = get_swapchain_extent(window, support.capabilities);
 
     let mut image_count = support.capabilities.min_image_count + 1;
@@ -74,6 +76,19 @@ pub unsafe fn create_swapchain(
         image_count = support.capabilities.max_image_count;
     }
 
+    // Copying from the swapchain images takes screenshots (see screenshot.rs). Nearly
+    // every surface supports it, but it isn't guaranteed.
+    let mut image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+    data.capture_supported = support
+        .capabilities
+        .supported_usage_flags
+        .contains(vk::ImageUsageFlags::TRANSFER_SRC);
+    if data.capture_supported {
+        image_usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+    } else {
+        warn!("The swapchain images can't be copied from, screenshots are disabled.");
+    }
+
     let mut queue_family_indices = vec![];
     let image_sharing_mode = if indices.graphics != indices.present {
         queue_family_indices.push(indices.graphics);
@@ -90,7 +105,7 @@ pub unsafe fn create_swapchain(
         .image_color_space(surface_format.color_space)
         .image_extent(extent)
         .image_array_layers(1)
-        .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+        .image_usage(image_usage)
         .image_sharing_mode(image_sharing_mode)
         .queue_family_indices(&queue_family_indices)
         .pre_transform(support.capabilities.current_transform)
@@ -101,6 +116,9 @@ pub unsafe fn create_swapchain(
 
     data.swapchain = device.create_swapchain_khr(&info, None)?;
     data.swapchain_images = device.get_swapchain_images_khr(data.swapchain)?;
+    for (i, &image) in data.swapchain_images.iter().enumerate() {
+        data.debug_utils.set_name(device, image, &format!("swapchain image {}", i));
+    }
     data.swapchain_format = surface_format.format;
     data.swapchain_extent = extent;
 
@@ -118,11 +136,16 @@ pub fn get_swapchain_surface_format(formats: &[vk::SurfaceFormatKHR]) -> vk::Sur
         .unwrap_or_else(|| formats[0])
 }
 
-pub fn get_swapchain_present_mode(present_modes: &[vk::PresentModeKHR]) -> vk::PresentModeKHR {
+/// Returns the preferred present mode if it's supported. Otherwise falls back to FIFO,
+/// which is the only mode guaranteed to be available.
+pub fn get_swapchain_present_mode(
+    present_modes: &[vk::PresentModeKHR],
+    preferred: vk::PresentModeKHR,
+) -> vk::PresentModeKHR {
     present_modes
         .iter()
         .cloned()
-        .find(|m| *m == vk::PresentModeKHR::MAILBOX)
+        .find(|m| *m == preferred)
         .unwrap_or(vk::PresentModeKHR::FIFO)
 }
 
@@ -168,6 +191,7 @@ pub unsafe fn create_swapchain_image_views(device: &Device, data: &mut AppData)
         .map(|i| {
             create_image_view(
                 device,
+                data,
                 *i,
                 data.swapchain_format,
                 vk::ImageAspectFlags::COLOR,
@@ -1,7 +1,7 @@
 use anyhow::Result;
 use vulkanalia::prelude::v1_3::*;
 
-use crate::app::{AppData, MAX_FRAMES_IN_FLIGHT};
+use crate::app::AppData;
 
 /// Semaphores
 ///
@@ -64,16 +64,12 @@ pub unsafe fn create_sync_objects(device: &Device, data: &mut AppData) -> Result
     let semaphore_info = vk::SemaphoreCreateInfo::builder();
     let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
 
-    for _ in 0..MAX_FRAMES_IN_FLIGHT {
-        data.image_available_semaphores
-            .push(device.create_semaphore(&semaphore_info, None)?);
-        data.render_finished_semaphores
-            .push(device.create_semaphore(&semaphore_info, None)?);
-        data.command_completion_fences
-            .push(device.create_fence(&fence_info, None)?);
+    // The fences start out signaled, as the frames have nothing to wait for the first time.
+    for frame in &mut data.frames {
+        frame.image_available_semaphore = device.create_semaphore(&semaphore_info, None)?;
+        frame.render_finished_semaphore = device.create_semaphore(&semaphore_info, None)?;
+        frame.command_completion_fence = device.create_fence(&fence_info, None)?;
     }
 
-    data.image_usage_fences = vec![vk::Fence::null(); data.swapchain_images.len()];
-
     Ok(())
 }
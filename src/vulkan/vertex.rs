@@ -3,6 +3,7 @@ use vulkanalia::prelude::v1_3::*;
 
 pub type Vec2 = cgmath::Vector2<f32>;
 pub type Vec3 = cgmath::Vector3<f32>;
+pub type Vec4 = cgmath::Vector4<f32>;
 
 /// "Representation C" indicates that the struct should use the C ABI (Application Binary Interface)
 /// for its memory layout. Ensures compatibility with C or other languages that follow the
@@ -13,11 +14,13 @@ pub struct Vertex {
     pub pos: Vec3,
     pub color: Vec3,
     pub tex_coord: Vec2,
+    /// A zero normal means the model didn't provide one (see `generate_missing_normals`).
+    pub normal: Vec3,
 }
 
 impl Vertex {
-    const fn new(pos: Vec3, color: Vec3, tex_coord: Vec2) -> Self {
-        Self {pos, color, tex_coord}
+    const fn new(pos: Vec3, color: Vec3, tex_coord: Vec2, normal: Vec3) -> Self {
+        Self {pos, color, tex_coord, normal}
     }
 
     pub fn binding_description() -> vk::VertexInputBindingDescription {
@@ -28,7 +31,7 @@ impl Vertex {
             .build()
     }
 
-    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3] {
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 4] {
         let pos = vk::VertexInputAttributeDescription::builder()
             .binding(0)
             .location(0)
@@ -50,7 +53,14 @@ impl Vertex {
             .offset((size_of::<Vec3>() + size_of::<Vec3>()) as u32)
             .build();
 
-        [pos, color, tex_coord]
+        let normal = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(3)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset((size_of::<Vec3>() + size_of::<Vec3>() + size_of::<Vec2>()) as u32)
+            .build();
+
+        [pos, color, tex_coord, normal]
     }
 }
 
@@ -58,7 +68,8 @@ impl PartialEq for Vertex {
     fn eq(&self, other: &Self) -> bool {
         self.pos == other.pos &&
             self.color == other.color &&
-            self.tex_coord == other.tex_coord
+            self.tex_coord == other.tex_coord &&
+            self.normal == other.normal
     }
 }
 
@@ -74,5 +85,8 @@ impl Hash for Vertex {
         self.color[2].to_bits().hash(state);
         self.tex_coord[0].to_bits().hash(state);
         self.tex_coord[1].to_bits().hash(state);
+        self.normal[0].to_bits().hash(state);
+        self.normal[1].to_bits().hash(state);
+        self.normal[2].to_bits().hash(state);
     }
 }
\ No newline at end of file